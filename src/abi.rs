@@ -0,0 +1,256 @@
+use crate::helpers::{bv_to_bytes, to_bv};
+use ethabi::{Function, ParamType, Token};
+use z3::{
+    ast::{Ast, Bool, BV},
+    Context, Model,
+};
+
+/// Most bytes of a symbolic `bytes` or `string` argument
+pub const MAX_DYNAMIC_BYTES: u64 = 64;
+/// Most items of a symbolic dynamic array argument
+pub const MAX_DYNAMIC_ITEMS: u64 = 2;
+
+/// Well-formed symbolic calldata for a call to an abi function.
+/// Offsets follow the standard encoding, and dynamic values have a symbolic length
+/// bounded by `MAX_DYNAMIC_BYTES` or `MAX_DYNAMIC_ITEMS`.
+#[derive(Debug)]
+pub struct SymbolicCall<'ctx> {
+    pub function: Function,
+    /// the whole calldata, starting with the selector
+    pub calldata: BV<'ctx>,
+    /// what makes the arguments valid for their types
+    pub constraints: Vec<Bool<'ctx>>,
+}
+
+impl<'ctx> SymbolicCall<'ctx> {
+    pub fn new(ctx: &'ctx Context, function: &Function) -> Self {
+        let mut encoder = Encoder {
+            ctx,
+            constraints: Vec::new(),
+        };
+
+        let kinds: Vec<_> = function.inputs.iter().map(|p| p.kind.clone()).collect();
+        let names: Vec<_> = function
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, p)| match p.name.is_empty() {
+                true => format!("{}_{i}", function.name),
+                false => format!("{}_{}", function.name, p.name),
+            })
+            .collect();
+        let words = encoder.tuple(&names, &kinds);
+
+        let selector = u32::from_be_bytes(function.short_signature());
+        let calldata = words
+            .iter()
+            .fold(BV::from_u64(ctx, selector.into(), 32), |calldata, word| {
+                calldata.concat(word)
+            });
+
+        Self {
+            function: function.clone(),
+            calldata,
+            constraints: encoder.constraints,
+        }
+    }
+
+    /// concrete calldata of the call in a model
+    pub fn bytes(&self, model: &Model<'ctx>) -> Option<Vec<u8>> {
+        bv_to_bytes(&model.eval(&self.calldata, true)?)
+    }
+
+    /// arguments of the call in a model
+    pub fn decode(&self, model: &Model<'ctx>) -> Option<Vec<Token>> {
+        let bytes = self.bytes(model)?;
+        self.function.decode_input(&bytes[4..]).ok()
+    }
+}
+
+struct Encoder<'ctx> {
+    ctx: &'ctx Context,
+    constraints: Vec<Bool<'ctx>>,
+}
+
+impl<'ctx> Encoder<'ctx> {
+    /// words of a value of type `kind`
+    fn encode(&mut self, name: &str, kind: &ParamType) -> Vec<BV<'ctx>> {
+        let ctx = self.ctx;
+
+        match kind {
+            ParamType::Address => vec![self.word(name, 160)],
+            ParamType::Uint(bits) => vec![self.word(name, *bits as u32)],
+            ParamType::Int(bits) => {
+                let bits = *bits as u32;
+                let word = BV::new_const(ctx, name, 256);
+                if bits < 256 {
+                    let low = word.extract(bits - 1, 0).sign_ext(256 - bits);
+                    self.constraints.push(word._eq(&low));
+                }
+                vec![word]
+            }
+            ParamType::Bool => {
+                let word = BV::new_const(ctx, name, 256);
+                self.constraints
+                    .push(word.bvule(&BV::from_u64(ctx, 1, 256)));
+                vec![word]
+            }
+            ParamType::FixedBytes(size) => {
+                let word = BV::new_const(ctx, name, 256);
+                // right padded with zeros
+                if *size < 32 {
+                    let padding = (32 - *size as u32) * 8;
+                    self.constraints.push(
+                        word.extract(padding - 1, 0)
+                            ._eq(&BV::from_u64(ctx, 0, padding)),
+                    );
+                }
+                vec![word]
+            }
+            ParamType::Bytes | ParamType::String => {
+                let len = self.len(name, MAX_DYNAMIC_BYTES);
+                let data = (0..(MAX_DYNAMIC_BYTES + 31) / 32)
+                    .map(|i| BV::new_const(ctx, format!("{name}_data{i}"), 256));
+                [len].into_iter().chain(data).collect()
+            }
+            ParamType::Array(inner) => {
+                let len = self.len(name, MAX_DYNAMIC_ITEMS);
+                let (names, kinds) = Self::items(name, inner, MAX_DYNAMIC_ITEMS as usize);
+                [len]
+                    .into_iter()
+                    .chain(self.tuple(&names, &kinds))
+                    .collect()
+            }
+            ParamType::FixedArray(inner, size) => {
+                let (names, kinds) = Self::items(name, inner, *size);
+                self.tuple(&names, &kinds)
+            }
+            ParamType::Tuple(kinds) => {
+                let names: Vec<_> = (0..kinds.len()).map(|i| format!("{name}_{i}")).collect();
+                self.tuple(&names, kinds)
+            }
+        }
+    }
+
+    /// heads of the values followed by the tails of the dynamic ones
+    fn tuple(&mut self, names: &[String], kinds: &[ParamType]) -> Vec<BV<'ctx>> {
+        let encoded: Vec<_> = names
+            .iter()
+            .zip(kinds)
+            .map(|(name, kind)| (kind.is_dynamic(), self.encode(name, kind)))
+            .collect();
+
+        let heads_len = encoded
+            .iter()
+            .map(|(dynamic, words)| if *dynamic { 1 } else { words.len() })
+            .sum::<usize>();
+
+        let mut heads = Vec::new();
+        let mut tails = Vec::new();
+        for (dynamic, words) in encoded {
+            if dynamic {
+                let offset = ((heads_len + tails.len()) * 32) as u64;
+                heads.push(to_bv(self.ctx, &offset.to_be_bytes()));
+                tails.extend(words);
+            } else {
+                heads.extend(words);
+            }
+        }
+
+        heads.extend(tails);
+        heads
+    }
+
+    /// a symbolic word holding an unsigned value of `bits` bits
+    fn word(&mut self, name: &str, bits: u32) -> BV<'ctx> {
+        let word = BV::new_const(self.ctx, name, 256);
+        if bits < 256 {
+            let high = word.extract(255, bits);
+            self.constraints
+                .push(high._eq(&BV::from_u64(self.ctx, 0, 256 - bits)));
+        }
+        word
+    }
+
+    /// a symbolic length of at most `max`
+    fn len(&mut self, name: &str, max: u64) -> BV<'ctx> {
+        let len = BV::new_const(self.ctx, format!("{name}_len"), 256);
+        self.constraints
+            .push(len.bvule(&BV::from_u64(self.ctx, max, 256)));
+        len
+    }
+
+    fn items(name: &str, inner: &ParamType, size: usize) -> (Vec<String>, Vec<ParamType>) {
+        let names = (0..size).map(|i| format!("{name}_{i}")).collect();
+        (names, vec![inner.clone(); size])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use z3::{Config, SatResult, Solver};
+
+    fn function(abi: &str) -> Function {
+        let abi = ethabi::Contract::load(abi.as_bytes()).unwrap();
+        abi.functions().next().unwrap().clone()
+    }
+
+    #[test]
+    fn static_args() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let function = function(
+            r#"[{"type":"function","name":"transfer","stateMutability":"nonpayable","outputs":[],
+            "inputs":[{"name":"to","type":"address"},{"name":"amount","type":"uint8"},{"name":"flag","type":"bool"}]}]"#,
+        );
+        let call = SymbolicCall::new(&ctx, &function);
+        assert_eq!(call.calldata.get_size(), (4 + 3 * 32) * 8);
+
+        let sol = Solver::new(&ctx);
+        for constraint in &call.constraints {
+            sol.assert(constraint);
+        }
+        assert_eq!(sol.check(), SatResult::Sat);
+
+        let bytes = call.bytes(&sol.get_model().unwrap()).unwrap();
+        assert_eq!(bytes[..4], function.short_signature());
+        let tokens = call.decode(&sol.get_model().unwrap()).unwrap();
+        assert!(matches!(tokens[0], Token::Address(_)));
+        assert!(matches!(tokens[2], Token::Bool(_)));
+
+        // an amount that doesn't fit in a uint8 is not a valid call
+        let amount = call.calldata.extract(511, 256);
+        sol.assert(&amount.bvugt(&BV::from_u64(&ctx, 0xff, 256)));
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn dynamic_args() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let function = function(
+            r#"[{"type":"function","name":"f","stateMutability":"nonpayable","outputs":[],
+            "inputs":[{"name":"data","type":"bytes"},{"name":"items","type":"uint256[]"}]}]"#,
+        );
+        let call = SymbolicCall::new(&ctx, &function);
+        // heads, then bytes length and data, then array length and items
+        let words = 2 + 1 + MAX_DYNAMIC_BYTES / 32 + 1 + MAX_DYNAMIC_ITEMS;
+        assert_eq!(call.calldata.get_size() as u64, (4 + words * 32) * 8);
+
+        let sol = Solver::new(&ctx);
+        for constraint in &call.constraints {
+            sol.assert(constraint);
+        }
+        assert_eq!(sol.check(), SatResult::Sat);
+
+        let tokens = call.decode(&sol.get_model().unwrap()).unwrap();
+        match (&tokens[0], &tokens[1]) {
+            (Token::Bytes(data), Token::Array(items)) => {
+                assert!(data.len() as u64 <= MAX_DYNAMIC_BYTES);
+                assert!(items.len() as u64 <= MAX_DYNAMIC_ITEMS);
+            }
+            tokens => panic!("unexpected {tokens:?}"),
+        }
+    }
+}
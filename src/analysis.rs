@@ -1,6 +1,20 @@
-use crate::bytecode::Mnemonics;
+use crate::{
+    bytecode::{Mnemonic, Mnemonics},
+    cfg::{Cfg, Edge},
+    opcodes::{OpCode, OpCodes},
+};
+use ethabi::Uint;
+use petgraph::visit::EdgeRef;
+use std::collections::{BTreeMap, BTreeSet};
 
+/// Selectors compared by the dispatcher,
+/// or every PUSH4 value when no dispatcher is recognised
 pub fn get_selectors(mnemo: &Mnemonics) -> Vec<u32> {
+    let dispatcher = get_dispatcher(mnemo);
+    if !dispatcher.functions.is_empty() {
+        return dispatcher.functions.into_keys().collect();
+    }
+
     mnemo
         .iter()
         .filter(|mn| mn.pushes.len() == 4)
@@ -23,15 +37,362 @@ pub fn get_jumpdest(code: Mnemonics) -> Vec<u64> {
         .collect()
 }
 
-use crate::opcodes::{OpCode, OpCodes};
+/// Entry points of a contract, recovered from its dispatcher
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dispatcher {
+    /// pc each selector starts executing at
+    pub functions: BTreeMap<u32, usize>,
+    /// pc of calls that match no selector, unless they revert
+    pub fallback: Option<usize>,
+    /// pc of calls with empty calldata, if they don't go to the fallback
+    pub receive: Option<usize>,
+}
+
+/// What the dispatcher knows about a value of the stack
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Const(Uint),
+    /// first word of the calldata
+    Word,
+    Selector,
+    Size,
+    Callvalue,
+    Cond(Cond),
+    Unknown,
+}
+
+/// Conditions the dispatcher branches on, true when the value is nonzero
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cond {
+    Eq(u32),
+    Ne(u32),
+    /// ordering of the selector with a constant, for binary searches
+    Cmp,
+    /// calldata is shorter than a selector
+    Short(bool),
+    Empty(bool),
+    Value(bool),
+}
+
+/// What the calldata of a branch of the dispatcher is known to be
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Calldata {
+    Any,
+    Short,
+    Empty,
+}
+
+#[derive(Debug, Clone)]
+struct Branch {
+    start: usize,
+    stack: Vec<Value>,
+    calldata: Calldata,
+    /// a value derived from the selector was used by something else than a comparison
+    tainted: bool,
+    /// walking the buckets of a jump table, exits are not the fallback
+    table: bool,
+}
+
+impl Value {
+    fn selector(&self) -> Option<u32> {
+        match self {
+            Value::Const(c) if c.bits() <= 32 => Some(c.low_u32()),
+            _ => None,
+        }
+    }
+
+    fn negate(self) -> Value {
+        match self {
+            Value::Cond(cond) => Value::Cond(match cond {
+                Cond::Eq(c) => Cond::Ne(c),
+                Cond::Ne(c) => Cond::Eq(c),
+                Cond::Cmp => Cond::Cmp,
+                Cond::Short(b) => Cond::Short(!b),
+                Cond::Empty(b) => Cond::Empty(!b),
+                Cond::Value(b) => Cond::Value(!b),
+            }),
+            Value::Size => Value::Cond(Cond::Empty(true)),
+            Value::Callvalue => Value::Cond(Cond::Value(false)),
+            Value::Selector => Value::Cond(Cond::Eq(0)),
+            _ => Value::Unknown,
+        }
+    }
+
+    /// the value as a condition of JUMPI
+    fn cond(&self) -> Option<Cond> {
+        match self {
+            Value::Cond(cond) => Some(*cond),
+            Value::Size => Some(Cond::Empty(false)),
+            Value::Callvalue => Some(Cond::Value(true)),
+            Value::Selector => Some(Cond::Ne(0)),
+            _ => None,
+        }
+    }
+}
+
+/// result of a binary opcode, `a` being the top of the stack
+fn binary(opcode: &OpCodes, a: &Value, b: &Value) -> Value {
+    use Value::*;
+
+    let four = |c: &Uint| *c == Uint::from(4) || *c == Uint::from(3);
+    match (opcode, a, b) {
+        (OpCodes::Shr, Const(c), Word) if *c == Uint::from(224) => Selector,
+        (OpCodes::Div, Word, Const(c)) if *c == Uint::one() << 224 => Selector,
+        (OpCodes::And, Selector, Const(c)) | (OpCodes::And, Const(c), Selector)
+            if *c == Uint::from(u32::MAX) =>
+        {
+            Selector
+        }
+        (OpCodes::Eq, Selector, c) | (OpCodes::Eq, c, Selector) => match c.selector() {
+            Some(c) => Cond(self::Cond::Eq(c)),
+            None => Unknown,
+        },
+        (OpCodes::Xor | OpCodes::Sub, Selector, c) | (OpCodes::Xor | OpCodes::Sub, c, Selector) => {
+            match c.selector() {
+                Some(c) => Cond(self::Cond::Ne(c)),
+                None => Unknown,
+            }
+        }
+        (OpCodes::Lt | OpCodes::Gt, Selector, Const(_))
+        | (OpCodes::Lt | OpCodes::Gt, Const(_), Selector) => Cond(self::Cond::Cmp),
+        (OpCodes::Lt, Size, Const(c)) | (OpCodes::Gt, Const(c), Size) if four(c) => {
+            Cond(self::Cond::Short(true))
+        }
+        (OpCodes::Gt, Size, Const(c)) | (OpCodes::Lt, Const(c), Size) if four(c) => {
+            Cond(self::Cond::Short(false))
+        }
+        _ => Unknown,
+    }
+}
+
+impl Branch {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or(Value::Unknown)
+    }
+
+    /// execute an instruction on the values of the stack
+    fn step(&mut self, instruction: &Mnemonic) {
+        let op = instruction.op;
+        let opcode = op.opcode();
+
+        if op.is_push() {
+            let value = Uint::from_big_endian(instruction.pushes);
+            self.stack.push(Value::Const(value));
+        } else if let Some(n) = op.dup_size() {
+            let value = match self.stack.len().checked_sub(n as usize) {
+                Some(i) => self.stack[i].clone(),
+                None => Value::Unknown,
+            };
+            self.stack.push(value);
+        } else if let Some(n) = op.swap_size() {
+            if let Some(top) = self.stack.len().checked_sub(1) {
+                match top.checked_sub(n as usize) {
+                    Some(other) => self.stack.swap(top, other),
+                    None => self.stack[top] = Value::Unknown,
+                }
+            }
+        } else {
+            let value = match opcode {
+                OpCodes::Calldatasize => Value::Size,
+                OpCodes::Callvalue => Value::Callvalue,
+                OpCodes::Calldataload => match self.pop() {
+                    Value::Const(c) if c.is_zero() => Value::Word,
+                    _ => Value::Unknown,
+                },
+                OpCodes::Iszero => self.pop().negate(),
+                OpCodes::Shr
+                | OpCodes::Div
+                | OpCodes::And
+                | OpCodes::Eq
+                | OpCodes::Xor
+                | OpCodes::Sub
+                | OpCodes::Lt
+                | OpCodes::Gt => {
+                    let (a, b) = (self.pop(), self.pop());
+                    let value = binary(opcode, &a, &b);
+                    let derived = |v: &Value| matches!(v, Value::Word | Value::Selector);
+                    if value == Value::Unknown && (derived(&a) || derived(&b)) {
+                        self.tainted = true;
+                    }
+                    value
+                }
+                _ => {
+                    let (inputs, outputs) = opcode.stack_io();
+                    for _ in 0..inputs {
+                        if matches!(self.pop(), Value::Word | Value::Selector) {
+                            self.tainted = true;
+                        }
+                    }
+                    if outputs == 0 {
+                        return;
+                    }
+                    Value::Unknown
+                }
+            };
+            self.stack.push(value);
+        }
+    }
+}
+
+/// recover the entry points of the functions from the dispatcher,
+/// following the comparisons of the selector from the start of the code.
+/// Recognises linear and binary searches on the selector,
+/// as solidity and vyper compare it, and jump tables indexed by the selector.
+pub fn get_dispatcher(mnemo: &Mnemonics) -> Dispatcher {
+    let mut dispatcher = Dispatcher::default();
+    let cfg = Cfg::new(mnemo);
+    if mnemo.is_empty() {
+        return dispatcher;
+    }
+
+    let mut fallbacks = BTreeSet::new();
+    let mut receives = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    let mut work = vec![Branch {
+        start: 0,
+        stack: Vec::new(),
+        calldata: Calldata::Any,
+        tainted: false,
+        table: false,
+    }];
+
+    while let Some(mut branch) = work.pop() {
+        let Some(node) = cfg.node(branch.start) else {
+            continue;
+        };
+        if !seen.insert((branch.start, branch.calldata, branch.table)) {
+            continue;
+        }
+        let block = &cfg.graph[node];
+        let code = &mnemo[block.range.clone()];
+        let (last, body) = code.split_last().unwrap();
+
+        for instruction in body {
+            branch.step(instruction);
+        }
+        let height = branch.stack.len();
+        let target = branch.stack.last().cloned();
+        let cond = branch
+            .stack
+            .len()
+            .checked_sub(2)
+            .map(|i| branch.stack[i].clone());
+        branch.step(last);
+
+        let fall = cfg
+            .graph
+            .edges(node)
+            .find(|edge| edge.weight() == &Edge::Fall)
+            .map(|edge| cfg.graph[edge.target()].start);
+        let next = |start, calldata| Branch {
+            start,
+            calldata,
+            ..branch.clone()
+        };
+
+        let opcode = last.opcode();
+
+        let jump = match target {
+            Some(Value::Const(target)) if target.bits() <= 64 => Some(target.low_u64() as usize),
+            _ => None,
+        };
+        match (opcode, cond.as_ref().and_then(Value::cond)) {
+            (OpCodes::Jumpi, Some(cond)) => {
+                let (Some(jump), Some(fall)) = (jump, fall) else {
+                    continue;
+                };
+                let calldata = branch.calldata;
+                match cond {
+                    Cond::Eq(selector) => {
+                        dispatcher.functions.entry(selector).or_insert(jump);
+                        work.push(next(fall, calldata));
+                    }
+                    Cond::Ne(selector) => {
+                        dispatcher.functions.entry(selector).or_insert(fall);
+                        work.push(next(jump, calldata));
+                    }
+                    Cond::Cmp | Cond::Value(_) => {
+                        work.push(next(jump, calldata));
+                        work.push(next(fall, calldata));
+                    }
+                    Cond::Short(short) => {
+                        let (short, long) = if short { (jump, fall) } else { (fall, jump) };
+                        work.push(next(short, Calldata::Short));
+                        work.push(next(long, calldata));
+                    }
+                    Cond::Empty(empty) => {
+                        let (empty, full) = if empty { (jump, fall) } else { (fall, jump) };
+                        work.push(next(empty, Calldata::Empty));
+                        work.push(next(full, Calldata::Any));
+                    }
+                }
+            }
+            // a trampoline, leaving the stack as it was
+            (OpCodes::Jump, _) if jump.is_some() && height == branch.stack.len() + 1 => {
+                let start = branch.start;
+                if body
+                    .iter()
+                    .all(|mn| mn.op.is_push() || mn.opcode() == &OpCodes::Jumpdest)
+                    && body.len() <= 2
+                {
+                    work.push(next(jump.unwrap(), branch.calldata));
+                } else if !branch.table {
+                    exit(&mut fallbacks, &mut receives, start, branch.calldata);
+                }
+            }
+            // the selector indexes a table of jump targets
+            (OpCodes::Jump, _) if jump.is_none() && branch.tainted && !branch.table => {
+                for start in cfg.jumpdests() {
+                    work.push(Branch {
+                        table: true,
+                        ..next(start as usize, branch.calldata)
+                    });
+                }
+            }
+            (OpCodes::Revert | OpCodes::Invalid, _) => {}
+            _ if !branch.table => {
+                // the dispatcher may fall into a block that only shuffles the stack
+                let shuffles = body.iter().chain([last]).all(|mn| {
+                    mn.op.is_push()
+                        || mn.op.is_dup()
+                        || mn.op.is_swap()
+                        || matches!(mn.opcode(), OpCodes::Jumpdest | OpCodes::Pop)
+                });
+                match fall {
+                    Some(fall) if shuffles => work.push(next(fall, branch.calldata)),
+                    _ => exit(&mut fallbacks, &mut receives, branch.start, branch.calldata),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    dispatcher.fallback = fallbacks.first().copied();
+    dispatcher.receive = receives.first().copied();
+    dispatcher
+}
+
+/// record where a branch that matched no selector leaves the dispatcher
+fn exit(
+    fallbacks: &mut BTreeSet<usize>,
+    receives: &mut BTreeSet<usize>,
+    pc: usize,
+    calldata: Calldata,
+) {
+    match calldata {
+        Calldata::Empty => receives.insert(pc),
+        Calldata::Any | Calldata::Short => fallbacks.insert(pc),
+    };
+}
+
 #[cfg(test)]
-use crate::{bytecode::to_mnemonics, utils::get_artifacts_code};
+use crate::{asm::assemble, bytecode::to_mnemonics, utils::get_artifacts_code};
 
 #[test]
 fn weth() {
     let bytecode = get_artifacts_code("test-data/WETH9.asm").unwrap();
     let mnemonics = to_mnemonics(&bytecode);
-    let selectors = get_selectors(mnemonics);
+    let selectors = get_selectors(&mnemonics);
 
     let expected = vec![
         0x06fdde03, // name()
@@ -49,3 +410,107 @@ fn weth() {
 
     assert!(expected.iter().all(|sel| selectors.contains(sel)));
 }
+
+#[test]
+fn weth_dispatcher() {
+    let bytecode = get_artifacts_code("test-data/WETH9.asm").unwrap();
+    let mnemonics = to_mnemonics(&bytecode);
+    let dispatcher = get_dispatcher(&mnemonics);
+
+    let expected = BTreeMap::from([
+        (0x06fdde03, 0x09c),
+        (0x095ea7b3, 0x126),
+        (0x18160ddd, 0x15e),
+        (0x23b872dd, 0x185),
+        (0x2e1a7d4d, 0x1af),
+        (0x313ce567, 0x1c7),
+        (0x70a08231, 0x1f2),
+        (0x95d89b41, 0x213),
+        (0xa9059cbb, 0x228),
+        (0xd0e30db0, 0x092),
+        (0xdd62ed3e, 0x24c),
+    ]);
+    assert_eq!(dispatcher.functions, expected);
+    // the fallback is deposit
+    assert_eq!(dispatcher.fallback, Some(0x92));
+    assert_eq!(dispatcher.receive, None);
+}
+
+#[test]
+fn binary_search() {
+    // short calldata goes to a receive, the rest is split on 0x20000000 and reverts on no match
+    let bytecode = assemble(
+        "
+        PUSH1 0x04 CALLDATASIZE LT PUSH2 short JUMPI
+        PUSH0 CALLDATALOAD PUSH1 0xe0 SHR
+        DUP1 PUSH4 0x20000000 GT PUSH2 low JUMPI
+        DUP1 PUSH4 0x30000000 EQ PUSH2 f3 JUMPI
+        PUSH2 revert JUMP
+        low: JUMPDEST DUP1 PUSH4 0x10000000 EQ PUSH2 f1 JUMPI
+        PUSH2 revert JUMP
+        short: JUMPDEST CALLDATASIZE PUSH2 revert JUMPI
+        CALLVALUE POP STOP ; 0x3d: receive
+        revert: JUMPDEST PUSH0 DUP1 REVERT
+        f1: JUMPDEST STOP ; 0x44
+        f3: JUMPDEST STOP ; 0x46
+        ",
+    )
+    .unwrap();
+    let mnemonics = to_mnemonics(&bytecode);
+    let dispatcher = get_dispatcher(&mnemonics);
+
+    let expected = BTreeMap::from([(0x10000000, 0x44), (0x30000000, 0x46)]);
+    assert_eq!(dispatcher.functions, expected);
+    assert_eq!(dispatcher.fallback, None);
+    assert_eq!(dispatcher.receive, Some(0x3d));
+    assert_eq!(get_selectors(&mnemonics), vec![0x10000000, 0x30000000]);
+}
+
+#[test]
+fn vyper() {
+    // each selector is xored with a constant, skipping the body when they differ
+    let bytecode = assemble(
+        "
+        PUSH1 0x03 CALLDATASIZE GT PUSH2 start JUMPI
+        PUSH2 fallback JUMP
+        start: JUMPDEST PUSH0 CALLDATALOAD PUSH1 0xe0 SHR
+        PUSH4 0xaabbccdd DUP2 XOR PUSH2 next JUMPI
+        STOP ; 0x1d
+        next: JUMPDEST PUSH4 0x11223344 DUP2 XOR PUSH2 fallback JUMPI
+        STOP ; 0x2a
+        fallback: JUMPDEST CALLVALUE POP STOP ; 0x2b
+        ",
+    )
+    .unwrap();
+    let mnemonics = to_mnemonics(&bytecode);
+    let dispatcher = get_dispatcher(&mnemonics);
+
+    let expected = BTreeMap::from([(0xaabbccdd, 0x1d), (0x11223344, 0x2a)]);
+    assert_eq!(dispatcher.functions, expected);
+    assert_eq!(dispatcher.fallback, Some(0x2b));
+}
+
+#[test]
+fn jump_table() {
+    // the selector modulo 2 picks a bucket from a table at the end of the code
+    let bytecode = assemble(
+        "
+        PUSH0 CALLDATALOAD PUSH1 0xe0 SHR
+        PUSH1 0x02 DUP2 MOD PUSH1 0x01 SHL PUSH2 table ADD
+        PUSH1 0x02 SWAP1 PUSH1 0x1e CODECOPY PUSH0 MLOAD JUMP
+        even: JUMPDEST PUSH4 0xaabbccd0 DUP2 EQ PUSH2 f0 JUMPI PUSH2 revert JUMP
+        odd: JUMPDEST PUSH4 0x11223341 DUP2 EQ PUSH2 f1 JUMPI PUSH2 revert JUMP
+        revert: JUMPDEST PUSH0 DUP1 REVERT
+        f0: JUMPDEST STOP ; 0x3d
+        f1: JUMPDEST STOP ; 0x3f
+        table: 0x0019 0x0029 ; even and odd
+        ",
+    )
+    .unwrap();
+    let mnemonics = to_mnemonics(&bytecode);
+    let dispatcher = get_dispatcher(&mnemonics);
+
+    let expected = BTreeMap::from([(0xaabbccd0, 0x3d), (0x11223341, 0x3f)]);
+    assert_eq!(dispatcher.functions, expected);
+    assert_eq!(dispatcher.fallback, None);
+}
@@ -0,0 +1,221 @@
+use crate::{
+    analysis::get_dispatcher,
+    bytecode::Mnemonics,
+    cfg::Cfg,
+    opcodes::{OpCode, OpCodes},
+};
+use ethabi::Contract;
+use eyre::{bail, eyre};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+};
+
+/// label of the JUMPDEST at `pc`
+fn label(pc: usize) -> String {
+    format!("tag_{pc:x}")
+}
+
+/// text disassembly, with a label on every JUMPDEST, the targets of the jumps
+/// and the selectors of the dispatcher, named after the abi functions when they are known.
+/// It assembles back into the same code.
+pub fn disassemble(code: &Mnemonics, abi: &Contract) -> String {
+    let mut cfg = Cfg::new(code);
+    let stack = cfg.analyze_stack(code);
+    let dispatcher = get_dispatcher(code);
+
+    let name = |selector: u32| {
+        abi.functions()
+            .find(|function| function.short_signature() == selector.to_be_bytes())
+            .map_or_else(
+                || format!("{selector:#010x}"),
+                |function| function.signature(),
+            )
+    };
+    let mut entries: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (selector, pc) in &dispatcher.functions {
+        entries.entry(*pc).or_default().push(name(*selector));
+    }
+    if let Some(pc) = dispatcher.fallback {
+        entries.entry(pc).or_default().push("fallback".into());
+    }
+    if let Some(pc) = dispatcher.receive {
+        entries.entry(pc).or_default().push("receive".into());
+    }
+
+    code.iter().fold(String::new(), |mut out, instruction| {
+        let pc = instruction.pc;
+        if let Some(names) = entries.get(&pc) {
+            let _ = writeln!(out, "; {}", names.join(", "));
+        }
+        if instruction.opcode() == &OpCodes::Jumpdest {
+            let _ = writeln!(out, "{}:", label(pc));
+        }
+
+        let selector = match instruction.pushes {
+            [a, b, c, d] => Some(u32::from_be_bytes([*a, *b, *c, *d])),
+            _ => None,
+        };
+        let comment = if let Some(targets) = stack.targets.get(&pc) {
+            let targets: Vec<_> = targets.iter().map(|target| label(*target)).collect();
+            Some(format!("-> {}", targets.join(", ")))
+        } else if stack.unresolved.contains(&pc) {
+            Some("-> ?".into())
+        } else {
+            selector
+                .filter(|selector| dispatcher.functions.contains_key(selector))
+                .map(name)
+        };
+
+        // pushes cut by the end of the code are kept as raw bytes
+        let size = instruction.op.push_size().unwrap_or_default() as usize;
+        let _ = match comment {
+            _ if instruction.pushes.len() < size => writeln!(
+                out,
+                "{pc:#06x}: 0x{:02x}{} ; {} past the end",
+                instruction.op.u8(),
+                hex::encode(instruction.pushes),
+                instruction.op.name()
+            ),
+            Some(comment) => writeln!(out, "{:<32} ; {comment}", instruction.to_string()),
+            None => writeln!(out, "{instruction}"),
+        };
+        out
+    })
+}
+
+/// Operand of a push
+enum Operand<'s> {
+    Bytes(Vec<u8>),
+    Label(&'s str),
+}
+
+/// assemble the text of `disassemble`, or a hand written program.
+/// Instructions are separated by whitespace, with a hex or label operand after pushes.
+/// `name:` defines a label, `0x..:` pc prefixes are ignored and `;` starts a comment.
+/// Hex anywhere else is copied as raw bytes.
+pub fn assemble(source: &str) -> eyre::Result<Vec<u8>> {
+    let mut items = Vec::new();
+    let mut labels = HashMap::new();
+    let mut pc = 0;
+
+    let tokens = source.lines().flat_map(|line| {
+        line.split(';')
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+    });
+    let mut tokens = tokens.peekable();
+
+    while let Some(token) = tokens.next() {
+        if let Some(name) = token.strip_suffix(':') {
+            if !name.starts_with("0x") && labels.insert(name, pc).is_some() {
+                bail!("label {name} is defined twice");
+            }
+            continue;
+        }
+
+        if let Some(bytes) = token.strip_prefix("0x") {
+            let bytes = decode(bytes)?;
+            pc += bytes.len();
+            items.push((bytes, None));
+            continue;
+        }
+
+        let op = OpCode::from_name(token).ok_or_else(|| eyre!("unknown opcode {token}"))?;
+        let size = op.push_size().unwrap_or_default() as usize;
+        let operand = match (size, tokens.peek()) {
+            (0, _) => None,
+            (_, Some(_)) => {
+                let value = tokens.next().unwrap();
+                match value.strip_prefix("0x") {
+                    Some(bytes) => Some(Operand::Bytes(decode(bytes)?)),
+                    None => Some(Operand::Label(value)),
+                }
+            }
+            (_, None) => bail!("missing operand of {token}"),
+        };
+
+        pc += 1 + size;
+        items.push((vec![op.u8()], operand.map(|operand| (size, operand))));
+    }
+
+    let mut code = Vec::with_capacity(pc);
+    for (bytes, operand) in items {
+        code.extend(bytes);
+        let Some((size, operand)) = operand else {
+            continue;
+        };
+
+        let value = match operand {
+            Operand::Bytes(bytes) => bytes,
+            Operand::Label(name) => {
+                let pc = labels
+                    .get(name)
+                    .ok_or_else(|| eyre!("unknown label {name}"))?;
+                let bytes = pc.to_be_bytes();
+                let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len());
+                bytes[start..].to_vec()
+            }
+        };
+        if value.len() > size {
+            bail!("0x{} doesn't fit in {size} bytes", hex::encode(value));
+        }
+        code.extend(std::iter::repeat(0).take(size - value.len()));
+        code.extend(value);
+    }
+
+    Ok(code)
+}
+
+/// hex digits, with a leading zero if there is an odd number of them
+fn decode(digits: &str) -> eyre::Result<Vec<u8>> {
+    match digits.len() % 2 {
+        0 => Ok(hex::decode(digits)?),
+        _ => Ok(hex::decode(format!("0{digits}"))?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bytecode::to_mnemonics, utils::get_artifacts_code};
+
+    #[test]
+    fn labels() {
+        let code = assemble(
+            "
+            PUSH1 0x04 CALLDATALOAD
+            PUSH1 0x2a EQ
+            PUSH1 ok JUMPI ; skip the revert
+            PUSH0 PUSH0 REVERT
+            ok: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        assert_eq!(code, hex::decode("600435602a14600c575f5ffd5b00").unwrap());
+
+        // operands are left padded, and raw bytes are kept
+        let code = assemble("0x0000: PUSH2 0x1 0x0c00").unwrap();
+        assert_eq!(code, vec![0x61, 0x00, 0x01, 0x0c, 0x00]);
+
+        assert!(assemble("PUSH1 0x0100").is_err());
+        assert!(assemble("PUSH1 nowhere").is_err());
+        assert!(assemble("PUSH1").is_err());
+        assert!(assemble("FOO").is_err());
+    }
+
+    #[test]
+    fn weth() {
+        let bytecode = get_artifacts_code("test-data/WETH9.asm").unwrap();
+        let code = to_mnemonics(&bytecode);
+        let text = disassemble(&code, &Contract::default());
+
+        assert!(text.contains("0x0000: PUSH1 0x80\n"));
+        assert!(text.contains("; 0xd0e30db0, fallback\ntag_92:\n0x0092: JUMPDEST\n"));
+        assert!(text
+            .lines()
+            .any(|line| line.starts_with("0x000c: JUMPI") && line.ends_with("; -> tag_92")));
+        assert_eq!(assemble(&text).unwrap(), bytecode);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
-    opcodes::{OpCode, OpCodes},
+    cfg::{propagate, Cfg},
+    opcodes::{OpCode, OpCodes, Revision},
     utils::range_to_slice,
 };
 use std::fmt::Display;
@@ -19,18 +20,27 @@ impl<'a> Mnemonic<'a> {
 
 impl Display for Mnemonic<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}: {:?}", self, self.opcode())
+        write!(f, "{:#06x}: {}", self.pc, self.op.name())?;
+        if !self.pushes.is_empty() {
+            write!(f, " 0x{}", hex::encode(self.pushes))?;
+        }
+        Ok(())
     }
 }
 
 pub type Mnemonics<'a> = Vec<Mnemonic<'a>>;
 
-/// turns hex into mnemonics
+/// turns hex into mnemonics, with the instruction set of the latest revision
 pub fn to_mnemonics(bytecode: &[u8]) -> Mnemonics {
+    to_mnemonics_for(bytecode, Revision::default())
+}
+
+/// turns hex into mnemonics, the opcodes undefined in `revision` are INVALID and take no immediate
+pub fn to_mnemonics_for(bytecode: &[u8], revision: Revision) -> Mnemonics {
     let (mut code, mut pc) = (Vec::new(), 0);
 
     while let Some(b) = bytecode.get(pc) {
-        let op = OpCode::from_u8(*b);
+        let op = OpCode::with_revision(*b, revision);
 
         let (_pc, pushes) = if let Some(push_size) = op.push_size() {
             // write in buffer an skip until stop
@@ -55,3 +65,253 @@ pub fn to_mnemonics(bytecode: &[u8]) -> Mnemonics {
 
     code
 }
+
+/// Metadata solc appends to the runtime code, as a CBOR map
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// compiler version, or the full version string of nightly builds
+    pub solc: Option<String>,
+    pub ipfs: Option<Vec<u8>>,
+    /// bzzr0 or bzzr1 hash of the metadata json
+    pub swarm: Option<Vec<u8>>,
+    pub experimental: bool,
+}
+
+/// Items of the CBOR subset solc emits
+enum Cbor<'a> {
+    Bytes(&'a [u8]),
+    Text(&'a str),
+    Bool(bool),
+    Map(usize),
+}
+
+/// first CBOR item of `data` and its encoded length
+fn cbor_item(data: &[u8]) -> Option<(Cbor, usize)> {
+    let (head, rest) = data.split_first()?;
+    let (major, info) = (head >> 5, head & 0x1f);
+
+    match (major, info) {
+        (7, 20) => return Some((Cbor::Bool(false), 1)),
+        (7, 21) => return Some((Cbor::Bool(true), 1)),
+        _ => {}
+    }
+
+    let (len, header) = match info {
+        0..=23 => (info as usize, 1),
+        24 => (*rest.first()? as usize, 2),
+        25 => (
+            u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize,
+            3,
+        ),
+        _ => return None,
+    };
+    let content = || data.get(header..header + len);
+
+    match major {
+        2 => Some((Cbor::Bytes(content()?), header + len)),
+        3 => {
+            let text = std::str::from_utf8(content()?).ok()?;
+            Some((Cbor::Text(text), header + len))
+        }
+        5 => Some((Cbor::Map(len), header)),
+        _ => None,
+    }
+}
+
+impl Metadata {
+    /// decode a CBOR map spanning the whole of `data`
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (Cbor::Map(entries), mut at) = cbor_item(data)? else {
+            return None;
+        };
+        if entries == 0 {
+            return None;
+        }
+
+        let mut metadata = Metadata::default();
+        for _ in 0..entries {
+            let (key, len) = cbor_item(&data[at..])?;
+            at += len;
+            let (value, len) = cbor_item(&data[at..])?;
+            at += len;
+
+            match (key, value) {
+                (Cbor::Text("solc"), Cbor::Bytes(version)) => {
+                    let version: Vec<_> = version.iter().map(|v| v.to_string()).collect();
+                    metadata.solc = Some(version.join("."));
+                }
+                (Cbor::Text("solc"), Cbor::Text(version)) => metadata.solc = Some(version.into()),
+                (Cbor::Text("ipfs"), Cbor::Bytes(hash)) => metadata.ipfs = Some(hash.to_vec()),
+                (Cbor::Text("bzzr0" | "bzzr1"), Cbor::Bytes(hash)) => {
+                    metadata.swarm = Some(hash.to_vec())
+                }
+                (Cbor::Text("experimental"), Cbor::Bool(experimental)) => {
+                    metadata.experimental = experimental
+                }
+                (Cbor::Text(_), _) => {}
+                _ => return None,
+            }
+        }
+
+        (at == data.len()).then_some(metadata)
+    }
+}
+
+/// split the code from the metadata trailer, if it ends with one.
+/// The trailer is a CBOR map followed by its length on 2 bytes.
+pub fn split_metadata(bytecode: &[u8]) -> (&[u8], Option<Metadata>) {
+    let trailer = || {
+        let at = bytecode.len().checked_sub(2)?;
+        let len = u16::from_be_bytes([bytecode[at], bytecode[at + 1]]) as usize;
+        let start = at.checked_sub(len)?;
+        Some((start, Metadata::decode(&bytecode[start..at])?))
+    };
+
+    match trailer() {
+        Some((start, metadata)) => (&bytecode[..start], Some(metadata)),
+        None => (bytecode, None),
+    }
+}
+
+/// Parts of creation code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deployment<'a> {
+    pub constructor: &'a [u8],
+    /// code copied to memory and returned by the constructor
+    pub runtime: &'a [u8],
+    /// what follows the runtime, usually the constructor arguments
+    pub args: &'a [u8],
+}
+
+/// separate the constructor from the runtime code it returns,
+/// found in a block that copies a constant part of the code to memory and returns it
+pub fn split_deploy(bytecode: &[u8]) -> Option<Deployment> {
+    let code = to_mnemonics(bytecode);
+    let cfg = Cfg::new(&code);
+
+    for block in cfg.blocks() {
+        let mut stack = Vec::new();
+        // memory offset, code offset and size of the last copy
+        let mut copied = None;
+
+        for instruction in &code[block.range.clone()] {
+            let arg = |i: usize| stack.len().checked_sub(i + 1).and_then(|i| stack[i]);
+
+            match instruction.opcode() {
+                OpCodes::Codecopy => copied = Some((arg(0), arg(1), arg(2))),
+                OpCodes::Return => {
+                    if let Some((Some(dest), Some(offset), Some(size))) = copied {
+                        let (offset, size) = (offset as usize, size as usize);
+                        let returned = arg(0) == Some(dest) && arg(1) == Some(size as u64);
+                        // a range past the bytecode, or overflowing, is no runtime code
+                        let end = offset
+                            .checked_add(size)
+                            .filter(|end| *end <= bytecode.len());
+                        if let Some(end) = end.filter(|_| returned && offset > instruction.pc) {
+                            return Some(Deployment {
+                                constructor: &bytecode[..offset],
+                                runtime: &bytecode[offset..end],
+                                args: &bytecode[end..],
+                            });
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            propagate(&mut stack, instruction);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::get_artifacts_code;
+
+    #[test]
+    fn metadata() {
+        let bytecode = get_artifacts_code("test-data/WETH9.asm").unwrap();
+        let (code, metadata) = split_metadata(&bytecode);
+        let metadata = metadata.unwrap();
+
+        assert_eq!(code.len(), bytecode.len() - 43);
+        assert_eq!(
+            metadata.swarm.unwrap(),
+            hex::decode("a474ad524b75b333105ffb7c638b74c8d9ece3444a1035f95c5e85fe6f8e1758")
+                .unwrap()
+        );
+        assert_eq!(metadata.solc, None);
+
+        // ipfs hash and solc 0.8.19
+        let trailer = hex::decode(concat!(
+            "a2646970667358221220",
+            "1111111111111111111111111111111111111111111111111111111111111111",
+            "64736f6c63430008130033"
+        ))
+        .unwrap();
+        let bytecode = [&[0x00][..], &trailer].concat();
+        let (code, metadata) = split_metadata(&bytecode);
+        let metadata = metadata.unwrap();
+        assert_eq!(code, [0x00]);
+        assert_eq!(metadata.solc.as_deref(), Some("0.8.19"));
+        assert_eq!(metadata.ipfs.unwrap().len(), 34);
+
+        // no trailer
+        assert_eq!(
+            split_metadata(&[0x60, 0x01, 0x00]),
+            (&[0x60, 0x01, 0x00][..], None)
+        );
+    }
+
+    #[test]
+    fn deploy() {
+        // copy 3 bytes from 0x0c to memory and return them, followed by an argument
+        let bytecode = hex::decode("6003600c5f3960035ff3fe005f5ff32a").unwrap();
+        let deployment = split_deploy(&bytecode).unwrap();
+
+        assert_eq!(deployment.constructor.len(), 0x0c);
+        assert_eq!(deployment.runtime, [0x5f, 0x5f, 0xf3]);
+        assert_eq!(deployment.args, [0x2a]);
+        assert!(split_deploy(deployment.runtime).is_none());
+
+        // the same with a size overflowing the offset
+        let bytecode =
+            hex::decode("67ffffffffffffffff60195f3967ffffffffffffffff5ff3fe5f5ff3").unwrap();
+        assert!(split_deploy(&bytecode).is_none());
+    }
+
+    #[test]
+    fn revisions() {
+        // PUSH0 PUSH1 0x2a TLOAD
+        let bytecode = hex::decode("5f602a5c").unwrap();
+
+        let code = to_mnemonics_for(&bytecode, Revision::Cancun);
+        let names: Vec<_> = code.iter().map(|i| i.op.name()).collect();
+        assert_eq!(names, ["PUSH0", "PUSH1", "TLOAD"]);
+
+        let code = to_mnemonics_for(&bytecode, Revision::Shanghai);
+        assert_eq!(code[2].opcode(), &OpCodes::Invalid);
+
+        // before shanghai 0x5f is not a push, the bytes after it are instructions
+        let code = to_mnemonics_for(&bytecode, Revision::London);
+        assert_eq!(code.len(), 3);
+        assert_eq!(code[0].opcode(), &OpCodes::Invalid);
+        assert_eq!(code[0].to_string(), "0x0000: 0x5f");
+        assert_eq!(code[1].pushes, [0x2a]);
+
+        // REVERT is undefined before byzantium
+        let code = to_mnemonics_for(&[0xfd], Revision::Homestead);
+        assert_eq!(code[0].opcode(), &OpCodes::Invalid);
+        assert_eq!(to_mnemonics(&[0xfd])[0].opcode(), &OpCodes::Revert);
+
+        assert_eq!(
+            Revision::from_name("spuriousDragon"),
+            Some(Revision::SpuriousDragon)
+        );
+        assert_eq!(Revision::from_name("Cancun"), Some(Revision::Cancun));
+        assert_eq!(Revision::from_name("prague"), None);
+    }
+}
@@ -0,0 +1,551 @@
+use crate::{
+    bytecode::{Mnemonic, Mnemonics},
+    opcodes::OpCodes,
+};
+use petgraph::{dot::Dot, graph::NodeIndex, prelude::Graph, visit::EdgeRef};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    ops::Range,
+};
+
+/// Most distinct stacks a block is analysed with
+const MAX_CONTEXTS: usize = 64;
+
+/// A run of instructions that is only entered from its first one
+/// and only left from its last one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// pc of the first and last instructions
+    pub start: usize,
+    pub end: usize,
+    /// indices of the instructions in the code
+    pub range: Range<usize>,
+    /// starts with a JUMPDEST
+    pub jumpdest: bool,
+    /// ends with a jump whose target isn't a constant of the block
+    pub dynamic: bool,
+}
+
+impl Display for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:#x}..{:#x}]", self.start, self.end)?;
+        if self.dynamic {
+            write!(f, " dynamic")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edge {
+    /// to the next block of the code
+    Fall,
+    /// to the target of a jump
+    Jump,
+}
+
+impl Display for Edge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Edge::Fall => write!(f, "fall"),
+            Edge::Jump => write!(f, "jump"),
+        }
+    }
+}
+
+/// Control flow graph of the basic blocks of the code
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub graph: Graph<Block, Edge>,
+    /// node of every block by the pc it starts at
+    blocks: BTreeMap<usize, NodeIndex>,
+    /// pc of every instruction, in order
+    pcs: Vec<usize>,
+}
+
+/// Stacks of the blocks, from the abstract execution of the graph
+#[derive(Debug, Clone, Default)]
+pub struct StackAnalysis {
+    /// heights of the stack when entering each block, by the pc it starts at
+    pub heights: BTreeMap<usize, BTreeSet<usize>>,
+    /// pc of the instructions popping more items than the stack holds
+    pub underflows: BTreeSet<usize>,
+    /// constant targets of every jump, by pc of the jump
+    pub targets: BTreeMap<usize, BTreeSet<usize>>,
+    /// pc of the jumps with a target that isn't a constant in some context
+    pub unresolved: BTreeSet<usize>,
+    /// every jump was resolved and no block had more than `MAX_CONTEXTS` stacks,
+    /// otherwise a jump can land on blocks not analyzed in all their contexts
+    pub complete: bool,
+}
+
+impl StackAnalysis {
+    /// blocks entered with different heights.
+    /// Internal functions called at different depths show up here too.
+    pub fn inconsistent(&self) -> Vec<usize> {
+        self.heights
+            .iter()
+            .filter(|(_, heights)| heights.len() > 1)
+            .map(|(start, _)| *start)
+            .collect()
+    }
+
+    /// every target of the jump at `pc`, if they are all known
+    pub fn resolved(&self, pc: usize) -> Option<&BTreeSet<usize>> {
+        match self.complete && !self.unresolved.contains(&pc) {
+            true => self.targets.get(&pc),
+            false => None,
+        }
+    }
+}
+
+/// constant value of push bytes, if it fits
+fn push_value(pushes: &[u8]) -> Option<u64> {
+    let bytes: Vec<_> = pushes.iter().skip_while(|b| **b == 0).copied().collect();
+    if bytes.len() > 8 {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |n, b| n << 8 | *b as u64))
+}
+
+/// execute an instruction on the top of a stack of constants,
+/// items under the top ones are unknown.
+pub fn propagate(stack: &mut Vec<Option<u64>>, instruction: &Mnemonic) {
+    let op = instruction.op;
+
+    if op.is_push() {
+        stack.push(push_value(instruction.pushes));
+    } else if let Some(n) = op.dup_size() {
+        let value = stack.len().checked_sub(n as usize).and_then(|i| stack[i]);
+        stack.push(value);
+    } else if let Some(n) = op.swap_size() {
+        if let Some(top) = stack.len().checked_sub(1) {
+            match top.checked_sub(n as usize) {
+                Some(other) => stack.swap(top, other),
+                // the top is swapped with an unknown item
+                None => stack[top] = None,
+            }
+        }
+    } else {
+        let (inputs, outputs) = op.opcode().stack_io();
+        for _ in 0..inputs {
+            stack.pop();
+        }
+        for _ in 0..outputs {
+            stack.push(None);
+        }
+    }
+}
+
+/// target of the jump ending a block, if it is a constant pushed in the block
+fn jump_target(block: &[Mnemonic]) -> Option<u64> {
+    let (_, body) = block.split_last()?;
+    let mut stack = Vec::new();
+    for instruction in body {
+        propagate(&mut stack, instruction);
+    }
+
+    stack.last().copied().flatten()
+}
+
+impl Cfg {
+    pub fn new(code: &Mnemonics) -> Self {
+        // a block starts at the entry, on a JUMPDEST, or after a jump or a halt
+        let mut starts = Vec::new();
+        for (i, instruction) in code.iter().enumerate() {
+            let opcode = instruction.opcode();
+            let after_branch = i > 0 && {
+                let last = code[i - 1].opcode();
+                last == &OpCodes::Jump || last == &OpCodes::Jumpi || last.is_terminator()
+            };
+            if i == 0 || opcode == &OpCodes::Jumpdest || after_branch {
+                starts.push(i);
+            }
+        }
+
+        let mut graph = Graph::new();
+        let mut blocks = BTreeMap::new();
+        let mut nodes = Vec::new();
+        for (n, start) in starts.iter().enumerate() {
+            let end = starts.get(n + 1).copied().unwrap_or(code.len());
+            let block = Block {
+                start: code[*start].pc,
+                end: code[end - 1].pc,
+                range: *start..end,
+                jumpdest: code[*start].opcode() == &OpCodes::Jumpdest,
+                dynamic: false,
+            };
+            let node = graph.add_node(block);
+            blocks.insert(code[*start].pc, node);
+            nodes.push(node);
+        }
+
+        for (n, node) in nodes.iter().enumerate() {
+            let range = graph[*node].range.clone();
+            let last = code[range.end - 1].opcode();
+
+            if last == &OpCodes::Jump || last == &OpCodes::Jumpi {
+                match jump_target(&code[range]) {
+                    Some(target) => {
+                        // jumps to anything else than a JUMPDEST are invalid
+                        let target = blocks
+                            .get(&(target as usize))
+                            .filter(|target| graph[**target].jumpdest);
+                        if let Some(target) = target {
+                            graph.add_edge(*node, *target, Edge::Jump);
+                        }
+                    }
+                    None => graph[*node].dynamic = true,
+                }
+            }
+
+            let falls =
+                last == &OpCodes::Jumpi || !(last == &OpCodes::Jump || last.is_terminator());
+            if let (true, Some(next)) = (falls, nodes.get(n + 1)) {
+                graph.add_edge(*node, *next, Edge::Fall);
+            }
+        }
+
+        Self {
+            graph,
+            blocks,
+            pcs: code.iter().map(|instruction| instruction.pc).collect(),
+        }
+    }
+
+    /// block starting at `pc`
+    pub fn block(&self, pc: usize) -> Option<&Block> {
+        self.blocks.get(&pc).map(|node| &self.graph[*node])
+    }
+
+    pub fn node(&self, pc: usize) -> Option<NodeIndex> {
+        self.blocks.get(&pc).copied()
+    }
+
+    /// index of the first instruction at or after `pc`
+    pub fn index(&self, pc: usize) -> usize {
+        self.pcs.partition_point(|start| *start < pc)
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values().map(|node| &self.graph[*node])
+    }
+
+    /// pc of every JUMPDEST
+    pub fn jumpdests(&self) -> Vec<u64> {
+        self.blocks()
+            .filter(|block| block.jumpdest)
+            .map(|block| block.start as u64)
+            .collect()
+    }
+
+    /// JUMPDESTs pushed without being jumped to right away,
+    /// as the callers of internal functions push their return address
+    pub fn return_addresses(&self, code: &Mnemonics) -> BTreeSet<u64> {
+        code.windows(2)
+            .filter(|pair| pair[0].op.is_push())
+            .filter(|pair| !matches!(pair[1].opcode(), OpCodes::Jump | OpCodes::Jumpi))
+            .filter_map(|pair| push_value(pair[0].pushes))
+            .filter(|pc| self.block(*pc as usize).is_some_and(|block| block.jumpdest))
+            .collect()
+    }
+
+    /// starts of the blocks following the one starting at `pc`
+    pub fn successors(&self, pc: usize) -> Vec<usize> {
+        let mut successors: Vec<_> = self
+            .node(pc)
+            .into_iter()
+            .flat_map(|node| self.graph.neighbors(node))
+            .map(|node| self.graph[node].start)
+            .collect();
+        successors.sort_unstable();
+        successors
+    }
+
+    /// blocks ending with a jump that couldn't be resolved
+    pub fn unresolved(&self) -> impl Iterator<Item = &Block> {
+        self.blocks().filter(|block| block.dynamic)
+    }
+
+    /// blocks that can't be reached from the entry.
+    /// Unresolved jumps are assumed to reach any JUMPDEST.
+    pub fn unreachable(&self) -> Vec<&Block> {
+        let mut reached = vec![false; self.graph.node_count()];
+        let mut stack: Vec<_> = self.blocks.values().take(1).copied().collect();
+        let mut dynamic = false;
+
+        while let Some(node) = stack.pop() {
+            if std::mem::replace(&mut reached[node.index()], true) {
+                continue;
+            }
+            stack.extend(self.graph.neighbors(node));
+
+            if self.graph[node].dynamic && !dynamic {
+                dynamic = true;
+                stack.extend(
+                    self.blocks
+                        .values()
+                        .filter(|node| self.graph[**node].jumpdest),
+                );
+            }
+        }
+
+        self.blocks()
+            .zip(self.blocks.values())
+            .filter(|(_, node)| !reached[node.index()])
+            .map(|(block, _)| block)
+            .collect()
+    }
+
+    /// execute the blocks on stacks of constants, starting from an empty one at the entry.
+    /// Every stack a block is reached with is a separate context,
+    /// so return addresses pushed by the callers of internal functions are resolved.
+    /// The jumps that are resolved become edges of the graph.
+    pub fn analyze_stack(&mut self, code: &Mnemonics) -> StackAnalysis {
+        let mut analysis = StackAnalysis {
+            complete: true,
+            ..Default::default()
+        };
+        let mut contexts: BTreeMap<NodeIndex, BTreeSet<Vec<Option<u64>>>> = BTreeMap::new();
+        let mut work: Vec<_> = self
+            .blocks
+            .values()
+            .take(1)
+            .map(|node| (*node, Vec::new()))
+            .collect();
+
+        while let Some((node, mut stack)) = work.pop() {
+            let seen = contexts.entry(node).or_default();
+            if seen.contains(&stack) {
+                continue;
+            }
+            if seen.len() >= MAX_CONTEXTS {
+                analysis.complete = false;
+                continue;
+            }
+            seen.insert(stack.clone());
+
+            let block = &self.graph[node];
+            analysis
+                .heights
+                .entry(block.start)
+                .or_default()
+                .insert(stack.len());
+
+            let mut next = Vec::new();
+            for instruction in &code[block.range.clone()] {
+                let opcode = instruction.opcode();
+                if opcode.stack_io().0 as usize > stack.len() {
+                    analysis.underflows.insert(instruction.pc);
+                    break;
+                }
+
+                if opcode == &OpCodes::Jump || opcode == &OpCodes::Jumpi {
+                    match stack.last().copied().flatten() {
+                        Some(target) => {
+                            let target = self
+                                .blocks
+                                .get(&(target as usize))
+                                .filter(|target| self.graph[**target].jumpdest);
+                            if let Some(target) = target {
+                                analysis
+                                    .targets
+                                    .entry(instruction.pc)
+                                    .or_default()
+                                    .insert(self.graph[*target].start);
+                                next.push(*target);
+                            }
+                        }
+                        None => {
+                            analysis.unresolved.insert(instruction.pc);
+                        }
+                    }
+                }
+
+                propagate(&mut stack, instruction);
+                if instruction.pc == block.end {
+                    next.extend(
+                        self.graph
+                            .edges(node)
+                            .filter(|edge| edge.weight() == &Edge::Fall)
+                            .map(|edge| edge.target()),
+                    );
+                }
+            }
+
+            work.extend(next.into_iter().map(|next| (next, stack.clone())));
+        }
+        if !analysis.unresolved.is_empty() {
+            analysis.complete = false;
+        }
+
+        let nodes: Vec<_> = self.blocks.values().copied().collect();
+        for node in nodes {
+            let block = self.graph[node].clone();
+            for target in analysis.targets.get(&block.end).into_iter().flatten() {
+                let target = self.blocks[target];
+                if !self.graph.contains_edge(node, target) {
+                    self.graph.add_edge(node, target, Edge::Jump);
+                }
+            }
+            if analysis.complete && analysis.heights.contains_key(&block.start) {
+                self.graph[node].dynamic = analysis.unresolved.contains(&block.end);
+            }
+        }
+
+        analysis
+    }
+
+    pub fn to_dot(&self) -> String {
+        let graph = self
+            .graph
+            .map(|_, block| block.to_string(), |_, edge| *edge);
+        format!("{}", Dot::new(&graph))
+    }
+
+    /// blocks and edges referred to by the pc the blocks start at
+    pub fn to_json(&self) -> serde_json::Value {
+        let blocks: Vec<_> = self
+            .blocks()
+            .map(|block| {
+                json!({
+                    "start": block.start,
+                    "end": block.end,
+                    "jumpdest": block.jumpdest,
+                    "dynamic": block.dynamic,
+                })
+            })
+            .collect();
+        let edges: Vec<_> = self
+            .graph
+            .edge_indices()
+            .filter_map(|edge| {
+                let (from, to) = self.graph.edge_endpoints(edge)?;
+                Some(json!({
+                    "from": self.graph[from].start,
+                    "to": self.graph[to].start,
+                    "kind": self.graph[edge].to_string(),
+                }))
+            })
+            .collect();
+
+        json!({ "blocks": blocks, "edges": edges })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bytecode::to_mnemonics, utils::get_artifacts_code};
+
+    #[test]
+    fn blocks() {
+        // jump(4) over an invalid opcode, then stop
+        let hex = hex::decode("600456fe5b00").unwrap();
+        let code = to_mnemonics(&hex);
+        let cfg = Cfg::new(&code);
+
+        let starts: Vec<_> = cfg.blocks().map(|block| block.start).collect();
+        assert_eq!(starts, vec![0, 3, 4]);
+        assert_eq!(cfg.successors(0), vec![4]);
+        assert!(cfg.successors(3).is_empty());
+        assert_eq!(cfg.jumpdests(), vec![4]);
+        assert_eq!(cfg.unresolved().count(), 0);
+        assert_eq!(cfg.unreachable(), vec![cfg.block(3).unwrap()]);
+    }
+
+    #[test]
+    fn dynamic() {
+        // jump(calldataload(0)), the JUMPI falls through to the next JUMPDEST
+        let hex = hex::decode("5f3556fe5b60015b").unwrap();
+        let code = to_mnemonics(&hex);
+        let cfg = Cfg::new(&code);
+
+        assert!(cfg.block(0).unwrap().dynamic);
+        assert!(cfg.successors(0).is_empty());
+        assert_eq!(cfg.successors(4), vec![7]);
+        // every JUMPDEST could be the target
+        assert_eq!(cfg.unreachable(), vec![cfg.block(3).unwrap()]);
+    }
+
+    #[test]
+    fn internal_function() {
+        // f is called from two places, and returns to the address pushed by its caller
+        // 0x00: push ret1, push f, jump
+        // 0x05: ret1: push ret2, push f, jump
+        // 0x0b: ret2: stop
+        // 0x0d: f: jump
+        let hex = hex::decode("6005600d565b600b600d565b005b56").unwrap();
+        let code = to_mnemonics(&hex);
+        let mut cfg = Cfg::new(&code);
+        assert!(cfg.block(0xd).unwrap().dynamic);
+        assert_eq!(cfg.return_addresses(&code), BTreeSet::from([0x5, 0xb]));
+
+        let analysis = cfg.analyze_stack(&code);
+        assert!(analysis.complete);
+        assert!(analysis.underflows.is_empty());
+        assert_eq!(analysis.heights[&0xd], BTreeSet::from([1]));
+        assert_eq!(analysis.resolved(0xe), Some(&BTreeSet::from([0x5, 0xb])));
+
+        assert!(!cfg.block(0xd).unwrap().dynamic);
+        assert_eq!(cfg.successors(0xd), vec![0x5, 0xb]);
+        assert!(cfg.unreachable().is_empty());
+    }
+
+    #[test]
+    fn partial() {
+        // f returns to 0x05, and to 0x0f once the dynamic jump lands on 0x09
+        // 0x00: push 0x05, push f, jump
+        // 0x05: jump(calldataload(0))
+        // 0x09: push 0x0f, push f, jump
+        // 0x0f: stop
+        // 0x11: f: jump
+        let hex = hex::decode("60056011565b5f35565b600f6011565b005b56").unwrap();
+        let code = to_mnemonics(&hex);
+        let mut cfg = Cfg::new(&code);
+
+        let analysis = cfg.analyze_stack(&code);
+        assert_eq!(analysis.unresolved, BTreeSet::from([0x8]));
+        assert_eq!(analysis.targets[&0x12], BTreeSet::from([0x5]));
+        assert!(!analysis.complete);
+        assert_eq!(analysis.resolved(0x12), None);
+        assert!(cfg.block(0x11).unwrap().dynamic);
+    }
+
+    #[test]
+    fn heights() {
+        // the JUMPDEST is reached with nothing or with 1 on the stack
+        let hex = hex::decode("3460065760015b00").unwrap();
+        let code = to_mnemonics(&hex);
+        let analysis = Cfg::new(&code).analyze_stack(&code);
+        assert_eq!(analysis.heights[&6], BTreeSet::from([0, 1]));
+        assert_eq!(analysis.inconsistent(), vec![6]);
+
+        // add with a single item
+        let hex = hex::decode("60010100").unwrap();
+        let code = to_mnemonics(&hex);
+        let analysis = Cfg::new(&code).analyze_stack(&code);
+        assert_eq!(analysis.underflows, BTreeSet::from([2]));
+    }
+
+    #[test]
+    fn weth() {
+        let bytecode = get_artifacts_code("test-data/WETH9.asm").unwrap();
+        let code = to_mnemonics(&bytecode);
+        let mut cfg = Cfg::new(&code);
+
+        // calldatasize < 4 jumps to the fallback
+        let entry = cfg.block(0).unwrap();
+        assert_eq!(entry.range.len(), 8);
+        assert_eq!(cfg.successors(0).len(), 2);
+        assert!(cfg.successors(0).contains(&0x92));
+        // internal functions return with a jump to a target pushed by the caller
+        assert!(cfg.unresolved().count() > 0);
+        let analysis = cfg.analyze_stack(&code);
+        assert!(analysis.complete);
+        assert!(analysis.underflows.is_empty());
+        assert_eq!(cfg.unresolved().count(), 0);
+    }
+}
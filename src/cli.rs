@@ -0,0 +1,636 @@
+use crate::{
+    analysis::{get_dispatcher, get_jumpdest, get_selectors},
+    asm::disassemble,
+    bytecode::{split_deploy, split_metadata, to_mnemonics_for, Metadata, Mnemonics},
+    cfg::Cfg,
+    fsm::{gen_graph, to_dot, Machine},
+    opcodes::Revision,
+    prover::{Prover, Tree},
+    utils::get_artifacts_code,
+};
+use ethabi::Contract;
+use eyre::{bail, eyre};
+use serde_json::{json, Value};
+use std::fmt::Write;
+use z3::{Config, Context};
+
+pub const USAGE: &str = "usage: statify <command> <file> [options]
+
+commands:
+    analyze     summary of the contract
+    disasm      instructions of the runtime code
+    selectors   function selectors of the dispatcher, with their entry points
+    cfg         basic blocks of the code and the jumps between them
+    paths       explored paths, with concrete inputs for each, or their graph as dot
+    fsm         state machine of the callable functions
+
+<file> holds the runtime code as hex, raw bytes or solc combined-json,
+its metadata trailer is left out of the analysis
+
+options:
+    --abi <path>            abi json of the contract
+    --contract <name>       contract to pick from a combined-json
+    --format <format>       text, json or dot
+    --max-states <n>        most states of the state machine, 16 by default
+    --evm <version>         instruction set of the code, from frontier to cancun (default)
+    --gas-bound <n>         flag the paths which can use more than n gas
+    --max-unroll <n>        most iterations of a loop on a path, 4 by default
+    --creation              <file> holds the creation code, its runtime code is analysed
+    --constructor           analyse the constructor of the creation code instead";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Analyze,
+    Disasm,
+    Selectors,
+    Cfg,
+    Paths,
+    Fsm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Dot,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Args {
+    pub command: Command,
+    pub file: String,
+    pub abi: Option<String>,
+    pub contract: Option<String>,
+    pub format: Format,
+    pub max_states: usize,
+    /// the file holds creation code
+    pub creation: bool,
+    /// analyse the constructor rather than the runtime code
+    pub constructor: bool,
+    /// hard fork the code is decoded for
+    pub revision: Revision,
+    /// paths using more gas are reported
+    pub gas_bound: Option<u64>,
+    /// most jumps of a path to the same destination, the prover's bound if unset
+    pub max_unroll: Option<usize>,
+}
+
+impl Args {
+    /// parse the arguments, without the name of the binary
+    pub fn parse(args: &[String]) -> eyre::Result<Self> {
+        let mut args = args.iter();
+
+        let command = match args.next().map(|arg| arg.as_str()) {
+            Some("analyze") => Command::Analyze,
+            Some("disasm") => Command::Disasm,
+            Some("selectors") => Command::Selectors,
+            Some("cfg") => Command::Cfg,
+            Some("paths") => Command::Paths,
+            Some("fsm") => Command::Fsm,
+            Some(command) => bail!("unknown command {command}"),
+            None => bail!("missing command"),
+        };
+
+        let mut parsed = Self {
+            command,
+            file: String::new(),
+            abi: None,
+            contract: None,
+            format: Format::Text,
+            max_states: 16,
+            creation: false,
+            constructor: false,
+            revision: Revision::default(),
+            gas_bound: None,
+            max_unroll: None,
+        };
+        let mut file = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| eyre!("missing value for {arg}"));
+            match arg.as_str() {
+                "--abi" => parsed.abi = Some(value()?.clone()),
+                "--contract" => parsed.contract = Some(value()?.clone()),
+                "--format" => {
+                    parsed.format = match value()?.as_str() {
+                        "text" => Format::Text,
+                        "json" => Format::Json,
+                        "dot" => Format::Dot,
+                        format => bail!("unknown format {format}"),
+                    }
+                }
+                "--max-states" => parsed.max_states = value()?.parse()?,
+                "--gas-bound" => parsed.gas_bound = Some(value()?.parse()?),
+                "--max-unroll" => parsed.max_unroll = Some(value()?.parse()?),
+                "--creation" => parsed.creation = true,
+                "--constructor" => (parsed.creation, parsed.constructor) = (true, true),
+                "--evm" => {
+                    let name = value()?;
+                    parsed.revision =
+                        Revision::from_name(name).ok_or_else(|| eyre!("unknown evm {name}"))?;
+                }
+                _ if arg.starts_with("--") => bail!("unknown option {arg}"),
+                _ if file.is_none() => file = Some(arg.clone()),
+                _ => bail!("unexpected argument {arg}"),
+            }
+        }
+
+        parsed.file = file.ok_or_else(|| eyre!("missing file"))?;
+        if parsed.format == Format::Dot
+            && !matches!(command, Command::Cfg | Command::Paths | Command::Fsm)
+        {
+            bail!("dot output is only available for cfg, paths and fsm");
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// Runtime code and abi of the analysed contract
+pub struct Input {
+    /// the analysed code, without its metadata
+    pub code: Vec<u8>,
+    pub abi: Contract,
+    pub metadata: Option<Metadata>,
+}
+
+impl Input {
+    pub fn load(args: &Args) -> eyre::Result<Self> {
+        let bytes = std::fs::read(&args.file)?;
+
+        let (code, abi) = match serde_json::from_slice::<Value>(&bytes) {
+            Ok(json) if json.is_object() => {
+                combined_json(&json, args.contract.as_deref(), args.creation)?
+            }
+            // hex if it can be decoded, else raw bytes
+            _ => match get_artifacts_code(&args.file) {
+                Ok(code) => (code, None),
+                Err(_) => (bytes, None),
+            },
+        };
+
+        let abi = match &args.abi {
+            Some(path) => Some(Contract::load(std::fs::File::open(path)?)?),
+            None => abi,
+        };
+
+        let code = match args.creation {
+            true => {
+                let deployment = split_deploy(&code)
+                    .ok_or_else(|| eyre!("no runtime code returned by the constructor"))?;
+                match args.constructor {
+                    true => deployment.constructor.to_vec(),
+                    false => deployment.runtime.to_vec(),
+                }
+            }
+            false => code,
+        };
+        let (code, metadata) = split_metadata(&code);
+
+        Ok(Self {
+            code: code.to_vec(),
+            abi: abi.unwrap_or_default(),
+            metadata,
+        })
+    }
+}
+
+/// runtime or creation code and abi of a contract of a solc combined-json output
+fn combined_json(
+    json: &Value,
+    contract: Option<&str>,
+    creation: bool,
+) -> eyre::Result<(Vec<u8>, Option<Contract>)> {
+    let key = if creation { "bin" } else { "bin-runtime" };
+    let contracts = json["contracts"]
+        .as_object()
+        .ok_or_else(|| eyre!("not a combined-json, no contracts"))?;
+
+    let (name, artifact) = contracts
+        .iter()
+        .find(|(name, artifact)| match contract {
+            Some(contract) => name.as_str() == contract || name.ends_with(&format!(":{contract}")),
+            None => artifact[key].as_str().map_or(false, |bin| !bin.is_empty()),
+        })
+        .ok_or_else(|| eyre!("no contract with a {key} code found"))?;
+
+    let bin = artifact[key]
+        .as_str()
+        .ok_or_else(|| eyre!("no {key} for {name}"))?;
+    let code = hex::decode(bin.trim_start_matches("0x"))?;
+
+    // older solc versions store the abi as a string
+    let abi = match &artifact["abi"] {
+        Value::String(abi) => Some(Contract::load(abi.as_bytes())?),
+        Value::Array(_) => Some(Contract::load(artifact["abi"].to_string().as_bytes())?),
+        _ => None,
+    };
+
+    Ok((code, abi))
+}
+
+pub fn run(args: &Args) -> eyre::Result<String> {
+    let input = Input::load(args)?;
+    let code = to_mnemonics_for(&input.code, args.revision);
+
+    match args.command {
+        Command::Analyze => analyze(args, &input, &code),
+        Command::Disasm => Ok(disasm(args, &input, &code)),
+        Command::Selectors => Ok(selectors(args, &input, &code)),
+        Command::Cfg => Ok(cfg(args, &code)),
+        Command::Paths => paths(args, &input, &code),
+        Command::Fsm => Ok(fsm(args, &input, &code)),
+    }
+}
+
+/// selectors of the abi functions, or the ones found in the code
+fn find_selectors(input: &Input, code: &Mnemonics) -> Vec<u32> {
+    let mut selectors: Vec<_> = match input.abi.functions().next() {
+        Some(_) => input
+            .abi
+            .functions()
+            .map(|function| u32::from_be_bytes(function.short_signature()))
+            .collect(),
+        None => get_selectors(code),
+    };
+    selectors.sort_unstable();
+    selectors.dedup();
+    selectors
+}
+
+fn outcome(tree: &Tree, pid: usize) -> &'static str {
+    match tree[&pid].1.last().map(|step| step.ret()) {
+        Some(ret) if ret.is_success() => "return",
+        Some(ret) if ret.has_ret() => "revert",
+        Some(ret) if ret.unsupported().is_some() => "unsupported",
+        _ => "open",
+    }
+}
+
+/// whether the path left out jumps past the unroll bound
+fn bounded(tree: &Tree, pid: usize) -> bool {
+    tree[&pid].1.last().is_some_and(|step| step.ret().bounded())
+}
+
+/// a prover for `code`, with the bounds of the arguments
+fn prover<'a, 'ctx: 'a>(
+    ctx: &'ctx Context,
+    args: &Args,
+    code: &'a Mnemonics,
+    abi: Contract,
+) -> Prover<'a, 'ctx> {
+    let mut prover = Prover::new(ctx, code, abi);
+    if let Some(max_unroll) = args.max_unroll {
+        prover.set_max_unroll(max_unroll);
+    }
+    prover
+}
+
+fn analyze(args: &Args, input: &Input, code: &Mnemonics) -> eyre::Result<String> {
+    let cfg = Config::default();
+    let ctx = Context::new(&cfg);
+    let prover = prover(&ctx, args, code, Contract::default());
+    let tree = prover
+        .run()
+        .map_err(|reason| eyre!("execution failed: {reason:?}"))?;
+
+    let count = |kind| {
+        tree.keys()
+            .filter(|pid| outcome(&tree, **pid) == kind)
+            .count()
+    };
+    let mut blocks = Cfg::new(code);
+    let stack = blocks.analyze_stack(code);
+    let summary = json!({
+        "size": input.code.len(),
+        "instructions": code.len(),
+        "jumpdests": get_jumpdest(code.to_vec()).len(),
+        "blocks": blocks.graph.node_count(),
+        "unreachable": blocks.unreachable().len(),
+        "dynamic_jumps": blocks.unresolved().count(),
+        "underflows": stack.underflows.len(),
+        "selectors": find_selectors(input, code).len(),
+        "solc": input.metadata.as_ref().and_then(|metadata| metadata.solc.clone()),
+        "paths": tree.len(),
+        "returns": count("return"),
+        "reverts": count("revert"),
+        "unsupported": count("unsupported"),
+        "bounded": tree.keys().filter(|pid| bounded(&tree, **pid)).count(),
+    });
+
+    Ok(match args.format {
+        Format::Json => summary.to_string(),
+        _ => summary
+            .as_object()
+            .unwrap()
+            .iter()
+            .map(|(key, value)| format!("{key}: {value}"))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    })
+}
+
+fn disasm(args: &Args, input: &Input, code: &Mnemonics) -> String {
+    match args.format {
+        Format::Json => {
+            let instructions: Vec<_> = code
+                .iter()
+                .map(|mn| {
+                    json!({
+                        "pc": mn.pc,
+                        "op": mn.op.name(),
+                        "push": (!mn.pushes.is_empty()).then(|| format!("0x{}", hex::encode(mn.pushes))),
+                    })
+                })
+                .collect();
+            json!(instructions).to_string()
+        }
+        _ => disassemble(code, &input.abi),
+    }
+}
+
+fn selectors(args: &Args, input: &Input, code: &Mnemonics) -> String {
+    let dispatcher = get_dispatcher(code);
+    let named: Vec<_> = find_selectors(input, code)
+        .into_iter()
+        .map(|selector| {
+            let signature = input
+                .abi
+                .functions()
+                .find(|function| function.short_signature() == selector.to_be_bytes())
+                .map(|function| function.signature());
+            let entry = dispatcher.functions.get(&selector).copied();
+            (selector, signature, entry)
+        })
+        .collect();
+
+    match args.format {
+        Format::Json => {
+            let selectors: Vec<_> = named
+                .iter()
+                .map(|(selector, signature, entry)| {
+                    json!({
+                        "selector": format!("{selector:#010x}"),
+                        "signature": signature,
+                        "entry": entry,
+                    })
+                })
+                .collect();
+            json!({
+                "functions": selectors,
+                "fallback": dispatcher.fallback,
+                "receive": dispatcher.receive,
+            })
+            .to_string()
+        }
+        _ => {
+            let mut lines: Vec<_> = named
+                .iter()
+                .map(|(selector, signature, entry)| {
+                    let mut line = format!("{selector:#010x}");
+                    if let Some(entry) = entry {
+                        let _ = write!(line, " @ {entry:#06x}");
+                    }
+                    if let Some(signature) = signature {
+                        let _ = write!(line, " {signature}");
+                    }
+                    line
+                })
+                .collect();
+            if let Some(fallback) = dispatcher.fallback {
+                lines.push(format!("fallback @ {fallback:#06x}"));
+            }
+            if let Some(receive) = dispatcher.receive {
+                lines.push(format!("receive @ {receive:#06x}"));
+            }
+            lines.join("\n")
+        }
+    }
+}
+
+fn cfg(args: &Args, code: &Mnemonics) -> String {
+    let mut cfg = Cfg::new(code);
+    let stack = cfg.analyze_stack(code);
+
+    match args.format {
+        Format::Json => cfg.to_json().to_string(),
+        Format::Dot => cfg.to_dot(),
+        Format::Text => cfg
+            .blocks()
+            .map(|block| {
+                let successors: Vec<_> = cfg
+                    .successors(block.start)
+                    .iter()
+                    .map(|start| format!("{start:#x}"))
+                    .collect();
+                let heights: Vec<_> = stack
+                    .heights
+                    .get(&block.start)
+                    .into_iter()
+                    .flatten()
+                    .map(|height| height.to_string())
+                    .collect();
+                format!(
+                    "{block} -> [{}] height [{}]",
+                    successors.join(", "),
+                    heights.join(", ")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+fn paths(args: &Args, input: &Input, code: &Mnemonics) -> eyre::Result<String> {
+    let cfg = Config::default();
+    let ctx = Context::new(&cfg);
+    let prover = prover(&ctx, args, code, input.abi.clone());
+    let failed = |reason| eyre!("execution failed: {reason:?}");
+
+    // one tree per abi function, or a single one with unconstrained calldata
+    let trees = match input.abi.functions().next() {
+        Some(_) => prover
+            .run_abi()
+            .map_err(failed)?
+            .into_iter()
+            .map(|(call, tree)| (Some(call), tree))
+            .collect(),
+        None => vec![(None, prover.run().map_err(failed)?)],
+    };
+
+    if args.format == Format::Dot {
+        let graphs: Vec<_> = trees
+            .iter()
+            .map(|(_, tree)| to_dot(&gen_graph(tree)))
+            .collect();
+        return Ok(graphs.join("\n"));
+    }
+
+    let mut paths = Vec::new();
+    for (call, tree) in &trees {
+        for pid in tree.keys() {
+            let witness = prover.witness(tree, *pid, call.as_ref());
+            let exceeds = args
+                .gas_bound
+                .map(|bound| prover.exceeds_gas(tree, *pid, bound));
+            paths.push((
+                call.as_ref().map(|call| call.function.signature()),
+                *pid,
+                outcome(tree, *pid),
+                bounded(tree, *pid),
+                witness,
+                exceeds,
+            ));
+        }
+    }
+
+    Ok(match args.format {
+        Format::Json => {
+            let paths: Vec<_> = paths
+                .iter()
+                .map(|(function, pid, outcome, bounded, witness, exceeds)| {
+                    json!({
+                        "function": function,
+                        "id": pid,
+                        "outcome": outcome,
+                        "bounded": bounded,
+                        "witness": witness.as_ref().map(|witness| witness.to_json()),
+                        "exceeds_gas": exceeds,
+                    })
+                })
+                .collect();
+            json!(paths).to_string()
+        }
+        _ => paths.iter().fold(
+            String::new(),
+            |mut out, (function, pid, outcome, bounded, witness, exceeds)| {
+                let _ = write!(out, "#{pid} {outcome}");
+                if let Some(function) = function {
+                    let _ = write!(out, " in {function}");
+                }
+                if let (Some(true), Some(bound)) = (exceeds, args.gas_bound) {
+                    let _ = write!(out, ", over {bound} gas");
+                }
+                if *bounded {
+                    let _ = write!(out, ", loops cut at the unroll bound");
+                }
+                out.push('\n');
+                if let Some(witness) = witness {
+                    for line in witness.to_string().lines() {
+                        let _ = writeln!(out, "    {line}");
+                    }
+                }
+                out
+            },
+        ),
+    })
+}
+
+fn fsm(args: &Args, input: &Input, code: &Mnemonics) -> String {
+    let selectors = find_selectors(input, code);
+    let machine = Machine::extract(code, &selectors, args.max_states, args.max_unroll);
+
+    match args.format {
+        Format::Json => machine.to_json().to_string(),
+        Format::Dot => machine.to_dot(),
+        Format::Text => {
+            let mut out = String::new();
+            for (id, storage) in machine.states.iter().enumerate() {
+                let _ = writeln!(out, "S{id}: {} slots set", storage.len());
+                for t in machine.transitions.iter().filter(|t| t.from == id) {
+                    let _ = writeln!(out, "    {:#010x} -> S{}", t.selector, t.to);
+                }
+            }
+            for (from, selector, reason) in &machine.failures {
+                let _ = writeln!(out, "S{from}: {selector:#010x} failed, {reason:?}");
+            }
+            if machine.truncated {
+                let _ = writeln!(out, "truncated: some states or paths were left out");
+            }
+            out
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> eyre::Result<Args> {
+        let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+        Args::parse(&args)
+    }
+
+    #[test]
+    fn parse() {
+        let parsed = args(&["cfg", "test-data/WETH9.asm", "--format", "dot"]).unwrap();
+        assert_eq!(parsed.command, Command::Cfg);
+        assert_eq!(parsed.file, "test-data/WETH9.asm");
+        assert_eq!(parsed.format, Format::Dot);
+        assert_eq!(parsed.abi, None);
+
+        assert!(args(&["disasm"]).is_err());
+        assert!(args(&["decompile", "code.hex"]).is_err());
+        assert!(args(&["selectors", "code.hex", "--format", "dot"]).is_err());
+        assert!(args(&["fsm", "code.hex", "--max-states"]).is_err());
+
+        let parsed = args(&["disasm", "code.hex", "--constructor"]).unwrap();
+        assert!(parsed.creation && parsed.constructor);
+        assert_eq!(parsed.revision, Revision::Cancun);
+
+        let parsed = args(&["disasm", "code.hex", "--evm", "london"]).unwrap();
+        assert_eq!(parsed.revision, Revision::London);
+        assert!(args(&["disasm", "code.hex", "--evm", "prague"]).is_err());
+
+        let parsed = args(&["paths", "code.hex", "--gas-bound", "30000"]).unwrap();
+        assert_eq!(parsed.gas_bound, Some(30000));
+        assert!(args(&["paths", "code.hex", "--gas-bound", "lots"]).is_err());
+
+        let parsed = args(&["paths", "code.hex", "--max-unroll", "8"]).unwrap();
+        assert_eq!(parsed.max_unroll, Some(8));
+    }
+
+    #[test]
+    fn weth_selectors() {
+        let parsed = args(&["selectors", "test-data/WETH9.asm", "--format", "json"]).unwrap();
+        let out: Value = serde_json::from_str(&run(&parsed).unwrap()).unwrap();
+        let selectors = out["functions"].as_array().unwrap();
+        assert!(selectors
+            .iter()
+            .any(|selector| selector["selector"] == "0xd0e30db0" && selector["entry"] == 0x92));
+        assert_eq!(selectors.len(), 11);
+        assert_eq!(out["fallback"], 0x92);
+    }
+
+    #[test]
+    fn weth_cfg() {
+        let parsed = args(&["cfg", "test-data/WETH9.asm", "--format", "json"]).unwrap();
+        let out: Value = serde_json::from_str(&run(&parsed).unwrap()).unwrap();
+        assert_eq!(out["blocks"][0]["start"], 0);
+        assert!(out["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|edge| edge["from"] == 0 && edge["to"] == 0x92 && edge["kind"] == "jump"));
+    }
+
+    #[test]
+    fn combined() {
+        let json = json!({
+            "contracts": {
+                "A.sol:A": { "bin-runtime": "", "abi": [] },
+                "A.sol:B": {
+                    "bin-runtime": "5f5ff3",
+                    "abi": r#"[{"type":"function","name":"f","stateMutability":"nonpayable","outputs":[],"inputs":[]}]"#,
+                },
+            }
+        });
+
+        let (code, abi) = combined_json(&json, None, false).unwrap();
+        assert_eq!(code, vec![0x5f, 0x5f, 0xf3]);
+        assert!(abi.unwrap().function("f").is_ok());
+        assert!(combined_json(&json, Some("A"), false).unwrap().0.is_empty());
+        assert!(combined_json(&json, Some("C"), false).is_err());
+        assert!(combined_json(&json, None, true).is_err());
+    }
+}
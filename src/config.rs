@@ -1,6 +1,64 @@
-use crate::helpers::U256;
+use crate::helpers::{Address, Env, Word, U256};
+use std::collections::BTreeMap;
 
-/// Wether or not env variables should be symbolic
+/// Block and transaction environment of the execution, every variable left unset is symbolic
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Config {
-    number: Option<U256>,
+    pub caller: Option<Address>,
+    /// accounts the caller is picked from, any account if empty
+    pub actors: Vec<Address>,
+    pub value: Option<U256>,
+    /// the value can't exceed the balance of the caller, when it is known
+    pub value_in_balance: bool,
+    pub coinbase: Option<Address>,
+    pub timestamp: Option<u64>,
+    pub number: Option<u64>,
+    pub prevrandao: Option<U256>,
+    pub gas_limit: Option<u64>,
+    pub chain_id: Option<u64>,
+    pub base_fee: Option<U256>,
+    pub blob_base_fee: Option<U256>,
+    /// hashes of the most recent blocks by number, the other ones are symbolic
+    pub block_hashes: BTreeMap<u64, Word>,
+    /// lower bound of a symbolic timestamp, the one of a previous transaction
+    pub min_timestamp: u64,
+    /// lower bound of a symbolic block number
+    pub min_number: u64,
+}
+
+impl Config {
+    /// every variable is the one of `env`
+    pub fn from_env(env: &Env) -> Self {
+        Self {
+            caller: Some(env.caller),
+            value: Some(env.value),
+            coinbase: Some(env.coinbase),
+            timestamp: Some(env.timestamp),
+            number: Some(env.number),
+            prevrandao: Some(env.difficulty),
+            gas_limit: Some(env.gas_limit),
+            chain_id: Some(env.chain_id),
+            base_fee: Some(env.base_fee),
+            blob_base_fee: Some(env.blob_base_fee),
+            block_hashes: env.block_hashes.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// environment of a later transaction, sent by the same actors: it runs on the same chain,
+    /// in the same block or a following one, so the timestamp and the number can only grow
+    pub fn next(&self) -> Self {
+        Self {
+            value: None,
+            coinbase: None,
+            timestamp: None,
+            number: None,
+            prevrandao: None,
+            base_fee: None,
+            blob_base_fee: None,
+            min_timestamp: self.timestamp.unwrap_or(self.min_timestamp),
+            min_number: self.number.unwrap_or(self.min_number),
+            ..self.clone()
+        }
+    }
 }
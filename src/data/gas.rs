@@ -0,0 +1,107 @@
+use crate::{
+    data::{EVMMemory, MAX_MEMORY},
+    opcodes::GasCosts,
+};
+use z3::{ast::Ast, Context};
+
+/// Gas used by a frame, with the accounts and storage slots it has accessed (EIP-2929)
+#[derive(Debug, Clone)]
+pub struct EVMGas<'ctx> {
+    ctx: &'ctx Context,
+    /// gas used so far, without the expansion of the memory
+    used: z3::ast::BV<'ctx>,
+    accounts: Vec<z3::ast::BV<'ctx>>,
+    slots: Vec<z3::ast::BV<'ctx>>,
+}
+
+impl<'ctx> EVMGas<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> Self {
+        Self {
+            ctx,
+            used: z3::ast::BV::from_u64(ctx, 0, 256),
+            accounts: Vec::new(),
+            slots: Vec::new(),
+        }
+    }
+
+    pub fn charge(&mut self, gas: u64) {
+        if gas > 0 {
+            self.charge_bv(&z3::ast::BV::from_u64(self.ctx, gas, 256));
+        }
+    }
+
+    pub fn charge_bv(&mut self, gas: &z3::ast::BV<'ctx>) {
+        self.used = self.used.bvadd(gas).simplify();
+    }
+
+    /// charge `gas` for each 32 bytes word of `size` bytes, the last one being partial
+    pub fn charge_words(&mut self, gas: u64, size: &z3::ast::BV<'ctx>) {
+        if gas == 0 {
+            return;
+        }
+        let ctx = self.ctx;
+        let words = size
+            .bvadd(&z3::ast::BV::from_u64(ctx, 31, 256))
+            .bvudiv(&z3::ast::BV::from_u64(ctx, 32, 256));
+        let cost = words.bvmul(&z3::ast::BV::from_u64(ctx, gas, 256));
+        let cost = Self::out_of_gas_above(ctx, size, &cost);
+        self.charge_bv(&cost);
+    }
+
+    /// charge `gas` if `cond` holds
+    pub fn charge_if(&mut self, cond: &z3::ast::Bool<'ctx>, gas: u64) {
+        let zero = z3::ast::BV::from_u64(self.ctx, 0, 256);
+        let gas = z3::ast::BV::from_u64(self.ctx, gas, 256);
+        self.charge_bv(&cond.ite(&gas, &zero));
+    }
+
+    /// the account is warm from now on, e.g. the sender and the recipient of the transaction
+    pub fn warm_account(&mut self, address: &z3::ast::BV<'ctx>) {
+        self.access_account(address);
+    }
+
+    /// whether `address` is accessed for the first time, it is warm afterwards
+    pub fn access_account(&mut self, address: &z3::ast::BV<'ctx>) -> z3::ast::Bool<'ctx> {
+        let address = address.extract(159, 0).simplify();
+        Self::access(self.ctx, &mut self.accounts, address)
+    }
+
+    /// whether the storage slot `key` is accessed for the first time, it is warm afterwards
+    pub fn access_slot(&mut self, key: &z3::ast::BV<'ctx>) -> z3::ast::Bool<'ctx> {
+        Self::access(self.ctx, &mut self.slots, key.simplify())
+    }
+
+    /// gas used so far, including the expansion of `memory`
+    pub fn used(&self, memory: &EVMMemory<'ctx>, costs: &GasCosts) -> z3::ast::BV<'ctx> {
+        self.used.bvadd(&memory.cost(costs)).simplify()
+    }
+
+    /// `cost`, or more gas than any block holds if `size` exceeds `MAX_MEMORY`, so that the
+    /// computation of `cost` can't overflow
+    pub fn out_of_gas_above(
+        ctx: &'ctx Context,
+        size: &z3::ast::BV<'ctx>,
+        cost: &z3::ast::BV<'ctx>,
+    ) -> z3::ast::BV<'ctx> {
+        let max = z3::ast::BV::from_u64(ctx, u64::MAX, 256);
+        let big = size.bvugt(&z3::ast::BV::from_u64(ctx, MAX_MEMORY.into(), 256));
+        big.ite(&max, cost)
+    }
+
+    fn access(
+        ctx: &'ctx Context,
+        accessed: &mut Vec<z3::ast::BV<'ctx>>,
+        item: z3::ast::BV<'ctx>,
+    ) -> z3::ast::Bool<'ctx> {
+        if accessed.contains(&item) {
+            return z3::ast::Bool::from_bool(ctx, false);
+        }
+
+        let cold = accessed
+            .iter()
+            .map(|known| known._eq(&item).not())
+            .collect::<Vec<_>>();
+        accessed.push(item);
+        z3::ast::Bool::and(ctx, &cold.iter().collect::<Vec<_>>()).simplify()
+    }
+}
@@ -0,0 +1,168 @@
+use crate::helpers::{bv_to_bytes, bv_to_word, keccak256, to_bv};
+use z3::{ast::Ast, Context, Model, Sort};
+
+/// Hashes below this bound are assumed not to happen, so that a hashed storage slot never
+/// collides with the plain ones a contract declares
+const MIN_HASH: u64 = 1 << 32;
+
+/// Keccak256 applications of a path, with their inputs. A concrete input is hashed for real,
+/// a symbolic one through a `keccak256` function per input width.
+#[derive(Debug, Clone)]
+pub struct EVMKeccak<'ctx> {
+    ctx: &'ctx Context,
+    /// (input, hash) of every application so far
+    hashes: Vec<(z3::ast::BV<'ctx>, z3::ast::BV<'ctx>)>,
+}
+
+impl<'ctx> EVMKeccak<'ctx> {
+    pub fn new(ctx: &'ctx Context) -> Self {
+        Self {
+            ctx,
+            hashes: Vec::new(),
+        }
+    }
+
+    /// hash of the bytes of `input`, with the axioms relating it to the previous hashes
+    pub fn hash(
+        &mut self,
+        input: &z3::ast::BV<'ctx>,
+    ) -> (z3::ast::BV<'ctx>, Vec<z3::ast::Bool<'ctx>>) {
+        let input = input.simplify();
+        if let Some((_, hash)) = self.hashes.iter().find(|(known, _)| known == &input) {
+            return (hash.clone(), Vec::new());
+        }
+
+        let bytes = bv_to_bytes(&input);
+        let hash = match &bytes {
+            Some(bytes) => to_bv(self.ctx, &keccak256(bytes)),
+            None => {
+                let keccak = z3::FuncDecl::new(
+                    self.ctx,
+                    "keccak256",
+                    &[&Sort::bitvector(self.ctx, input.get_size())],
+                    &Sort::bitvector(self.ctx, 256),
+                );
+                keccak.apply(&[&input]).as_bv().unwrap()
+            }
+        };
+
+        let mut axioms = self.axioms(&input, &hash);
+        if bytes.is_none() {
+            let min = z3::ast::BV::from_u64(self.ctx, MIN_HASH, 256);
+            axioms.push(hash.bvuge(&min));
+        }
+        self.hashes.push((input, hash.clone()));
+
+        (hash, axioms)
+    }
+
+    /// take in the hashes of a nested frame, with the axioms relating them to the known ones
+    pub fn absorb(&mut self, other: &EVMKeccak<'ctx>) -> Vec<z3::ast::Bool<'ctx>> {
+        let mut axioms = Vec::new();
+        for (input, hash) in &other.hashes {
+            if self.hashes.iter().any(|(known, _)| known == input) {
+                continue;
+            }
+            axioms.extend(self.axioms(input, hash));
+            self.hashes.push((input.clone(), hash.clone()));
+        }
+
+        axioms
+    }
+
+    /// constraints ruling out the hashes of `model` which are not the real ones.
+    /// Empty if the model agrees with keccak256 on every application.
+    pub fn reconcile(&self, model: &Model<'ctx>) -> Vec<z3::ast::Bool<'ctx>> {
+        self.hashes
+            .iter()
+            .filter_map(|(input, hash)| {
+                let value = model.eval(input, true)?;
+                let real = keccak256(&bv_to_bytes(&value)?);
+                if bv_to_word(&model.eval(hash, true)?)? == real {
+                    return None;
+                }
+                Some(
+                    input
+                        ._eq(&value)
+                        .implies(&hash._eq(&to_bv(self.ctx, &real))),
+                )
+            })
+            .collect()
+    }
+
+    /// hashes of the same width are equal only for equal inputs, and never for different widths
+    fn axioms(
+        &self,
+        input: &z3::ast::BV<'ctx>,
+        hash: &z3::ast::BV<'ctx>,
+    ) -> Vec<z3::ast::Bool<'ctx>> {
+        self.hashes
+            .iter()
+            .map(|(known, known_hash)| {
+                let same_hash = hash._eq(known_hash);
+                if known.get_size() == input.get_size() {
+                    input._eq(known)._eq(&same_hash)
+                } else {
+                    same_hash.not()
+                }
+            })
+            .map(|axiom| axiom.simplify())
+            .filter(|axiom| axiom.as_bool() != Some(true))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use z3::{Config, SatResult, Solver};
+
+    #[test]
+    fn keccak() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let sol = Solver::new(&ctx);
+        let mut keccak = EVMKeccak::new(&ctx);
+
+        let zero = to_bv(&ctx, &[0]);
+        let (zero_hash, _) = keccak.hash(&zero);
+        assert_eq!(
+            bv_to_word(&zero_hash).map(hex::encode).as_deref(),
+            Some("290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563")
+        );
+
+        let a = z3::ast::BV::new_const(&ctx, "a", 256);
+        let b = z3::ast::BV::new_const(&ctx, "b", 256);
+        let c = z3::ast::BV::new_const(&ctx, "c", 512);
+        let mut hash = |input| {
+            let (hash, axioms) = keccak.hash(input);
+            axioms.iter().for_each(|axiom| sol.assert(axiom));
+            hash
+        };
+        let (hash_a, hash_b, hash_c) = (hash(&a), hash(&b), hash(&c));
+
+        // distinct inputs have distinct hashes, whatever their width
+        sol.push();
+        sol.assert(&z3::ast::Bool::or(
+            &ctx,
+            &[
+                &z3::ast::Bool::and(&ctx, &[&hash_a._eq(&hash_b), &a._eq(&b).not()]),
+                &hash_a._eq(&hash_c),
+                &z3::ast::Bool::and(&ctx, &[&hash_a._eq(&zero_hash), &a._eq(&zero).not()]),
+            ],
+        ));
+        assert_eq!(sol.check(), SatResult::Unsat);
+        sol.pop(1);
+
+        // a model is brought back to the real hashes
+        sol.assert(&a._eq(&to_bv(&ctx, &[1])));
+        sol.assert(&b._eq(&to_bv(&ctx, &[2])));
+        sol.assert(&c._eq(&to_bv(&ctx, &[3]).zero_ext(256)));
+        assert_eq!(sol.check(), SatResult::Sat);
+        let fixes = keccak.reconcile(&sol.get_model().unwrap());
+        assert!(!fixes.is_empty());
+        fixes.iter().for_each(|fix| sol.assert(fix));
+        assert_eq!(sol.check(), SatResult::Sat);
+        assert!(keccak.reconcile(&sol.get_model().unwrap()).is_empty());
+    }
+}
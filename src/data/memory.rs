@@ -1,6 +1,25 @@
+use crate::{data::EVMGas, opcodes::GasCosts};
 use std::ops::Range;
-use z3::{ast::Ast, Context};
+use z3::{ast::Ast, Context, Sort};
 
+/// Above this many bytes the concatenated memory is turned into an array, so that a large
+/// concrete offset doesn't build a bitvector of its size
+const MAX_CONCAT_MEMORY: u32 = 4096;
+
+/// Bytes of memory whose expansion costs more gas than any block holds
+pub const MAX_MEMORY: u32 = 1 << 25;
+
+/// Representation of the memory of a frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryModel {
+    /// the bytes concatenated in a bitvector, it becomes an array at the first symbolic offset
+    Concat,
+    /// an array of bytes, indexed by symbolic offsets
+    #[default]
+    Array,
+}
+
+/// Bytes of the memory concatenated in a single bitvector, the first one in the highest bits
 #[derive(Debug, Default, Clone)]
 pub struct Memory<'ctx> {
     data: Option<z3::ast::BV<'ctx>>,
@@ -11,118 +30,327 @@ impl<'ctx> Memory<'ctx> {
         Default::default()
     }
 
-    /// set a vec of words in the memory at offset
-    pub fn set(&mut self, offset: u32, words: z3::ast::BV<'ctx>) {
-        let data = if let Some(data) = &self.data {
-            let size = data.get_size();
-            let wsize = words.get_size();
-            dbg!(&offset, &size, &wsize, &words);
-            if offset > size {
-                let low_data = data.zero_ext(offset - size);
-                low_data.concat(&words)
-            } else if offset + wsize > size {
-                let low_data = data.extract(offset - 1, 0);
-                low_data.concat(&words)
-            } else if offset > 0 {
-                let low_data = data.extract(offset - 1, 0);
-                // we cut the legs of data so we must sub off - 1
-                // old was "extract(size - 1, wsize + offset)"
-                let up_data = data.extract(size - offset, wsize);
-                low_data.concat(&words.concat(&up_data))
-            } else if offset == 0 {
-                if size > wsize {
-                    // get from the start
-                    let up_data = data.extract(size - 1, wsize);
-                    words.concat(&up_data)
-                } else {
-                    words
-                }
-            } else {
-                // is there other cases ?
-                unreachable!()
-            }
-        } else {
-            words
-        };
+    /// number of bytes held
+    pub fn len(&self) -> u32 {
+        self.data.as_ref().map_or(0, |data| data.get_size() / 8)
+    }
 
-        self.data = Some(data.simplify());
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    /// Get a `BV` representing the data in memory in the range `r`.
-    pub fn get(&mut self, ctx: &'ctx Context, r: Range<u32>) -> z3::ast::BV<'ctx> {
-        let (low, high) = (r.start, r.end);
-        if low == high {
-            return z3::ast::BV::from_u64(ctx, 0, 1);
+    /// append zeros up to `len` bytes, which must fit in a bitvector
+    fn extend(&mut self, ctx: &'ctx Context, len: u32) {
+        let missing = len.saturating_sub(self.len());
+        if missing == 0 {
+            return;
         }
 
-        let data = std::mem::replace(&mut self.data, None)
-            .unwrap_or_else(|| z3::ast::BV::from_u64(ctx, 0, high));
+        assert!(
+            len.checked_mul(8).is_some(),
+            "memory too large for a bitvector"
+        );
+        let zeros = z3::ast::BV::from_u64(ctx, 0, missing * 8);
+        self.data = Some(match self.data.take() {
+            Some(data) => data.concat(&zeros),
+            None => zeros,
+        });
+    }
+
+    /// write the bytes of `value` at `offset`
+    pub fn set(&mut self, ctx: &'ctx Context, offset: u32, value: z3::ast::BV<'ctx>) {
+        let size = value.get_size() / 8;
+        let end = offset.checked_add(size).expect("memory range overflows");
+        self.extend(ctx, end);
 
-        if high > data.get_size() {
-            let extended_data = data.zero_ext(high - data.get_size());
-            self.data.replace(extended_data);
+        let data = self.data.take().expect("the memory was extended");
+        let len = data.get_size() / 8;
+        let mut bytes = value;
+        if offset > 0 {
+            bytes = data.extract(len * 8 - 1, (len - offset) * 8).concat(&bytes);
+        }
+        if end < len {
+            bytes = bytes.concat(&data.extract((len - offset - size) * 8 - 1, 0));
         }
 
-        self.data.get_or_insert(data).extract(high - 1, low)
+        self.data = Some(bytes.simplify());
+    }
+
+    /// bytes in the range `r`, which must not be empty
+    pub fn get(&mut self, ctx: &'ctx Context, r: Range<u32>) -> z3::ast::BV<'ctx> {
+        assert!(r.start < r.end, "empty memory range");
+        self.extend(ctx, r.end);
+
+        let data = self.data.as_ref().expect("the memory was extended");
+        let len = data.get_size() / 8;
+        data.extract((len - r.start) * 8 - 1, (len - r.end) * 8)
+            .simplify()
+    }
+
+    /// the same bytes as an array, the other ones being zero
+    pub fn to_array(&self, ctx: &'ctx Context) -> z3::ast::Array<'ctx> {
+        let zero = z3::ast::BV::from_u64(ctx, 0, 8);
+        let bytes = z3::ast::Array::const_array(ctx, &Sort::bitvector(ctx, 256), &zero);
+
+        let data = match &self.data {
+            Some(data) => data,
+            None => return bytes,
+        };
+        let len = self.len();
+        (0..len).fold(bytes, |bytes, i| {
+            let high = (len - i) * 8 - 1;
+            bytes.store(
+                &z3::ast::BV::from_u64(ctx, i.into(), 256),
+                &data.extract(high, high - 7).simplify(),
+            )
+        })
     }
 }
 
+#[derive(Debug, Clone)]
+enum Backend<'ctx> {
+    Concat(Memory<'ctx>),
+    Array(z3::ast::Array<'ctx>),
+}
+
+/// Memory of a frame, byte addressed
 #[derive(Debug, Clone)]
 pub struct EVMMemory<'ctx> {
     ctx: &'ctx Context,
-    memory: Memory<'ctx>,
+    memory: Backend<'ctx>,
+    /// size in bytes of the memory accessed so far, a multiple of 32
+    msize: z3::ast::BV<'ctx>,
 }
 
 impl<'ctx> EVMMemory<'ctx> {
     pub fn new(ctx: &'ctx Context) -> Self {
+        Self::with_model(ctx, MemoryModel::default())
+    }
+
+    pub fn with_model(ctx: &'ctx Context, model: MemoryModel) -> Self {
+        let memory = match model {
+            MemoryModel::Concat => Backend::Concat(Memory::new()),
+            MemoryModel::Array => Backend::Array(Memory::new().to_array(ctx)),
+        };
+
         Self {
             ctx,
-            memory: Memory::new(),
+            memory,
+            msize: z3::ast::BV::from_u64(ctx, 0, 256),
         }
     }
 
-    pub fn mload(&mut self, off: u32) -> z3::ast::BV<'ctx> {
-        let ret = self.memory.get(self.ctx, off..(off + 256));
-        assert_eq!(ret.get_size(), 256, "mload val len != 256b");
-        ret
+    /// size of the memory, as read by MSIZE
+    pub fn msize(&self) -> &z3::ast::BV<'ctx> {
+        &self.msize
     }
 
-    pub fn mstore(&mut self, offset: u32, value: z3::ast::BV<'ctx>) {
+    pub fn mload(&mut self, off: &z3::ast::BV<'ctx>) -> z3::ast::BV<'ctx> {
+        self.load(off, 32)
+    }
+
+    pub fn mstore(&mut self, off: &z3::ast::BV<'ctx>, value: z3::ast::BV<'ctx>) {
         assert_eq!(value.get_size(), 256);
-        self.memory.set(offset, value);
+        self.store(off, value);
     }
 
-    pub fn mbig_load(&mut self, from: u32, to: u32) -> z3::ast::BV<'ctx> {
-        self.memory.get(self.ctx, from..to)
+    /// store the lowest byte of `value`
+    pub fn mstore8(&mut self, off: &z3::ast::BV<'ctx>, value: &z3::ast::BV<'ctx>) {
+        self.store(off, value.extract(7, 0));
     }
 
-    pub fn mbig_store(&mut self, offset: u32, value: z3::ast::BV<'ctx>) {
-        self.memory.set(offset, value);
+    /// read `size` bytes from `off`, `size` must not be zero
+    pub fn load(&mut self, off: &z3::ast::BV<'ctx>, size: u32) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        self.expand(off, &z3::ast::BV::from_u64(ctx, size.into(), 256));
+
+        match (&mut self.memory, Self::concrete(off, size)) {
+            (Backend::Concat(memory), Some(off)) => memory.get(ctx, off..off + size),
+            _ => {
+                let bytes = self.array();
+                (0..size)
+                    .map(|i| {
+                        let index = off.bvadd(&z3::ast::BV::from_u64(ctx, i.into(), 256));
+                        bytes.select(&index).as_bv().unwrap()
+                    })
+                    .reduce(|bytes, byte| bytes.concat(&byte))
+                    .expect("at least a byte is read")
+                    .simplify()
+            }
+        }
+    }
+
+    /// write the concatenated `bytes` at `off`
+    pub fn store(&mut self, off: &z3::ast::BV<'ctx>, bytes: z3::ast::BV<'ctx>) {
+        let ctx = self.ctx;
+        let size = bytes.get_size() / 8;
+        self.expand(off, &z3::ast::BV::from_u64(ctx, size.into(), 256));
+
+        match (&mut self.memory, Self::concrete(off, size)) {
+            (Backend::Concat(memory), Some(off)) => memory.set(ctx, off, bytes),
+            _ => {
+                let array = (0..size).fold(self.array().clone(), |array, i| {
+                    let index = off.bvadd(&z3::ast::BV::from_u64(ctx, i.into(), 256));
+                    let high = (size - i) * 8 - 1;
+                    array.store(&index, &bytes.extract(high, high - 7).simplify())
+                });
+                self.memory = Backend::Array(array);
+            }
+        }
+    }
+
+    /// write the first `size` of the concatenated `bytes` at `off`, the other ones are left as they were.
+    /// Stores all of them if `size` is `None`.
+    pub fn store_sized(
+        &mut self,
+        off: &z3::ast::BV<'ctx>,
+        bytes: z3::ast::BV<'ctx>,
+        size: Option<&z3::ast::BV<'ctx>>,
+    ) {
+        let size = match size {
+            Some(size) => size,
+            None => return self.store(off, bytes),
+        };
+
+        let ctx = self.ctx;
+        let len = bytes.get_size() / 8;
+        let msize = self.msize.clone();
+        let old = self.load(off, len);
+        let bytes = (0..len)
+            .map(|i| {
+                let high = (len - i) * 8 - 1;
+                let index = z3::ast::BV::from_u64(ctx, i.into(), 256);
+                index
+                    .bvult(size)
+                    .ite(&bytes.extract(high, high - 7), &old.extract(high, high - 7))
+            })
+            .reduce(|bytes, byte| bytes.concat(&byte))
+            .expect("at least a byte is written");
+        self.store(off, bytes.simplify());
+
+        // only the written bytes expand the memory
+        self.msize = msize;
+        self.expand(off, size);
+    }
+
+    /// copy `size` bytes from `src` to `dest` (EIP-5656), the whole source is read
+    /// before anything is written so overlapping ranges behave like memmove
+    pub fn mcopy(&mut self, dest: &z3::ast::BV<'ctx>, src: &z3::ast::BV<'ctx>, size: u32) {
+        if size == 0 {
+            return;
+        }
+
+        let data = self.load(src, size);
+        self.store(dest, data);
+    }
+
+    /// grow msize to cover `size` bytes from `off`, nothing is accessed if `size` is zero
+    pub fn expand(&mut self, off: &z3::ast::BV<'ctx>, size: &z3::ast::BV<'ctx>) {
+        let ctx = self.ctx;
+        let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+        let end = off
+            .bvadd(size)
+            .bvadd(&z3::ast::BV::from_u64(ctx, 31, 256))
+            .bvand(&z3::ast::BV::from_u64(ctx, 31, 256).bvnot());
+
+        let grows = z3::ast::Bool::and(ctx, &[&size._eq(&zero).not(), &end.bvugt(&self.msize)]);
+        self.msize = grows.ite(&end, &self.msize).simplify();
+    }
+
+    /// gas paid for the expansion of the memory so far
+    pub fn cost(&self, costs: &GasCosts) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        let words = self.msize.bvudiv(&z3::ast::BV::from_u64(ctx, 32, 256));
+        let linear = words.bvmul(&z3::ast::BV::from_u64(ctx, costs.memory_word, 256));
+        let quadratic =
+            words
+                .bvmul(&words)
+                .bvudiv(&z3::ast::BV::from_u64(ctx, costs.memory_quad, 256));
+        EVMGas::out_of_gas_above(ctx, &self.msize, &linear.bvadd(&quadratic)).simplify()
+    }
+
+    /// `off` if it is concrete and the `size` bytes from it stay within the concatenated memory
+    fn concrete(off: &z3::ast::BV<'ctx>, size: u32) -> Option<u32> {
+        let off: u32 = off.simplify().as_u64()?.try_into().ok()?;
+        let end = off.checked_add(size)?;
+        (end <= MAX_CONCAT_MEMORY).then_some(off)
+    }
+
+    /// the bytes as an array, the concatenated ones are converted on the first symbolic access
+    fn array(&mut self) -> &z3::ast::Array<'ctx> {
+        if let Backend::Concat(memory) = &self.memory {
+            self.memory = Backend::Array(memory.to_array(self.ctx));
+        }
+
+        match &self.memory {
+            Backend::Array(array) => array,
+            Backend::Concat(_) => unreachable!(),
+        }
     }
 }
 
-// impl Debug for Memory<'ctx> {
-//     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-//         let mut data = self.data.chunks(32);
-
-//         let mut data_str = if let Some(first) = data.next() {
-//             data.fold(format!("[{:?}", hex::encode(first)), |d, w| {
-//                 format!("{d}, {:?}", hex::encode(w))
-//             })
-//         } else {
-//             String::from("[")
-//         };
-
-//         data_str.push(']');
-
-//         write!(f, "{}", data_str)
-//     }
-// }
-
-// #[test]
-// fn set_mem() {
-//     let mut memo = Memory::new();
-//     let words = vec![1, 2, 3, 4, 5];
-//     memo.set(0, words.clone());
-//     assert_eq!(memo.get(0..5), words);
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        helpers::{bv_to_word, to_bv, to_word},
+        opcodes::Revision,
+    };
+    use z3::Config;
+
+    #[test]
+    fn concat() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut memory = Memory::new();
+
+        memory.set(&ctx, 2, z3::ast::BV::from_u64(&ctx, 0xaabb, 16));
+        assert_eq!(memory.len(), 4);
+        memory.set(&ctx, 3, z3::ast::BV::from_u64(&ctx, 0xcc, 8));
+        assert_eq!(memory.get(&ctx, 1..4).as_u64(), Some(0x00aacc));
+        // reading past the end extends with zeros
+        assert_eq!(memory.get(&ctx, 3..6).as_u64(), Some(0xcc0000));
+        assert_eq!(memory.len(), 6);
+    }
+
+    #[test]
+    fn symbolic_offset() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        for model in [MemoryModel::Concat, MemoryModel::Array] {
+            let mut memory = EVMMemory::with_model(&ctx, model);
+            memory.mstore(&to_bv(&ctx, &[0x40]), to_bv(&ctx, &[0xaa, 0xbb]));
+            memory.mstore8(&to_bv(&ctx, &[0x5f]), &to_bv(&ctx, &[0x12, 0xcc]));
+            assert_eq!(bv_to_word(memory.msize()), Some(to_word(&[0x60])));
+            let costs = Revision::default().gas_costs();
+            assert_eq!(memory.cost(&costs).as_u64(), Some(9));
+
+            // the value written at a symbolic offset is read back from the same one
+            let off = z3::ast::BV::new_const(&ctx, "off", 256);
+            memory.mstore(&off, to_bv(&ctx, &[0x2a]));
+            let sol = z3::Solver::new(&ctx);
+            sol.assert(&memory.mload(&off)._eq(&to_bv(&ctx, &[0x2a])).not());
+            assert_eq!(sol.check(), z3::SatResult::Unsat);
+        }
+    }
+
+    #[test]
+    fn large_offset() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut memory = EVMMemory::with_model(&ctx, MemoryModel::Concat);
+        memory.mstore(&to_bv(&ctx, &[0x20]), to_bv(&ctx, &[0x11]));
+
+        // past the bound, and past u32::MAX, the memory becomes an array
+        for off in [&[0x01, 0x00, 0x00][..], &[0xff; 4], &[0xff; 32]] {
+            let off = to_bv(&ctx, off);
+            memory.mstore(&off, to_bv(&ctx, &[0x2a]));
+            assert!(matches!(memory.memory, Backend::Array(_)));
+            assert_eq!(bv_to_word(&memory.mload(&off)), Some(to_word(&[0x2a])));
+        }
+        assert_eq!(
+            bv_to_word(&memory.mload(&to_bv(&ctx, &[0x20]))),
+            Some(to_word(&[0x11]))
+        );
+    }
+}
@@ -1,7 +1,15 @@
+pub use gas::*;
+pub use keccak::*;
 pub use memory::*;
+pub use returndata::*;
 pub use stack::*;
 pub use storage::*;
+pub use transient::*;
 
+mod gas;
+mod keccak;
 mod memory;
+mod returndata;
 mod stack;
 mod storage;
+mod transient;
@@ -0,0 +1,74 @@
+use z3::{ast::Ast, Context, Sort};
+
+/// Output of the last message call of a frame, a byte array of symbolic size
+#[derive(Debug, Clone)]
+pub struct ReturnData<'ctx> {
+    ctx: &'ctx Context,
+    /// size in bytes
+    size: z3::ast::BV<'ctx>,
+    data: z3::ast::Array<'ctx>,
+}
+
+impl<'ctx> ReturnData<'ctx> {
+    /// no call was made yet, the returndata is empty
+    pub fn new(ctx: &'ctx Context) -> Self {
+        let word = Sort::bitvector(ctx, 256);
+
+        Self {
+            ctx,
+            size: z3::ast::BV::from_u64(ctx, 0, 256),
+            data: z3::ast::Array::const_array(ctx, &word, &z3::ast::BV::from_u64(ctx, 0, 8)),
+        }
+    }
+
+    /// output of a call to unknown code, any size and content
+    pub fn symbolic(ctx: &'ctx Context, prefix: &str) -> Self {
+        let (word, byte) = (Sort::bitvector(ctx, 256), Sort::bitvector(ctx, 8));
+
+        Self {
+            ctx,
+            size: z3::ast::BV::new_const(ctx, format!("{prefix}returndatasize"), 256),
+            data: z3::ast::Array::new_const(ctx, format!("{prefix}returndata"), &word, &byte),
+        }
+    }
+
+    /// output made of the concatenated `bytes`, empty if `None`
+    pub fn from_bytes(ctx: &'ctx Context, bytes: Option<&z3::ast::BV<'ctx>>) -> Self {
+        let mut returndata = Self::new(ctx);
+
+        if let Some(bytes) = bytes {
+            let len = bytes.get_size() / 8;
+            returndata.size = z3::ast::BV::from_u64(ctx, len.into(), 256);
+            returndata.data = (0..len).fold(returndata.data, |data, i| {
+                let high = (len - i) * 8 - 1;
+                data.store(
+                    &z3::ast::BV::from_u64(ctx, i.into(), 256),
+                    &bytes.extract(high, high - 7),
+                )
+            });
+        }
+
+        returndata
+    }
+
+    pub fn size(&self) -> &z3::ast::BV<'ctx> {
+        &self.size
+    }
+
+    /// read `size` bytes starting at `offset`, `None` if `size` is zero
+    pub fn load(&self, offset: &z3::ast::BV<'ctx>, size: u32) -> Option<z3::ast::BV<'ctx>> {
+        (0..size)
+            .map(|i| {
+                let index = offset.bvadd(&z3::ast::BV::from_u64(self.ctx, i.into(), 256));
+                self.data.select(&index).as_bv().unwrap()
+            })
+            .reduce(|bytes, byte| bytes.concat(&byte))
+            .map(|bytes| bytes.simplify())
+    }
+
+    /// use the output of `other` whenever `cond` holds
+    pub fn merge(&mut self, cond: &z3::ast::Bool<'ctx>, other: &ReturnData<'ctx>) {
+        self.size = cond.ite(&other.size, &self.size);
+        self.data = cond.ite(&other.data, &self.data);
+    }
+}
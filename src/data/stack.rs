@@ -1,6 +1,9 @@
 use crate::helpers::RevertReason;
 use z3::ast::Ast;
 
+/// Most words the stack can hold
+const STACK_LIMIT: usize = 1024;
+
 #[derive(Default, Debug, Clone)]
 pub struct Stack<'ctx> {
     data: Vec<z3::ast::BV<'ctx>>,
@@ -14,7 +17,7 @@ impl<'ctx> Stack<'ctx> {
     }
 
     pub fn push(&mut self, value: z3::ast::BV<'ctx>) -> Result<(), RevertReason> {
-        if self.data.len() == 16 {
+        if self.data.len() == STACK_LIMIT {
             return Err(RevertReason::StackOverflow);
         }
 
@@ -27,14 +30,10 @@ impl<'ctx> Stack<'ctx> {
         self.data.pop().ok_or(RevertReason::StackUnderflow)
     }
 
-    /// dup the word at index n on the stack. Returns false if n is out of stack
+    /// dup the word at index n from the top of the stack
     pub fn dupn(&mut self, n: usize) -> Result<(), RevertReason> {
-        let word = match self.data.get(n) {
-            Some(w) => w,
-            None => return Err(RevertReason::StackUnderflow),
-        };
-
-        self.push(word.clone())
+        let word = self.get(n)?;
+        self.push(word)
     }
 
     pub fn get(&self, n: usize) -> Result<z3::ast::BV<'ctx>, RevertReason> {
@@ -49,11 +48,12 @@ impl<'ctx> Stack<'ctx> {
             .cloned()
     }
 
-    /// swap the first word with the one at index n on the stack. Returns false if n is out of stack
+    /// swap the top word with the one at index n from the top of the stack
     pub fn swapn(&mut self, n: usize) -> Result<(), RevertReason> {
-        match self.data.get(n) {
-            Some(_) => {
-                self.data.swap(0, n);
+        let len = self.data.len();
+        match len.checked_sub(n + 1) {
+            Some(index) => {
+                self.data.swap(index, len - 1);
                 Ok(())
             }
             None => Err(RevertReason::StackUnderflow),
@@ -88,45 +88,29 @@ impl<'ctx> EVMStack<'ctx> {
         self.stack.get(n)
     }
 
-    pub fn pop64(&mut self) -> Result<Option<u64>, RevertReason> {
-        let val = self.stack.pop()?;
-
-        for i in (1..4).rev() {
-            let ex = val.extract((i + 1) * 64 - 1, i * 64).simplify();
-            if ex.as_u64().unwrap() != 0 {
-                return Ok(None);
-            }
-        }
+    /// the words that are constants fitting in 64 bits, from the bottom
+    pub fn constants(&self) -> impl Iterator<Item = u64> + '_ {
+        self.stack.data.iter().filter_map(|word| word.as_u64())
+    }
 
-        Ok(Some(val.extract(63, 0).simplify().as_u64().unwrap()))
+    /// pop a word, `None` if it is symbolic or doesn't fit in 64 bits
+    pub fn pop64(&mut self) -> Result<Option<u64>, RevertReason> {
+        let val = self.stack.pop()?.simplify();
+        Ok(val.as_u64())
     }
 
+    /// pop a word, `None` if it is symbolic or doesn't fit in 32 bits
     pub fn pop32(&mut self) -> Result<Option<u32>, RevertReason> {
-        let val = self.stack.pop()?;
-
-        for i in (1..8).rev() {
-            let ex = val.extract((i + 1) * 32 - 1, i * 32).simplify();
-            if ex.as_u64().unwrap() != 0 {
-                return Ok(None);
-            }
-        }
-
-        Ok(Some(
-            val.extract(31, 0)
-                .simplify()
-                .as_u64()
-                .unwrap()
-                .try_into()
-                .unwrap(),
-        ))
+        let val = self.pop64()?;
+        Ok(val.and_then(|val| val.try_into().ok()))
     }
 
-    /// dup the word at index n on the stack
+    /// dup the word at index n from the top of the stack, DUP1 being index 0
     pub fn dupn(&mut self, n: usize) -> Result<(), RevertReason> {
         self.stack.dupn(n)
     }
 
-    /// swap the first word with the one at index n on the stack
+    /// swap the top word with the one at index n from the top, SWAP1 being index 1
     pub fn swapn(&mut self, n: usize) -> Result<(), RevertReason> {
         self.stack.swapn(n)
     }
@@ -152,44 +136,37 @@ impl<'ctx> EVMStack<'ctx> {
 //     }
 // }
 
-// TODO: revive the tests
-// #[test]
-// fn push_stack() {
-//     let mut stack = Stack::new();
-//     let num = convert_to_bytes(100_u16);
-//     stack.push(num);
-
-//     assert_eq!(stack.data, vec![num]);
-// }
-
-// #[test]
-// fn pop_stack() {
-//     let mut stack = Stack::new();
-//     let num = convert_to_bytes(100_u16);
-//     stack.push(num);
-//     stack.pop();
-
-//     assert!(stack.data.is_empty());
-// }
-
-// #[test]
-// fn dup_stack() {
-//     let mut stack = Stack::new();
-//     let num = convert_to_bytes(100_u16);
-//     stack.push(num);
-//     stack.dupn(0);
-
-//     assert_eq!(stack.data, vec![num, num]);
-// }
-
-// #[test]
-// fn swap_stack() {
-//     let mut stack = Stack::new();
-//     let num1 = convert_to_bytes(100_u16);
-//     let num2 = convert_to_bytes(200_u16);
-//     stack.push(num1);
-//     stack.push(num2);
-//     stack.swapn(1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use z3::{Config, Context};
+
+    #[test]
+    fn dup_swap() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut stack = EVMStack::new();
+        for n in [3, 2, 1] {
+            stack.push(z3::ast::BV::from_u64(&ctx, n, 256)).unwrap();
+        }
 
-//     assert_eq!(stack.data, vec![num2, num1]);
-// }
+        // DUP2 copies the second word from the top
+        stack.dupn(1).unwrap();
+        assert_eq!(stack.peek(0).unwrap().as_u64(), Some(2));
+        // SWAP3 exchanges the top with the fourth word
+        stack.swapn(3).unwrap();
+        assert_eq!(stack.peek(0).unwrap().as_u64(), Some(3));
+        assert_eq!(stack.peek(3).unwrap().as_u64(), Some(2));
+        assert!(stack.swapn(4).is_err());
+        assert!(stack.dupn(4).is_err());
+
+        // symbolic and wide words are not concrete sizes
+        stack.push(z3::ast::BV::new_const(&ctx, "x", 256)).unwrap();
+        assert_eq!(stack.pop32().unwrap(), None);
+        stack
+            .push(z3::ast::BV::from_u64(&ctx, 1 << 32, 256))
+            .unwrap();
+        assert_eq!(stack.pop32().unwrap(), None);
+        assert_eq!(stack.pop32().unwrap(), Some(3));
+    }
+}
@@ -1,27 +1,112 @@
-use std::collections::HashMap;
+use crate::helpers::{to_bv, Word};
+use std::collections::BTreeMap;
+use z3::{ast::Ast, Context, Sort};
 
-use z3::Context;
-
-// we don't need a generalized data structure, that would be complex for no reason
-#[derive(Debug, Default, Clone)]
-pub struct EVMStorage<'ctx>(HashMap<z3::ast::BV<'ctx>, z3::ast::BV<'ctx>>);
+/// Persistent storage of a contract, a map of 256 bits keys to 256 bits values
+#[derive(Debug, Clone)]
+pub struct EVMStorage<'ctx> {
+    slots: z3::ast::Array<'ctx>,
+    /// keys written during the execution, in order
+    written: Vec<z3::ast::BV<'ctx>>,
+    /// keys loaded during the execution, in order
+    read: Vec<z3::ast::BV<'ctx>>,
+}
 
 impl<'ctx> EVMStorage<'ctx> {
-    pub fn new() -> Self {
-        Default::default()
+    /// storage of a freshly deployed contract, every slot is zero
+    pub fn new(ctx: &'ctx Context) -> Self {
+        let word = Sort::bitvector(ctx, 256);
+        let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+
+        Self {
+            slots: z3::ast::Array::const_array(ctx, &word, &zero),
+            written: Vec::new(),
+            read: Vec::new(),
+        }
+    }
+
+    /// storage of unknown content
+    pub fn symbolic(ctx: &'ctx Context, name: &str) -> Self {
+        let word = Sort::bitvector(ctx, 256);
+
+        Self {
+            slots: z3::ast::Array::new_const(ctx, name, &word, &word),
+            written: Vec::new(),
+            read: Vec::new(),
+        }
+    }
+
+    /// build a storage holding the given concrete slots, the others being zero
+    pub fn from_words(ctx: &'ctx Context, words: &BTreeMap<Word, Word>) -> Self {
+        let mut storage = Self::new(ctx);
+        storage.slots = words.iter().fold(storage.slots, |slots, (key, value)| {
+            slots.store(&to_bv(ctx, key), &to_bv(ctx, value))
+        });
+
+        storage
+    }
+
+    /// load the value at key
+    pub fn sload(&mut self, key: &z3::ast::BV<'ctx>) -> z3::ast::BV<'ctx> {
+        if !self.read.contains(key) {
+            self.read.push(key.clone());
+        }
+
+        self.value(key)
+    }
+
+    /// value at key, without recording the access
+    pub fn value(&self, key: &z3::ast::BV<'ctx>) -> z3::ast::BV<'ctx> {
+        assert_eq!(key.get_size(), 256);
+
+        self.slots
+            .select(key)
+            .as_bv()
+            .expect("storage values are bitvectors")
+            .simplify()
     }
 
     /// store a value at key
-    pub fn sstore(&mut self, ctx: &Context, key: z3::ast::BV, value: z3::ast::BV) {
+    pub fn sstore(&mut self, key: z3::ast::BV<'ctx>, value: z3::ast::BV<'ctx>) {
         assert_eq!(key.get_size(), 256);
         assert_eq!(value.get_size(), 256);
 
-        let off = key.bvurem(&z3::ast::BV::from_u64(ctx, 32, 256));
-        // if off.bvugt(&z3::ast::BV::from_u64(ctx, 0, 256)) {
-        //     // store at low_key + high
-        // } else {
-        //     self.0.insert(key, value);
-        // }
-        // let low_key = key.bvsub(&off);
+        self.slots = self.slots.store(&key, &value);
+        if !self.written.contains(&key) {
+            self.written.push(key);
+        }
+    }
+
+    /// forget the content of every slot, e.g. after a call that could reenter
+    pub fn havoc(&mut self, ctx: &'ctx Context, name: &str) {
+        let word = Sort::bitvector(ctx, 256);
+        self.slots = z3::ast::Array::new_const(ctx, name, &word, &word);
+    }
+
+    /// use the slots of `other` whenever `cond` holds
+    pub fn merge(&mut self, cond: &z3::ast::Bool<'ctx>, other: &EVMStorage<'ctx>) {
+        self.slots = cond.ite(&other.slots, &self.slots);
+        for key in &other.written {
+            if !self.written.contains(key) {
+                self.written.push(key.clone());
+            }
+        }
+        for key in &other.read {
+            if !self.read.contains(key) {
+                self.read.push(key.clone());
+            }
+        }
+    }
+
+    /// keys loaded so far
+    pub fn read(&self) -> &[z3::ast::BV<'ctx>] {
+        &self.read
+    }
+
+    /// iterate over the written slots and their current value
+    pub fn iter(&self) -> impl Iterator<Item = (z3::ast::BV<'ctx>, z3::ast::BV<'ctx>)> + '_ {
+        self.written
+            .iter()
+            .map(|key| (key.clone(), self.value(key)))
     }
 }
@@ -0,0 +1,50 @@
+use z3::{ast::Ast, Context, Sort};
+
+/// Transient storage of a contract (EIP-1153), a map of 256 bits keys to 256 bits values
+/// living for a single transaction. Every slot is zero when the transaction starts.
+#[derive(Debug, Clone)]
+pub struct EVMTransientStorage<'ctx> {
+    slots: z3::ast::Array<'ctx>,
+}
+
+impl<'ctx> EVMTransientStorage<'ctx> {
+    /// transient storage at the start of a transaction, every slot is zero
+    pub fn new(ctx: &'ctx Context) -> Self {
+        let word = Sort::bitvector(ctx, 256);
+        let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+
+        Self {
+            slots: z3::ast::Array::const_array(ctx, &word, &zero),
+        }
+    }
+
+    /// load the value at key
+    pub fn tload(&self, key: &z3::ast::BV<'ctx>) -> z3::ast::BV<'ctx> {
+        assert_eq!(key.get_size(), 256);
+
+        self.slots
+            .select(key)
+            .as_bv()
+            .expect("transient values are bitvectors")
+            .simplify()
+    }
+
+    /// store a value at key
+    pub fn tstore(&mut self, key: &z3::ast::BV<'ctx>, value: &z3::ast::BV<'ctx>) {
+        assert_eq!(key.get_size(), 256);
+        assert_eq!(value.get_size(), 256);
+
+        self.slots = self.slots.store(key, value);
+    }
+
+    /// forget the content of every slot, e.g. after a call that could reenter
+    pub fn havoc(&mut self, ctx: &'ctx Context, name: &str) {
+        let word = Sort::bitvector(ctx, 256);
+        self.slots = z3::ast::Array::new_const(ctx, name, &word, &word);
+    }
+
+    /// use the slots of `other` whenever `cond` holds
+    pub fn merge(&mut self, cond: &z3::ast::Bool<'ctx>, other: &EVMTransientStorage<'ctx>) {
+        self.slots = cond.ite(&other.slots, &self.slots);
+    }
+}
@@ -1,26 +1,383 @@
+use crate::{
+    bytecode::Mnemonics,
+    helpers::{bv_to_word, RevertReason, Word},
+    prover::{Prover, Step, Tree},
+};
+use ethabi::Contract;
 use petgraph::dot::Dot;
 use petgraph::prelude::Graph;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt::Display,
+};
+use z3::{ast::Ast, Config, Context, Solver};
 
-pub fn gen_graph(assertions: Vec<String>) {
-    let mut graph = Graph::<&str, &str>::new();
-    let origin = graph.add_node("Denver");
-    let destination_1 = graph.add_node("San Diego");
-    let destination_2 = graph.add_node("New York");
+/// Most post-states told apart on a single path, its writes may allow many more
+const MAX_PATH_POSTS: usize = 8;
 
-    graph.extend_with_edges(&[
-        (origin, destination_1, assertions[0].as_str()),
-        (origin, destination_2, "hello"),
-    ]);
+/// Abstract state of a contract: the value of every storage slot written so far
+pub type StorageState = BTreeMap<Word, Word>;
 
-    println!("{}", Dot::new(&graph));
+/// A successful call to `selector` moving the contract from the state `from` to `to`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    pub from: usize,
+    pub to: usize,
+    pub selector: u32,
+}
+
+/// State machine of the callable functions of a contract.
+/// The first state is the freshly deployed contract, with an empty storage.
+#[derive(Debug, Default)]
+pub struct Machine {
+    pub states: Vec<StorageState>,
+    pub transitions: Vec<Transition>,
+    /// some transitions were left out: to states past `max_states`,
+    /// or on paths the prover didn't explore to the end
+    pub truncated: bool,
+    /// calls the prover failed to explore, by state and selector
+    pub failures: Vec<(usize, u32, RevertReason)>,
+}
+
+impl Machine {
+    /// explore every selector from every reachable state, up to `max_states` states.
+    /// The paths unroll their loops `max_unroll` times, the prover's default if unset
+    pub fn extract(
+        code: &Mnemonics,
+        selectors: &[u32],
+        max_states: usize,
+        max_unroll: Option<usize>,
+    ) -> Self {
+        let mut machine = Machine {
+            states: vec![StorageState::new()],
+            ..Default::default()
+        };
+        let mut queue = VecDeque::from([0]);
+
+        while let Some(from) = queue.pop_front() {
+            for selector in selectors {
+                let posts = match Self::call(code, &machine.states[from], *selector, max_unroll) {
+                    Ok((posts, complete)) => {
+                        machine.truncated |= !complete;
+                        posts
+                    }
+                    Err(reason) => {
+                        machine.failures.push((from, *selector, reason));
+                        continue;
+                    }
+                };
+
+                for post in posts {
+                    let to = match machine.states.iter().position(|s| s == &post) {
+                        Some(to) => to,
+                        None if machine.states.len() < max_states => {
+                            machine.states.push(post);
+                            queue.push_back(machine.states.len() - 1);
+                            machine.states.len() - 1
+                        }
+                        None => {
+                            machine.truncated = true;
+                            continue;
+                        }
+                    };
+
+                    let transition = Transition {
+                        from,
+                        to,
+                        selector: *selector,
+                    };
+                    if !machine.transitions.contains(&transition) {
+                        machine.transitions.push(transition);
+                    }
+                }
+            }
+        }
+
+        machine
+    }
+
+    /// symbolically call `selector` from the `pre` state, returns the states its successful paths allow
+    /// and whether they are all of them.
+    /// Each call is a new transaction: only the storage is carried over, the transient storage is empty.
+    fn call(
+        code: &Mnemonics,
+        pre: &StorageState,
+        selector: u32,
+        max_unroll: Option<usize>,
+    ) -> Result<(Vec<StorageState>, bool), RevertReason> {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut prover = Prover::new(&ctx, code, Contract::default());
+        prover.set_storage(pre.clone());
+        prover.constrain_selector(selector);
+        if let Some(max_unroll) = max_unroll {
+            prover.set_max_unroll(max_unroll);
+        }
+
+        let tree = prover.run()?;
+
+        let mut posts = Vec::new();
+        let mut complete = true;
+        for (sol, steps, _) in tree.values() {
+            let last = match steps.last() {
+                Some(last) if last.ret().is_success() => last,
+                // the paths cut short could have reached other states
+                Some(last) => {
+                    complete &= !last.ret().bounded() && last.ret().unsupported().is_none();
+                    continue;
+                }
+                None => continue,
+            };
+            complete &= !last.ret().bounded();
+
+            // every write of the path the model picked is ruled out to find the next post-state
+            sol.push();
+            let mut exhausted = false;
+            for _ in 0..MAX_PATH_POSTS {
+                let (post, writes) = match Self::post(&ctx, sol, last, pre) {
+                    Some(post) => post,
+                    None => {
+                        exhausted = true;
+                        break;
+                    }
+                };
+                if !posts.contains(&post) {
+                    posts.push(post);
+                }
+                match writes {
+                    Some(writes) => sol.assert(&writes.not()),
+                    None => {
+                        exhausted = true;
+                        break;
+                    }
+                }
+            }
+            sol.pop(1);
+            complete &= exhausted;
+        }
+
+        Ok((posts, complete))
+    }
+
+    /// a post-state the path ending on `last` allows, with the writes leading to it if any
+    fn post<'ctx>(
+        ctx: &'ctx Context,
+        sol: &Solver<'ctx>,
+        last: &Step<'_, 'ctx>,
+        pre: &StorageState,
+    ) -> Option<(StorageState, Option<z3::ast::Bool<'ctx>>)> {
+        let model = Prover::model(sol, last)?;
+        let mut post = pre.clone();
+        let mut writes = Vec::new();
+        for (key, value) in last.storage().iter() {
+            let (at, word) = (model.eval(&key, true)?, model.eval(&value, true)?);
+            post.insert(bv_to_word(&at)?, bv_to_word(&word)?);
+            writes.push(z3::ast::Bool::and(ctx, &[&key._eq(&at), &value._eq(&word)]));
+        }
+        // an unset slot and a slot set to zero are the same state
+        post.retain(|_, value| value != &[0u8; 32]);
+
+        let writes = match writes.is_empty() {
+            true => None,
+            false => Some(z3::ast::Bool::and(ctx, &writes.iter().collect::<Vec<_>>())),
+        };
+        Some((post, writes))
+    }
+
+    /// selectors that can be successfully called from `state`
+    pub fn callable(&self, state: usize) -> Vec<u32> {
+        self.transitions
+            .iter()
+            .filter(|t| t.from == state)
+            .map(|t| t.selector)
+            .collect()
+    }
+
+    /// whether `call` can succeed without `before` having been called first,
+    /// `None` if it wasn't found but the machine is truncated or some calls failed
+    pub fn callable_before(&self, call: u32, before: u32) -> Option<bool> {
+        let mut visited = HashSet::new();
+        let mut stack = vec![0];
+
+        while let Some(state) = stack.pop() {
+            if !visited.insert(state) {
+                continue;
+            }
+
+            for t in self.transitions.iter().filter(|t| t.from == state) {
+                if t.selector == call {
+                    return Some(true);
+                } else if t.selector != before {
+                    stack.push(t.to);
+                }
+            }
+        }
+
+        (!self.truncated && self.failures.is_empty()).then_some(false)
+    }
+
+    /// one node per state, one edge per transition labelled by its selector
+    pub fn to_dot(&self) -> String {
+        let mut graph = Graph::<String, String>::new();
+        let nodes: Vec<_> = (0..self.states.len())
+            .map(|state| graph.add_node(format!("S{state}")))
+            .collect();
+        for t in &self.transitions {
+            graph.add_edge(nodes[t.from], nodes[t.to], format!("{:#010x}", t.selector));
+        }
+
+        format!("{}", Dot::new(&graph))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let states: Vec<_> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(id, storage)| {
+                let storage: serde_json::Map<_, _> = storage
+                    .iter()
+                    .map(|(key, value)| {
+                        (
+                            format!("0x{}", hex::encode(key)),
+                            json!(format!("0x{}", hex::encode(value))),
+                        )
+                    })
+                    .collect();
+                json!({ "id": id, "storage": storage })
+            })
+            .collect();
+        let transitions: Vec<_> = self
+            .transitions
+            .iter()
+            .map(|t| json!({ "from": t.from, "to": t.to, "selector": format!("{:#010x}", t.selector) }))
+            .collect();
+        let failures: Vec<_> = self
+            .failures
+            .iter()
+            .map(|(from, selector, reason)| {
+                json!({
+                    "from": from,
+                    "selector": format!("{selector:#010x}"),
+                    "reason": format!("{reason:?}"),
+                })
+            })
+            .collect();
+
+        json!({
+            "states": states,
+            "transitions": transitions,
+            "truncated": self.truncated,
+            "failures": failures,
+        })
+    }
+}
+
+/// A branch explored by the prover
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub id: usize,
+    /// pc of the first and last executed instructions
+    pub start: Option<usize>,
+    pub end: Option<usize>,
+    pub outcome: &'static str,
+}
+
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pc = |pc: Option<usize>| pc.map_or(String::from("?"), |pc| format!("{pc:#x}"));
+        write!(
+            f,
+            "#{} [{}..{}] {}",
+            self.id,
+            pc(self.start),
+            pc(self.end),
+            self.outcome
+        )
+    }
+}
+
+/// Control flow explored by the prover, one node per branch id and
+/// one edge per fork, labelled by its simplified path condition.
+pub fn gen_graph(tree: &Tree) -> Graph<Node, String> {
+    let mut graph = Graph::new();
+
+    let nodes: BTreeMap<_, _> = tree
+        .iter()
+        .map(|(id, (_, steps, _))| {
+            let outcome = match steps.last().map(|step| step.ret()) {
+                Some(ret) if ret.is_success() => "return",
+                Some(ret) if ret.has_ret() => "revert",
+                Some(ret) if ret.unsupported().is_some() => "unsupported",
+                _ => "open",
+            };
+            let node = Node {
+                id: *id,
+                start: steps.first().map(|step| step.pc()),
+                end: steps.last().map(|step| step.pc()),
+                outcome,
+            };
+
+            (*id, graph.add_node(node))
+        })
+        .collect();
+
+    for (id, (_, _, fork)) in tree {
+        if let Some(fork) = fork {
+            if let Some(parent) = nodes.get(&fork.parent) {
+                graph.add_edge(*parent, nodes[id], fork.cond.to_string());
+            }
+        }
+    }
+
+    graph
+}
+
+pub fn to_dot(graph: &Graph<Node, String>) -> String {
+    format!("{}", Dot::new(graph))
+}
+
+/// Adjacency export of the graph, nodes and edges are referred to by their branch id
+pub fn to_json(graph: &Graph<Node, String>) -> serde_json::Value {
+    let nodes: Vec<_> = graph
+        .node_weights()
+        .map(|node| {
+            json!({
+                "id": node.id,
+                "start": node.start,
+                "end": node.end,
+                "outcome": node.outcome,
+            })
+        })
+        .collect();
+
+    let edges: Vec<_> = graph
+        .edge_indices()
+        .filter_map(|edge| {
+            let (from, to) = graph.edge_endpoints(edge)?;
+            Some(json!({
+                "from": graph[from].id,
+                "to": graph[to].id,
+                "cond": graph[edge],
+            }))
+        })
+        .collect();
+
+    json!({ "nodes": nodes, "edges": edges })
 }
 
 #[cfg(test)]
 mod tests {
+    use super::{gen_graph, to_dot, to_json, Machine};
     use crate::analysis::get_selectors;
+    use crate::asm::assemble;
     use crate::bytecode;
     use crate::utils::get_artifacts_code;
-    use crate::{bytecode::to_mnemonics, prover::Prover};
+    use crate::{
+        bytecode::{split_deploy, split_metadata, to_mnemonics},
+        prover::Prover,
+    };
     use ethabi::Contract;
     use tempsol::compile_contract;
     use z3::{Config, Context, SatResult};
@@ -56,6 +413,108 @@ mod tests {
         dbg!(&tree);
     }
 
+    #[test]
+    fn graph() {
+        let cfg = Config::default();
+        let hex = hex::decode("5F3556FE5B60015B").unwrap();
+        let code = to_mnemonics(&hex);
+        let ctx = Context::new(&cfg);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+
+        let graph = gen_graph(&tree);
+        assert_eq!(graph.node_count(), 3);
+        assert_eq!(graph.edge_count(), 2);
+
+        // the jump lands on either JUMPDEST, both branches run off the end of the code
+        let dot = to_dot(&graph);
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains(r##"0 [ label = "#0 [0x0..0x2] open" ]"##));
+        assert!(dot.contains(r##"1 [ label = "#1 [0x4..0x7] open" ]"##));
+        assert!(dot.contains(r##"2 [ label = "#3 [0x7..0x7] open" ]"##));
+        assert!(dot.contains(r##"0 -> 1 [ label = "(= 4"##));
+        assert!(dot.contains(r##"0 -> 2 [ label = "(= 7"##));
+
+        let json = to_json(&graph);
+        assert_eq!(json["nodes"].as_array().unwrap().len(), 3);
+        assert!(json["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|edge| edge["from"] == 0));
+    }
+
+    #[test]
+    fn start_before_finish() {
+        // start() only succeeds on an empty slot 0 and writes 1,
+        // finish() only succeeds when slot 0 holds 1 and writes 2
+        let hex = hex::decode(concat!(
+            "5f3560e01c",
+            "80631111111114601b57",
+            "632222222214602b57",
+            "5f5ffd",
+            "5b5f5415602557", // 0x1b: start()
+            "5f5ffd",
+            "5b60015f5500",
+            "5b5f54600114603757", // 0x2b: finish()
+            "5f5ffd",
+            "5b60025f5500",
+        ))
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let selectors = get_selectors(&code);
+        let (start, finish) = (0x11111111, 0x22222222);
+
+        let machine = Machine::extract(&code, &selectors, 8, None);
+
+        assert_eq!(machine.states.len(), 3);
+        assert!(!machine.truncated);
+        assert_eq!(machine.callable(0), vec![start]);
+        assert_eq!(machine.callable_before(start, finish), Some(true));
+        assert_eq!(machine.callable_before(finish, start), Some(false));
+
+        // past the bound on the states, start() is not known to be callable
+        let machine = Machine::extract(&code, &selectors, 1, None);
+        assert!(machine.truncated);
+        assert!(machine.transitions.is_empty());
+        assert_eq!(machine.callable_before(start, finish), None);
+        assert_eq!(machine.to_json()["truncated"], true);
+    }
+
+    #[test]
+    fn transient_lock() {
+        // reverts if the lock is taken, else takes it and sets slot 0,
+        // the lock doesn't outlive the transaction so the call can be repeated
+        let hex = assemble(
+            "
+            PUSH0 TLOAD PUSH1 locked JUMPI
+            PUSH1 0x01 PUSH0 TSTORE
+            PUSH1 0x01 PUSH0 SSTORE STOP
+            locked: JUMPDEST PUSH0 PUSH0 REVERT
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let selector = 0x11111111;
+
+        let machine = Machine::extract(&code, &[selector], 8, None);
+        assert_eq!(machine.states.len(), 2);
+        assert_eq!(machine.callable(0), vec![selector]);
+        assert_eq!(machine.callable(1), vec![selector]);
+    }
+
+    #[test]
+    fn symbolic_write() {
+        // a single path storing the lowest bit of the argument in slot 0
+        let hex = assemble("PUSH1 0x04 CALLDATALOAD PUSH1 0x01 AND PUSH0 SSTORE STOP").unwrap();
+        let code = to_mnemonics(&hex);
+        let selector = 0x11111111;
+
+        let machine = Machine::extract(&code, &[selector], 8, None);
+        assert_eq!(machine.states.len(), 2);
+        assert_eq!(machine.transitions.len(), 4);
+    }
+
     #[test]
     fn state_no_stor() {
         let bytecode = tempsol::get_deploy_code(String::from(
@@ -93,8 +552,12 @@ contract FSM {
     }
 }",
         ));
+        // only the runtime code, without its metadata
+        let runtime = split_deploy(&bytecode).unwrap().runtime;
+        let (runtime, metadata) = split_metadata(runtime);
+        assert_eq!(metadata.unwrap().solc.as_deref(), Some("0.8.0"));
 
-        let code = to_mnemonics(&bytecode);
+        let code = to_mnemonics(runtime);
         let selectors = get_selectors(&code);
         let cfg = Config::default();
         let ctx = Context::new(&cfg);
@@ -1,8 +1,9 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::Debug,
     ops::{Add, Range, Sub},
 };
+use tiny_keccak::{Hasher, Keccak};
 use z3::{ast::Ast, Context};
 
 pub type Word = [u8; 32];
@@ -21,6 +22,8 @@ pub enum RevertReason {
     Unsat,
     /// Unknown solve status
     Unknown,
+    /// The instruction at this pc can't be executed, e.g. a size which is not concrete
+    Unsupported(usize),
 }
 
 /// ret a word with 1 if eq, else an empty word
@@ -67,6 +70,15 @@ impl EVMCalldata {
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.calldata.data.len()
+    }
+
+    /// `size` bytes starting at `offset`, zero past the end
+    pub fn copy(&self, offset: usize, size: usize) -> Vec<u8> {
+        self.calldata.get(offset..(offset + size))
+    }
+
     pub fn load(&self, offset: U256) -> Word {
         let off: usize = offset.into();
         let mut ret = [0; 32];
@@ -77,7 +89,7 @@ impl EVMCalldata {
 }
 
 pub type Address = [u8; 20];
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 pub struct U256([u8; 32]);
 
 impl U256 {
@@ -96,6 +108,11 @@ impl U256 {
     pub fn zero() -> Self {
         Self::min_value()
     }
+
+    /// big-endian bytes of the number
+    pub fn as_bytes(&self) -> Word {
+        self.0
+    }
 }
 
 impl Add for U256 {
@@ -374,23 +391,69 @@ impl Iterator for U256 {
     }
 }
 
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Env {
-    caller: Address,
-    origin: Address,
-    coinbase: Address,
-    value: U256,
-    gas_limit: u64,
-    gas_price: u64,
-    nonce: u64,
-    timestamp: u32,
-    difficulty: U256,
-    number: u64,
+    pub caller: Address,
+    pub origin: Address,
+    pub coinbase: Address,
+    pub value: U256,
+    pub gas_limit: u64,
+    pub gas_price: u64,
+    pub nonce: u64,
+    pub timestamp: u64,
+    pub difficulty: U256,
+    pub number: u64,
+    pub chain_id: u64,
+    pub base_fee: U256,
+    pub blob_base_fee: U256,
+    /// hashes of the past blocks by number, zero for the other ones
+    pub block_hashes: BTreeMap<u64, Word>,
 }
 
+#[derive(Default)]
 pub struct State {
     storage: HashMap<Address, HashMap<U256, U256>>,
     code: HashMap<Address, Vec<u8>>,
     balance: HashMap<Address, U256>,
+    nonce: HashMap<Address, u64>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn code(&self, address: &Address) -> Option<&[u8]> {
+        self.code.get(address).map(|code| code.as_slice())
+    }
+
+    pub fn set_code(&mut self, address: Address, code: Vec<u8>) {
+        self.code.insert(address, code);
+    }
+
+    pub fn storage(&self, address: &Address) -> Option<&HashMap<U256, U256>> {
+        self.storage.get(address)
+    }
+
+    pub fn set_storage(&mut self, address: Address, key: U256, value: U256) {
+        self.storage.entry(address).or_default().insert(key, value);
+    }
+
+    pub fn balance(&self, address: &Address) -> Option<U256> {
+        self.balance.get(address).copied()
+    }
+
+    pub fn set_balance(&mut self, address: Address, balance: U256) {
+        self.balance.insert(address, balance);
+    }
+
+    pub fn nonce(&self, address: &Address) -> Option<u64> {
+        self.nonce.get(address).copied()
+    }
+
+    pub fn set_nonce(&mut self, address: Address, nonce: u64) {
+        self.nonce.insert(address, nonce);
+    }
 }
 
 pub fn convert_to_bytes<N: Into<u128>>(n: N) -> Word {
@@ -410,6 +473,95 @@ pub fn to_word(val: &[u8]) -> Word {
     slice
 }
 
+/// Read back a constant bitvector as a big-endian word. `None` if it is still symbolic
+pub fn bv_to_word(bv: &z3::ast::BV) -> Option<Word> {
+    let bv = bv.simplify();
+    let mut word = [0u8; 32];
+
+    for (i, chunk) in word.chunks_exact_mut(8).enumerate() {
+        let high = 255 - i as u32 * 64;
+        let limb = bv.extract(high, high - 63).simplify().as_u64()?;
+        chunk.copy_from_slice(&limb.to_be_bytes());
+    }
+
+    Some(word)
+}
+
+/// Read back a constant bitvector as big-endian bytes. `None` if it is still symbolic
+pub fn bv_to_bytes(bv: &z3::ast::BV) -> Option<Vec<u8>> {
+    let size = bv.get_size();
+    if size % 8 != 0 {
+        return None;
+    }
+
+    let bv = bv.simplify();
+    (0..size / 8)
+        .map(|i| {
+            let high = size - i * 8 - 1;
+            bv.extract(high, high - 7)
+                .simplify()
+                .as_u64()
+                .map(|b| b as u8)
+        })
+        .collect()
+}
+
+/// concatenate big-endian bytes of any length in a bitvector
+pub fn bytes_to_bv<'ctx>(ctx: &'ctx Context, bytes: &[u8]) -> z3::ast::BV<'ctx> {
+    bytes
+        .iter()
+        .map(|b| z3::ast::BV::from_u64(ctx, (*b).into(), 8))
+        .reduce(|bv, b| bv.concat(&b))
+        .expect("no bytes to convert")
+}
+
+/// the 20 lowest bytes of a word
+pub fn to_address(word: &Word) -> Address {
+    let mut address = [0; 20];
+    address.copy_from_slice(&word[12..]);
+    address
+}
+
+pub fn keccak256(data: &[u8]) -> Word {
+    let mut hasher = Keccak::v256();
+    let mut out = [0; 32];
+    hasher.update(data);
+    hasher.finalize(&mut out);
+    out
+}
+
+/// rlp encoding of an integer
+pub fn rlp_u64(n: u64) -> Vec<u8> {
+    match n {
+        0 => vec![0x80],
+        1..=0x7f => vec![n as u8],
+        _ => {
+            let bytes: Vec<u8> = n
+                .to_be_bytes()
+                .into_iter()
+                .skip_while(|b| *b == 0)
+                .collect();
+            [vec![0x80 + bytes.len() as u8], bytes].concat()
+        }
+    }
+}
+
+#[test]
+fn rlp() {
+    assert_eq!(rlp_u64(0), vec![0x80]);
+    assert_eq!(rlp_u64(0x7f), vec![0x7f]);
+    assert_eq!(rlp_u64(0x80), vec![0x81, 0x80]);
+    assert_eq!(rlp_u64(0x0400), vec![0x82, 0x04, 0x00]);
+}
+
+#[test]
+fn keccak() {
+    assert_eq!(
+        hex::encode(keccak256(&[])),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+}
+
 pub fn to_bv<'ctx>(ctx: &'ctx Context, val: &[u8]) -> z3::ast::BV<'ctx> {
     // println!("{:#?}", &val);
     assert!(val.len() <= 32);
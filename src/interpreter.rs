@@ -0,0 +1,777 @@
+use crate::{
+    analysis::get_jumpdest,
+    bytecode::{Mnemonic, Mnemonics},
+    data::MAX_MEMORY,
+    helpers::{keccak256, to_address, Address, EVMCalldata, Env, State, Word, U256},
+    opcodes::OpCodes::*,
+    prover::{path_trace, Tree},
+    witness::Witness,
+};
+use ethabi::{ethereum_types::U512, Uint};
+use std::collections::HashMap;
+
+/// Longest concrete execution, in steps
+const MAX_STEPS: usize = 100_000;
+
+/// How a concrete execution ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Stop,
+    Return(Vec<u8>),
+    Revert(Vec<u8>),
+    /// exceptional halt: invalid opcode or jump, stack underflow or overflow
+    Invalid,
+    /// an opcode the interpreter doesn't run, at this pc
+    Unsupported(usize),
+    /// ran for more than `MAX_STEPS`
+    Timeout,
+}
+
+impl Outcome {
+    pub fn is_success(&self) -> bool {
+        matches!(self, Outcome::Stop | Outcome::Return(_))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Execution {
+    /// pc of every executed instruction
+    pub trace: Vec<usize>,
+    pub outcome: Outcome,
+}
+
+/// A difference between a symbolic path and the concrete execution of its witness
+#[derive(Debug)]
+pub enum Mismatch {
+    Trace {
+        expected: Vec<usize>,
+        found: Vec<usize>,
+    },
+    Outcome {
+        expected_success: bool,
+        found: Outcome,
+    },
+    /// the concrete execution can't be compared, at this pc
+    Unsupported(usize),
+}
+
+/// what to execute after an instruction
+enum Flow {
+    Next,
+    Jump(Uint),
+    Halt(Outcome),
+}
+
+/// Concrete interpreter over the same opcode table as the prover
+pub struct Interpreter<'a> {
+    code: &'a Mnemonics<'a>,
+    bytes: Vec<u8>,
+    address: Address,
+    calldata: &'a EVMCalldata,
+    env: &'a Env,
+    state: &'a mut State,
+    /// transient storage of the executing account, empty at the start of the transaction
+    transient: HashMap<Uint, Uint>,
+    stack: Vec<Uint>,
+    memory: Vec<u8>,
+}
+
+fn to_uint(word: &Word) -> Uint {
+    Uint::from_big_endian(word)
+}
+
+fn to_word(n: Uint) -> Word {
+    let mut word = [0; 32];
+    n.to_big_endian(&mut word);
+    word
+}
+
+/// two's complement helpers
+fn is_neg(n: Uint) -> bool {
+    n.bit(255)
+}
+
+fn neg(n: Uint) -> Uint {
+    (!n).overflowing_add(Uint::one()).0
+}
+
+fn abs(n: Uint) -> Uint {
+    if is_neg(n) {
+        neg(n)
+    } else {
+        n
+    }
+}
+
+fn from_bool(b: bool) -> Uint {
+    if b {
+        Uint::one()
+    } else {
+        Uint::zero()
+    }
+}
+
+/// an offset or a size that fits in memory
+fn to_usize(n: Uint) -> Option<usize> {
+    (n <= Uint::from(u32::MAX)).then(|| n.low_u64() as usize)
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(
+        code: &'a Mnemonics<'a>,
+        address: Address,
+        calldata: &'a EVMCalldata,
+        env: &'a Env,
+        state: &'a mut State,
+    ) -> Self {
+        // the code as bytes, for CODESIZE and CODECOPY
+        let bytes = code
+            .iter()
+            .flat_map(|mn| std::iter::once(mn.op.u8()).chain(mn.pushes.iter().copied()))
+            .collect();
+
+        Self {
+            code,
+            bytes,
+            address,
+            calldata,
+            env,
+            state,
+            transient: HashMap::new(),
+            stack: Vec::new(),
+            memory: Vec::new(),
+        }
+    }
+
+    /// run the code until it halts
+    pub fn execute(mut self) -> Execution {
+        let jumpdests = get_jumpdest(self.code.to_vec());
+        let mut trace = Vec::new();
+        let mut index = 0;
+
+        let outcome = loop {
+            let instruction = match self.code.get(index) {
+                Some(instruction) => instruction,
+                None => break Outcome::Stop,
+            };
+            if trace.len() >= MAX_STEPS {
+                break Outcome::Timeout;
+            }
+            trace.push(instruction.pc);
+
+            match self.step(instruction) {
+                Some(Flow::Next) => index += 1,
+                Some(Flow::Jump(dest)) => {
+                    let dest = match to_usize(dest) {
+                        Some(dest) if jumpdests.contains(&(dest as u64)) => dest,
+                        _ => break Outcome::Invalid,
+                    };
+                    index = self.code.iter().position(|mn| mn.pc == dest).unwrap();
+                }
+                Some(Flow::Halt(outcome)) => break outcome,
+                None => break Outcome::Invalid,
+            }
+        };
+
+        Execution { trace, outcome }
+    }
+
+    fn pop(&mut self) -> Option<Uint> {
+        self.stack.pop()
+    }
+
+    fn push(&mut self, n: Uint) -> Option<()> {
+        if self.stack.len() >= 1024 {
+            return None;
+        }
+        self.stack.push(n);
+        Some(())
+    }
+
+    /// memory range, expanded with zeros if needed. `None` past `MAX_MEMORY`,
+    /// the expansion would run out of gas
+    fn memory(&mut self, offset: Uint, size: Uint) -> Option<&mut [u8]> {
+        let size = to_usize(size)?;
+        if size == 0 {
+            return Some(&mut []);
+        }
+        let offset = to_usize(offset)?;
+        let end = offset + size;
+        if end > MAX_MEMORY as usize {
+            return None;
+        }
+        if self.memory.len() < end {
+            self.memory.resize((end + 31) / 32 * 32, 0);
+        }
+        Some(&mut self.memory[offset..end])
+    }
+
+    fn address_of(n: Uint) -> Address {
+        to_address(&to_word(n))
+    }
+
+    /// execute one instruction, `None` on an exceptional halt
+    fn step(&mut self, instruction: &Mnemonic) -> Option<Flow> {
+        let op = instruction.op;
+
+        match op.opcode() {
+            Stop => return Some(Flow::Halt(Outcome::Stop)),
+            Add => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a.overflowing_add(b).0)?;
+            }
+            Mul => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a.overflowing_mul(b).0)?;
+            }
+            Sub => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a.overflowing_sub(b).0)?;
+            }
+            Div => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a.checked_div(b).unwrap_or_default())?;
+            }
+            Sdiv => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                let res = match abs(a).checked_div(abs(b)) {
+                    Some(res) if is_neg(a) != is_neg(b) => neg(res),
+                    Some(res) => res,
+                    None => Uint::zero(),
+                };
+                self.push(res)?;
+            }
+            Mod => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a.checked_rem(b).unwrap_or_default())?;
+            }
+            Smod => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                let res = match abs(a).checked_rem(abs(b)) {
+                    Some(res) if is_neg(a) => neg(res),
+                    Some(res) => res,
+                    None => Uint::zero(),
+                };
+                self.push(res)?;
+            }
+            Addmod | Mulmod => {
+                let (a, b, n) = (self.pop()?, self.pop()?, self.pop()?);
+                let res = if n.is_zero() {
+                    Uint::zero()
+                } else {
+                    let (a, b, n) = (U512::from(a), U512::from(b), U512::from(n));
+                    let res = if op.opcode() == &Addmod {
+                        (a + b) % n
+                    } else {
+                        (a * b) % n
+                    };
+                    Uint::try_from(res).unwrap()
+                };
+                self.push(res)?;
+            }
+            Exp => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a.overflowing_pow(b).0)?;
+            }
+            Signextend => {
+                let (b, x) = (self.pop()?, self.pop()?);
+                let res = if b < Uint::from(31) {
+                    let bit = b.low_u64() as usize * 8 + 7;
+                    let mask = (Uint::one() << (bit + 1)) - 1;
+                    if x.bit(bit) {
+                        x | !mask
+                    } else {
+                        x & mask
+                    }
+                } else {
+                    x
+                };
+                self.push(res)?;
+            }
+            Lt => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(from_bool(a < b))?;
+            }
+            Gt => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(from_bool(a > b))?;
+            }
+            Slt => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                let sign = Uint::one() << 255;
+                self.push(from_bool((a ^ sign) < (b ^ sign)))?;
+            }
+            Sgt => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                let sign = Uint::one() << 255;
+                self.push(from_bool((a ^ sign) > (b ^ sign)))?;
+            }
+            Eq => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(from_bool(a == b))?;
+            }
+            Iszero => {
+                let a = self.pop()?;
+                self.push(from_bool(a.is_zero()))?;
+            }
+            And => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a & b)?;
+            }
+            Or => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a | b)?;
+            }
+            Xor => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(a ^ b)?;
+            }
+            Not => {
+                let a = self.pop()?;
+                self.push(!a)?;
+            }
+            Byte => {
+                let (i, x) = (self.pop()?, self.pop()?);
+                let res = if i < Uint::from(32) {
+                    Uint::from(x.byte(31 - i.low_u64() as usize))
+                } else {
+                    Uint::zero()
+                };
+                self.push(res)?;
+            }
+            Shl => {
+                let (shift, value) = (self.pop()?, self.pop()?);
+                let res = if shift < Uint::from(256) {
+                    value << shift.low_u64() as usize
+                } else {
+                    Uint::zero()
+                };
+                self.push(res)?;
+            }
+            Shr => {
+                let (shift, value) = (self.pop()?, self.pop()?);
+                let res = if shift < Uint::from(256) {
+                    value >> shift.low_u64() as usize
+                } else {
+                    Uint::zero()
+                };
+                self.push(res)?;
+            }
+            Sar => {
+                let (shift, value) = (self.pop()?, self.pop()?);
+                let shift = shift.min(Uint::from(255)).low_u64() as usize;
+                let res = if is_neg(value) {
+                    !((!value) >> shift)
+                } else {
+                    value >> shift
+                };
+                self.push(res)?;
+            }
+            Sha3 => {
+                let (off, size) = (self.pop()?, self.pop()?);
+                let hash = keccak256(self.memory(off, size)?);
+                self.push(to_uint(&hash))?;
+            }
+            Address => {
+                let mut word = [0; 32];
+                word[12..].copy_from_slice(&self.address);
+                self.push(to_uint(&word))?;
+            }
+            Balance => {
+                let address = Self::address_of(self.pop()?);
+                let balance = self.state.balance(&address).unwrap_or_default();
+                self.push(to_uint(&balance.as_bytes()))?;
+            }
+            Origin | Caller | Coinbase => {
+                let address = match op.opcode() {
+                    Origin => self.env.origin,
+                    Caller => self.env.caller,
+                    _ => self.env.coinbase,
+                };
+                let mut word = [0; 32];
+                word[12..].copy_from_slice(&address);
+                self.push(to_uint(&word))?;
+            }
+            Callvalue => self.push(to_uint(&self.env.value.as_bytes()))?,
+            Calldataload => {
+                let off = self.pop()?;
+                let word = match to_usize(off) {
+                    Some(off) => self.calldata.load(U256::from(off)),
+                    None => [0; 32],
+                };
+                self.push(to_uint(&word))?;
+            }
+            Calldatasize => self.push(Uint::from(self.calldata.size()))?,
+            Calldatacopy | Codecopy => {
+                let (dest, off, size) = (self.pop()?, self.pop()?, self.pop()?);
+                // expand first, so that the copied bytes fit in memory
+                let len = self.memory(dest, size)?.len();
+                let bytes = match to_usize(off) {
+                    Some(off) if op.opcode() == &Calldatacopy => self.calldata.copy(off, len),
+                    Some(off) => (off..off + len)
+                        .map(|i| self.bytes.get(i).copied().unwrap_or_default())
+                        .collect(),
+                    None => vec![0; len],
+                };
+                self.memory(dest, size)?.copy_from_slice(&bytes);
+            }
+            Codesize => self.push(Uint::from(self.bytes.len()))?,
+            Gasprice => self.push(Uint::from(self.env.gas_price))?,
+            Extcodesize => {
+                let address = Self::address_of(self.pop()?);
+                let size = self.state.code(&address).map_or(0, |code| code.len());
+                self.push(Uint::from(size))?;
+            }
+            Returndatasize => self.push(Uint::zero())?,
+            Returndatacopy => {
+                // no call runs, so the returndata stays empty and only an empty copy succeeds
+                let (dest, off, size) = (self.pop()?, self.pop()?, self.pop()?);
+                if !off.is_zero() || !size.is_zero() {
+                    return None;
+                }
+                self.memory(dest, size)?;
+            }
+            Timestamp => self.push(Uint::from(self.env.timestamp))?,
+            Number => self.push(Uint::from(self.env.number))?,
+            Difficulty => self.push(to_uint(&self.env.difficulty.as_bytes()))?,
+            Gaslimit => self.push(Uint::from(self.env.gas_limit))?,
+            Chainid => self.push(Uint::from(self.env.chain_id))?,
+            Basefee => self.push(to_uint(&self.env.base_fee.as_bytes()))?,
+            Blobbasefee => self.push(to_uint(&self.env.blob_base_fee.as_bytes()))?,
+            Blockhash => {
+                let number = self.pop()?;
+                let hash = match number.try_into() {
+                    Ok(number) => self.env.block_hashes.get(&number).copied(),
+                    Err(_) => None,
+                };
+                self.push(to_uint(&hash.unwrap_or_default()))?;
+            }
+            Selfbalance => {
+                let balance = self.state.balance(&self.address).unwrap_or_default();
+                self.push(to_uint(&balance.as_bytes()))?;
+            }
+            Pop => {
+                self.pop()?;
+            }
+            Mload => {
+                let off = self.pop()?;
+                let word = to_uint(&self.memory(off, Uint::from(32))?.try_into().unwrap());
+                self.push(word)?;
+            }
+            Mstore => {
+                let (off, value) = (self.pop()?, self.pop()?);
+                self.memory(off, Uint::from(32))?
+                    .copy_from_slice(&to_word(value));
+            }
+            Mstore8 => {
+                let (off, value) = (self.pop()?, self.pop()?);
+                self.memory(off, Uint::one())?[0] = value.byte(0);
+            }
+            Sload => {
+                let key = U256::new(to_word(self.pop()?));
+                let value = self
+                    .state
+                    .storage(&self.address)
+                    .and_then(|storage| storage.get(&key).copied())
+                    .unwrap_or_default();
+                self.push(to_uint(&value.as_bytes()))?;
+            }
+            Sstore => {
+                let (key, value) = (self.pop()?, self.pop()?);
+                self.state.set_storage(
+                    self.address,
+                    U256::new(to_word(key)),
+                    U256::new(to_word(value)),
+                );
+            }
+            Tload => {
+                let key = self.pop()?;
+                let value = self.transient.get(&key).copied().unwrap_or_default();
+                self.push(value)?;
+            }
+            Tstore => {
+                let (key, value) = (self.pop()?, self.pop()?);
+                self.transient.insert(key, value);
+            }
+            Mcopy => {
+                let (dest, off, size) = (self.pop()?, self.pop()?, self.pop()?);
+                let bytes = self.memory(off, size)?.to_vec();
+                self.memory(dest, size)?.copy_from_slice(&bytes);
+            }
+            Jump => return Some(Flow::Jump(self.pop()?)),
+            Jumpi => {
+                let (dest, cond) = (self.pop()?, self.pop()?);
+                if !cond.is_zero() {
+                    return Some(Flow::Jump(dest));
+                }
+            }
+            Pc => self.push(Uint::from(instruction.pc))?,
+            Msize => self.push(Uint::from(self.memory.len()))?,
+            Jumpdest => {}
+            Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9
+            | Push10 | Push11 | Push12 | Push13 | Push14 | Push15 | Push16 | Push17 | Push18
+            | Push19 | Push20 | Push21 | Push22 | Push23 | Push24 | Push25 | Push26 | Push27
+            | Push28 | Push29 | Push30 | Push31 | Push32 => {
+                self.push(Uint::from_big_endian(instruction.pushes))?;
+            }
+            Dup1 | Dup2 | Dup3 | Dup4 | Dup5 | Dup6 | Dup7 | Dup8 | Dup9 | Dup10 | Dup11
+            | Dup12 | Dup13 | Dup14 | Dup15 | Dup16 => {
+                let n = op.dup_size().unwrap() as usize;
+                let value = *self.stack.iter().rev().nth(n - 1)?;
+                self.push(value)?;
+            }
+            Swap1 | Swap2 | Swap3 | Swap4 | Swap5 | Swap6 | Swap7 | Swap8 | Swap9 | Swap10
+            | Swap11 | Swap12 | Swap13 | Swap14 | Swap15 | Swap16 => {
+                let n = op.swap_size().unwrap() as usize;
+                let top = self.stack.len().checked_sub(1)?;
+                let other = top.checked_sub(n)?;
+                self.stack.swap(top, other);
+            }
+            Log0 | Log1 | Log2 | Log3 | Log4 => {
+                let (off, size) = (self.pop()?, self.pop()?);
+                for _ in 0..op.log_size().unwrap() {
+                    self.pop()?;
+                }
+                self.memory(off, size)?;
+            }
+            Return | Revert => {
+                let (off, size) = (self.pop()?, self.pop()?);
+                let data = self.memory(off, size)?.to_vec();
+                let outcome = if op.opcode() == &Return {
+                    Outcome::Return(data)
+                } else {
+                    Outcome::Revert(data)
+                };
+                return Some(Flow::Halt(outcome));
+            }
+            Selfdestruct => {
+                // the balance isn't followed, the frame stops as with STOP
+                self.pop()?;
+                return Some(Flow::Halt(Outcome::Stop));
+            }
+            Invalid => return None,
+            // the other accounts are unknown to the witness, and so is what calling them does.
+            // The gas left depends on costs the interpreter doesn't meter
+            Call | Callcode | Delegatecall | Staticcall | Create | Create2 | Extcodehash
+            | Extcodecopy | Gas => return Some(Flow::Halt(Outcome::Unsupported(instruction.pc))),
+            _ => return Some(Flow::Halt(Outcome::Unsupported(instruction.pc))),
+        }
+
+        Some(Flow::Next)
+    }
+}
+
+/// replay the witness of the branch `pid` concretely.
+/// It must go through the same pcs and end the same way as the symbolic path.
+pub fn validate(
+    code: &Mnemonics,
+    tree: &Tree,
+    pid: usize,
+    witness: &Witness,
+) -> Result<Execution, Mismatch> {
+    let mut state = State::new();
+    for (key, value) in &witness.storage {
+        state.set_storage(witness.address, U256::new(*key), U256::new(*value));
+    }
+    let env = Env {
+        caller: witness.caller,
+        value: U256::new(witness.value),
+        ..witness.env.clone()
+    };
+    let calldata = EVMCalldata::from(witness.calldata.clone());
+
+    let execution = Interpreter::new(code, witness.address, &calldata, &env, &mut state).execute();
+    if let Outcome::Unsupported(pc) = execution.outcome {
+        return Err(Mismatch::Unsupported(pc));
+    }
+
+    let expected = path_trace(tree, pid);
+    if execution.trace != expected {
+        return Err(Mismatch::Trace {
+            expected,
+            found: execution.trace,
+        });
+    }
+
+    let ret = tree
+        .get(&pid)
+        .and_then(|(_, steps, _)| steps.last())
+        .map(|step| step.ret());
+    match ret {
+        Some(ret) if ret.has_ret() && ret.is_success() != execution.outcome.is_success() => {
+            Err(Mismatch::Outcome {
+                expected_success: ret.is_success(),
+                found: execution.outcome,
+            })
+        }
+        _ => Ok(execution),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, bytecode::to_mnemonics, prover::Prover};
+
+    fn run(hex: &str, calldata: Vec<u8>) -> Execution {
+        let hex = hex::decode(hex).unwrap();
+        let code = to_mnemonics(&hex);
+        let calldata = EVMCalldata::from(calldata);
+        let env = Env::default();
+        let mut state = State::new();
+        Interpreter::new(&code, [0; 20], &calldata, &env, &mut state).execute()
+    }
+
+    #[test]
+    fn arithmetic() {
+        // return(0, 32) of mstore(0, 3 + 2)
+        let execution = run("600260030160005260205ff3", Vec::new());
+        assert_eq!(
+            execution.outcome,
+            Outcome::Return(to_word(Uint::from(5)).to_vec())
+        );
+        assert_eq!(execution.trace, vec![0, 2, 4, 5, 7, 8, 10, 11]);
+
+        // sdiv(-4, 2)
+        let execution = run("60027ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffc0560005260205ff3", Vec::new());
+        assert_eq!(
+            execution.outcome,
+            Outcome::Return(to_word(neg(Uint::from(2))).to_vec())
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        // jumps over an invalid opcode if calldata(0) is not zero
+        let execution = run("5f35600657fe5b00", vec![1]);
+        assert_eq!(execution.outcome, Outcome::Stop);
+        assert_eq!(execution.trace, vec![0, 1, 2, 4, 6, 7]);
+
+        let execution = run("5f35600657fe5b00", Vec::new());
+        assert_eq!(execution.outcome, Outcome::Invalid);
+
+        // not a jumpdest
+        let execution = run("600556", Vec::new());
+        assert_eq!(execution.outcome, Outcome::Invalid);
+    }
+
+    #[test]
+    fn memory_bound() {
+        // the last byte before the bound can be written, the next one runs out of gas
+        let hex = assemble("PUSH1 0x01 PUSH4 0x01ffffff MSTORE8 STOP").unwrap();
+        let execution = run(&hex::encode(hex), Vec::new());
+        assert_eq!(execution.outcome, Outcome::Stop);
+        let hex = assemble("PUSH1 0x01 PUSH4 0x02000000 MSTORE8 STOP").unwrap();
+        let execution = run(&hex::encode(hex), Vec::new());
+        assert_eq!(execution.outcome, Outcome::Invalid);
+
+        // a copy past the bound halts before building its bytes
+        let hex = assemble("PUSH4 0xffffffff PUSH0 PUSH0 CALLDATACOPY STOP").unwrap();
+        let execution = run(&hex::encode(hex), Vec::new());
+        assert_eq!(execution.outcome, Outcome::Invalid);
+    }
+
+    #[test]
+    fn validate_witness() {
+        // reverts unless calldata(0) is 0x2a and slot 1 is 7
+        let cfg = z3::Config::default();
+        let ctx = z3::Context::new(&cfg);
+        let hex = hex::decode("5f35602a14600154600714166012575f5ffd5b00").unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, ethabi::Contract::default());
+        let tree = prover.run().unwrap();
+
+        for pid in [0, 1] {
+            let witness = prover.witness(&tree, pid, None).unwrap();
+            let execution = validate(&code, &tree, pid, &witness).unwrap();
+            assert_eq!(execution.outcome.is_success(), pid == 1);
+        }
+    }
+
+    #[test]
+    fn validate_env() {
+        // stops only late enough, with a known previous block and a nonzero own address
+        let cfg = z3::Config::default();
+        let ctx = z3::Context::new(&cfg);
+        let hex = assemble(
+            "
+            PUSH2 0x03e8 TIMESTAMP GT
+            PUSH1 0x01 NUMBER SUB BLOCKHASH ISZERO ISZERO AND
+            ADDRESS ISZERO ISZERO AND
+            PUSH1 ok JUMPI PUSH0 PUSH0 REVERT
+            ok: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, ethabi::Contract::default());
+        let tree = prover.run().unwrap();
+
+        let pid = *tree
+            .keys()
+            .find(|pid| tree[pid].1.last().unwrap().ret().is_success())
+            .unwrap();
+        let witness = prover.witness(&tree, pid, None).unwrap();
+        assert!(witness.env.timestamp > 1000);
+        assert_ne!(witness.address, [0; 20]);
+        assert_eq!(witness.env.block_hashes.len(), 1);
+        let execution = validate(&code, &tree, pid, &witness).unwrap();
+        assert_eq!(execution.outcome, Outcome::Stop);
+
+        // the gas left isn't metered
+        let hex = assemble("GAS PUSH1 0x01 SSTORE STOP").unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, ethabi::Contract::default());
+        let tree = prover.run().unwrap();
+        let witness = prover.witness(&tree, 0, None).unwrap();
+        assert!(matches!(
+            validate(&code, &tree, 0, &witness),
+            Err(Mismatch::Unsupported(0))
+        ));
+    }
+
+    #[test]
+    fn validate_loop() {
+        // for (i = 0; i < calldata(0); i++) {}, the branches of the loop pass the same jumps
+        let cfg = z3::Config::default();
+        let ctx = z3::Context::new(&cfg);
+        let hex = assemble(
+            "
+            PUSH0 CALLDATALOAD PUSH0
+            loop: JUMPDEST DUP2 DUP2 LT ISZERO PUSH1 end JUMPI
+            PUSH1 0x01 ADD PUSH1 loop JUMP
+            end: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, ethabi::Contract::default());
+        let tree = prover.run().unwrap();
+
+        let exits: Vec<_> = tree
+            .keys()
+            .filter(|pid| tree[pid].1.last().unwrap().ret().is_success())
+            .collect();
+        assert!(exits.len() > 2);
+        for pid in exits {
+            let witness = prover.witness(&tree, *pid, None).unwrap();
+            let execution = validate(&code, &tree, *pid, &witness).unwrap();
+            assert_eq!(execution.outcome, Outcome::Stop);
+        }
+    }
+
+    #[test]
+    fn transient_and_mcopy() {
+        // tstore(0, 0x2a), mstore(0, tload(0)), mcopy(0x20, 0, 0x20), return(0x20, 0x20)
+        let hex = assemble(
+            "PUSH1 0x2a PUSH0 TSTORE PUSH0 TLOAD PUSH0 MSTORE \
+             PUSH1 0x20 PUSH0 PUSH1 0x20 MCOPY PUSH1 0x20 PUSH1 0x20 RETURN",
+        )
+        .unwrap();
+        let execution = run(&hex::encode(hex), Vec::new());
+        assert_eq!(
+            execution.outcome,
+            Outcome::Return(to_word(Uint::from(0x2a)).to_vec())
+        );
+    }
+}
@@ -1,17 +1,19 @@
-use self::bytecode::to_mnemonics;
-use crate::{fsm::gen_graph, prover::Prover};
-use ::z3::{Config, Context, SatResult};
-use ethabi::Contract;
-
+use crate::cli::{Args, USAGE};
+mod abi;
 mod analysis;
+mod asm;
 mod bytecode;
+mod cfg;
+mod cli;
 mod config;
 mod data;
 mod fsm;
 mod helpers;
+mod interpreter;
 mod opcodes;
 mod prover;
 mod utils;
+mod witness;
 mod z3;
 
 struct Function {
@@ -26,22 +28,17 @@ struct Function {
 
 // parallelize z3: https://stackoverflow.com/questions/53246030/parallel-solving-in-z3
 
-fn main() {
-    let code = [0x5F, 0x35, 0x60, 0xFF, 0x14];
-    let mnemonics = to_mnemonics(&code);
-    let cfg = Config::default();
-    let ctx = Context::new(&cfg);
-    let mut prover = Prover::new(&ctx, &mnemonics, Contract::default());
-    let tree = prover.run().unwrap();
-    dbg!(&tree);
+fn main() -> eyre::Result<()> {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let args = match Args::parse(&args) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
 
-    let sol = &tree[&0].0;
-    assert_eq!(sol.check(), SatResult::Sat, "Cannot be satisfied");
-    let assertions = sol
-        .get_assertions()
-        .into_iter()
-        .map(|a| format!("{:#?}", a))
-        .collect();
+    println!("{}", cli::run(&args)?);
 
-    gen_graph(assertions);
+    Ok(())
 }
@@ -1,5 +1,192 @@
+/// An opcode byte, decoded by the rules of an evm revision
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub struct OpCode(u8);
+pub struct OpCode(u8, Revision);
+
+/// Hard forks of the evm which changed its instruction set, named after solc's `evmVersion`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Revision {
+    Frontier,
+    Homestead,
+    TangerineWhistle,
+    SpuriousDragon,
+    Byzantium,
+    Constantinople,
+    Petersburg,
+    Istanbul,
+    Berlin,
+    London,
+    Paris,
+    Shanghai,
+    #[default]
+    Cancun,
+}
+
+impl Revision {
+    pub const ALL: [Revision; 13] = [
+        Revision::Frontier,
+        Revision::Homestead,
+        Revision::TangerineWhistle,
+        Revision::SpuriousDragon,
+        Revision::Byzantium,
+        Revision::Constantinople,
+        Revision::Petersburg,
+        Revision::Istanbul,
+        Revision::Berlin,
+        Revision::London,
+        Revision::Paris,
+        Revision::Shanghai,
+        Revision::Cancun,
+    ];
+
+    /// first revision in which `opcode` is defined
+    pub fn introducing(opcode: u8) -> Revision {
+        use Revision::*;
+
+        match opcode {
+            0xf4 => Homestead,
+            0x3d | 0x3e | 0xfa | 0xfd => Byzantium,
+            0x1b..=0x1d | 0x3f | 0xf5 => Constantinople,
+            0x46 | 0x47 => Istanbul,
+            0x48 => London,
+            0x5f => Shanghai,
+            0x49 | 0x4a | 0x5c..=0x5e => Cancun,
+            _ => Frontier,
+        }
+    }
+
+    /// whether `opcode` is part of the instruction set, undefined ones execute as INVALID
+    pub fn defines(&self, opcode: u8) -> bool {
+        *self >= Self::introducing(opcode) && OPCODE_JUMPMAP[opcode as usize] != OpCodes::Invalid
+    }
+
+    /// lowercase camel case name, as in solc's `evmVersion`
+    pub fn name(&self) -> String {
+        let name = format!("{self:?}");
+        name[..1].to_lowercase() + &name[1..]
+    }
+
+    pub fn from_name(name: &str) -> Option<Revision> {
+        Self::ALL
+            .into_iter()
+            .find(|revision| revision.name().eq_ignore_ascii_case(name))
+    }
+
+    /// gas charged for `opcode` whatever its operands, the rest is given by `gas_costs`
+    pub fn static_gas(&self, opcode: &OpCodes) -> u64 {
+        use OpCodes::*;
+        use Revision::*;
+
+        match opcode {
+            Invalid | Stop | Return | Revert | Sstore => 0,
+            Jumpdest => 1,
+            Address | Origin | Caller | Callvalue | Calldatasize | Codesize | Gasprice
+            | Returndatasize | Coinbase | Timestamp | Number | Difficulty | Gaslimit | Chainid
+            | Basefee | Blobbasefee | Pop | Pc | Msize | Gas | Push0 => 2,
+            Add | Sub | Not | Lt | Gt | Slt | Sgt | Eq | Iszero | And | Or | Xor | Byte | Shl
+            | Shr | Sar | Calldataload | Mload | Mstore | Mstore8 | Calldatacopy | Codecopy
+            | Returndatacopy | Mcopy | Blobhash => 3,
+            Mul | Div | Sdiv | Mod | Smod | Signextend | Selfbalance => 5,
+            Addmod | Mulmod | Jump => 8,
+            Exp | Jumpi => 10,
+            Blockhash => 20,
+            Sha3 => 30,
+            Tload | Tstore => 100,
+            Log0 | Log1 | Log2 | Log3 | Log4 => 375,
+            Create | Create2 => 32000,
+            Balance => match self {
+                Frontier | Homestead => 20,
+                TangerineWhistle | SpuriousDragon | Byzantium | Constantinople | Petersburg => 400,
+                Istanbul => 700,
+                _ => 100,
+            },
+            Extcodesize | Extcodecopy => match self {
+                Frontier | Homestead => 20,
+                TangerineWhistle | SpuriousDragon | Byzantium | Constantinople | Petersburg
+                | Istanbul => 700,
+                _ => 100,
+            },
+            Extcodehash => match self {
+                Constantinople | Petersburg => 400,
+                Istanbul => 700,
+                _ => 100,
+            },
+            Sload => match self {
+                Frontier | Homestead => 50,
+                TangerineWhistle | SpuriousDragon | Byzantium | Constantinople | Petersburg => 200,
+                Istanbul => 800,
+                _ => 100,
+            },
+            Call | Callcode | Delegatecall | Staticcall => match self {
+                Frontier | Homestead => 40,
+                TangerineWhistle | SpuriousDragon | Byzantium | Constantinople | Petersburg
+                | Istanbul => 700,
+                _ => 100,
+            },
+            Selfdestruct => match self {
+                Frontier | Homestead => 0,
+                _ => 5000,
+            },
+            _ => 3,
+        }
+    }
+
+    /// gas charged depending on the operands and the state
+    pub fn gas_costs(&self) -> GasCosts {
+        use Revision::*;
+
+        let berlin = *self >= Berlin;
+        GasCosts {
+            memory_word: 3,
+            memory_quad: 512,
+            copy_word: 3,
+            sha3_word: 6,
+            log_topic: 375,
+            log_byte: 8,
+            exp_byte: if *self >= SpuriousDragon { 50 } else { 10 },
+            cold_account: if berlin { 2500 } else { 0 },
+            cold_slot: if berlin { 2000 } else { 0 },
+            sstore_set: 20000,
+            sstore_reset: if berlin { 2900 } else { 5000 },
+            sstore_dirty: match self {
+                Istanbul => Some(800),
+                _ if berlin => Some(100),
+                _ => None,
+            },
+            call_value: 9000,
+            initcode_word: if *self >= Shanghai { 2 } else { 0 },
+        }
+    }
+}
+
+/// Dynamic gas costs of a revision
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasCosts {
+    /// a memory of `w` words costs `memory_word * w + w * w / memory_quad`
+    pub memory_word: u64,
+    pub memory_quad: u64,
+    /// per word copied by the copy instructions
+    pub copy_word: u64,
+    /// per word hashed by SHA3 and CREATE2
+    pub sha3_word: u64,
+    pub log_topic: u64,
+    pub log_byte: u64,
+    /// per byte of the exponent of EXP
+    pub exp_byte: u64,
+    /// first access to an account or a storage slot in the transaction (EIP-2929),
+    /// on top of the static gas of the instruction. SSTORE pays the one of SLOAD too.
+    pub cold_account: u64,
+    pub cold_slot: u64,
+    /// SSTORE of a non-zero value to a zero slot
+    pub sstore_set: u64,
+    /// any other SSTORE changing a slot left as it was before the transaction
+    pub sstore_reset: u64,
+    /// SSTORE which leaves a slot unchanged or changes it once more, net metering (EIP-2200)
+    pub sstore_dirty: Option<u64>,
+    /// call sending a non-zero value
+    pub call_value: u64,
+    /// per word of the init code of a new contract (EIP-3860)
+    pub initcode_word: u64,
+}
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum OpCodes {
@@ -56,6 +243,8 @@ pub enum OpCodes {
     Chainid,
     Selfbalance,
     Basefee,
+    Blobhash,
+    Blobbasefee,
     Pop,
     Mload,
     Mstore,
@@ -68,6 +257,9 @@ pub enum OpCodes {
     Msize,
     Gas,
     Jumpdest,
+    Tload,
+    Tstore,
+    Mcopy,
     Push0,
     Push1,
     Push2,
@@ -149,13 +341,111 @@ pub enum OpCodes {
     Selfdestruct,
 }
 
+impl OpCodes {
+    /// number of stack items taken and pushed back
+    pub fn stack_io(&self) -> (u8, u8) {
+        use OpCodes::*;
+
+        match self {
+            Invalid | Stop | Jumpdest => (0, 0),
+            Add | Mul | Sub | Div | Sdiv | Mod | Smod | Exp | Signextend | Lt | Gt | Slt | Sgt
+            | Eq | And | Or | Xor | Byte | Shl | Shr | Sar | Sha3 => (2, 1),
+            Addmod | Mulmod => (3, 1),
+            Iszero | Not | Balance | Calldataload | Extcodesize | Extcodehash | Blockhash
+            | Mload | Sload | Tload | Blobhash => (1, 1),
+            Address | Origin | Caller | Callvalue | Calldatasize | Codesize | Gasprice
+            | Returndatasize | Coinbase | Timestamp | Number | Difficulty | Gaslimit | Chainid
+            | Selfbalance | Basefee | Blobbasefee | Pc | Msize | Gas => (0, 1),
+            Calldatacopy | Codecopy | Returndatacopy | Mcopy => (3, 0),
+            Extcodecopy => (4, 0),
+            Pop | Jump | Selfdestruct => (1, 0),
+            Mstore | Mstore8 | Sstore | Tstore | Jumpi | Return | Revert => (2, 0),
+            Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9
+            | Push10 | Push11 | Push12 | Push13 | Push14 | Push15 | Push16 | Push17 | Push18
+            | Push19 | Push20 | Push21 | Push22 | Push23 | Push24 | Push25 | Push26 | Push27
+            | Push28 | Push29 | Push30 | Push31 | Push32 => (0, 1),
+            Dup1 => (1, 2),
+            Dup2 => (2, 3),
+            Dup3 => (3, 4),
+            Dup4 => (4, 5),
+            Dup5 => (5, 6),
+            Dup6 => (6, 7),
+            Dup7 => (7, 8),
+            Dup8 => (8, 9),
+            Dup9 => (9, 10),
+            Dup10 => (10, 11),
+            Dup11 => (11, 12),
+            Dup12 => (12, 13),
+            Dup13 => (13, 14),
+            Dup14 => (14, 15),
+            Dup15 => (15, 16),
+            Dup16 => (16, 17),
+            Swap1 => (2, 2),
+            Swap2 => (3, 3),
+            Swap3 => (4, 4),
+            Swap4 => (5, 5),
+            Swap5 => (6, 6),
+            Swap6 => (7, 7),
+            Swap7 => (8, 8),
+            Swap8 => (9, 9),
+            Swap9 => (10, 10),
+            Swap10 => (11, 11),
+            Swap11 => (12, 12),
+            Swap12 => (13, 13),
+            Swap13 => (14, 14),
+            Swap14 => (15, 15),
+            Swap15 => (16, 16),
+            Swap16 => (17, 17),
+            Log0 => (2, 0),
+            Log1 => (3, 0),
+            Log2 => (4, 0),
+            Log3 => (5, 0),
+            Log4 => (6, 0),
+            Create => (3, 1),
+            Create2 => (4, 1),
+            Call | Callcode => (7, 1),
+            Delegatecall | Staticcall => (6, 1),
+        }
+    }
+
+    /// halts the execution of the frame
+    pub fn is_terminator(&self) -> bool {
+        use OpCodes::*;
+
+        matches!(self, Stop | Return | Revert | Invalid | Selfdestruct)
+    }
+}
+
 impl OpCode {
+    /// decode `opcode` with the instruction set of the latest revision
     pub fn from_u8(opcode: u8) -> OpCode {
-        OpCode(opcode)
+        OpCode(opcode, Revision::default())
+    }
+
+    pub fn with_revision(opcode: u8, revision: Revision) -> OpCode {
+        OpCode(opcode, revision)
+    }
+
+    pub fn revision(&self) -> Revision {
+        self.1
+    }
+
+    /// gas charged for the instruction whatever its operands
+    pub fn static_gas(&self) -> u64 {
+        self.1.static_gas(self.opcode())
+    }
+
+    /// whether the opcode exists in its revision
+    pub fn is_defined(&self) -> bool {
+        self.1.defines(self.0)
     }
 
+    /// the instruction, INVALID if it is not defined in the revision
     pub fn opcode(&self) -> &OpCodes {
-        OPCODE_JUMPMAP.get(self.0 as usize).unwrap()
+        match self.is_defined() {
+            true => &OPCODE_JUMPMAP[self.0 as usize],
+            false => &OpCodes::Invalid,
+        }
     }
 
     #[inline(always)]
@@ -163,8 +453,23 @@ impl OpCode {
         self.0
     }
 
+    /// uppercase mnemonic, or the byte itself for unassigned opcodes
+    pub fn name(&self) -> String {
+        match self.opcode() {
+            OpCodes::Invalid if self.0 != 0xfe => format!("{:#04x}", self.0),
+            opcode => format!("{opcode:?}").to_uppercase(),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<OpCode> {
+        let name = name.to_uppercase();
+        (0..=u8::MAX)
+            .map(OpCode::from_u8)
+            .find(|op| op.name() == name)
+    }
+
     pub fn is_push(&self) -> bool {
-        self.0 >= 95 && self.0 < 128
+        self.0 >= 95 && self.0 < 128 && self.is_defined()
     }
 
     pub fn is_dup(&self) -> bool {
@@ -199,6 +504,19 @@ impl OpCode {
             None
         }
     }
+
+    pub fn is_log(&self) -> bool {
+        self.0 >= 160 && self.0 < 165
+    }
+
+    /// number of topics of the log
+    pub fn log_size(&self) -> Option<u8> {
+        if self.is_log() {
+            Some(self.0 - 160)
+        } else {
+            None
+        }
+    }
 }
 
 pub const OPCODE_JUMPMAP: [OpCodes; 256] = [
@@ -275,8 +593,8 @@ pub const OPCODE_JUMPMAP: [OpCodes; 256] = [
     /* 0x46 */ OpCodes::Chainid,
     /* 0x47 */ OpCodes::Selfbalance,
     /* 0x48 */ OpCodes::Basefee,
-    /* 0x49 */ OpCodes::Invalid,
-    /* 0x4a */ OpCodes::Invalid,
+    /* 0x49 */ OpCodes::Blobhash,
+    /* 0x4a */ OpCodes::Blobbasefee,
     /* 0x4b */ OpCodes::Invalid,
     /* 0x4c */ OpCodes::Invalid,
     /* 0x4d */ OpCodes::Invalid,
@@ -294,9 +612,9 @@ pub const OPCODE_JUMPMAP: [OpCodes; 256] = [
     /* 0x59 */ OpCodes::Msize,
     /* 0x5a */ OpCodes::Gas,
     /* 0x5b */ OpCodes::Jumpdest,
-    /* 0x5c */ OpCodes::Invalid,
-    /* 0x5d */ OpCodes::Invalid,
-    /* 0x5e */ OpCodes::Invalid,
+    /* 0x5c */ OpCodes::Tload,
+    /* 0x5d */ OpCodes::Tstore,
+    /* 0x5e */ OpCodes::Mcopy,
     /* 0x5f */ OpCodes::Push0,
     /* 0x60 */ OpCodes::Push1,
     /* 0x61 */ OpCodes::Push2,
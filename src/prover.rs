@@ -1,20 +1,75 @@
 use crate::{
-    analysis::get_jumpdest,
-    bytecode::{Mnemonic, Mnemonics},
-    data::{EVMMemory, EVMStack},
-    helpers::{bool_to_bv, is_zero, to_bv, RevertReason},
-    opcodes::OpCodes::*,
+    abi::SymbolicCall,
+    bytecode::{to_mnemonics_for, Mnemonic, Mnemonics},
+    cfg::{Cfg, StackAnalysis},
+    config::Config,
+    data::{
+        EVMGas, EVMKeccak, EVMMemory, EVMStack, EVMStorage, EVMTransientStorage, MemoryModel,
+        ReturnData,
+    },
+    helpers::{
+        bool_to_bv, bv_to_bytes, bv_to_word, bytes_to_bv, is_zero, keccak256, rlp_u64, to_address,
+        to_bv, Address, Env, RevertReason, State, Word, U256,
+    },
+    opcodes::{OpCodes, OpCodes::*, Revision},
+    witness::{decode_call, Witness},
 };
 use ethabi::Contract;
-use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
-use z3::{ast::Ast, Context, SatResult, Solver};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    rc::Rc,
+};
+use z3::{ast::Ast, Context, Model, SatResult, Solver};
+
+/// Deepest nested frame that gets inlined, calls below are treated as unknown
+const MAX_CALL_DEPTH: usize = 4;
+/// Largest calldata of a witness
+const MAX_WITNESS_CALLDATA: u64 = 1024;
+/// Largest copy of a symbolic size, the paths copying more are not explored
+const MAX_SYMBOLIC_COPY: u32 = 256;
+/// Most models discarded for disagreeing with keccak256 before giving up on a path
+const MAX_KECCAK_ROUNDS: usize = 8;
+/// Most jumps of a path to the same destination within the same internal function calls,
+/// unrolling its loops that many times
+const MAX_UNROLL: usize = 4;
+
+/// jumps taken by a path, by destination and the return addresses on the stack when jumping
+type Visits = BTreeMap<(u64, BTreeSet<u64>), usize>;
 
 pub struct Prover<'a, 'ctx> {
     ctx: &'ctx Context,
     sol: Solver<'ctx>,
     code: &'a Mnemonics<'a>,
+    cfg: Cfg,
+    /// stacks of the blocks, resolving the targets of internal function returns
+    stack: StackAnalysis,
+    /// JUMPDESTs pushed by the callers of internal functions
+    return_addresses: BTreeSet<u64>,
     abi: Contract,
     sym: Symbolic<'ctx>,
+    /// storage of the contract before the execution, symbolic if unset
+    storage: Option<EVMStorage<'ctx>>,
+    /// transient storage at the start of the frame, empty as a new transaction if unset
+    transient: Option<EVMTransientStorage<'ctx>>,
+    /// storage of the other accounts at the start of the frame, as left by the earlier calls
+    accounts: BTreeMap<Address, EVMStorage<'ctx>>,
+    /// known accounts, calls to their code are executed as nested frames
+    state: Option<&'a State>,
+    /// call depth of the executed frame
+    depth: usize,
+    /// the frame runs inside a STATICCALL, changing the state reverts it
+    is_static: bool,
+    /// concrete address of the executing account, symbolic if unset
+    address: Option<Address>,
+    /// instruction set of the code, nested frames decode theirs with it
+    revision: Revision,
+    /// block environment, shared with the nested frames
+    config: Config,
+    memory_model: MemoryModel,
+    /// most jumps of a path to the same destination within the same internal function calls,
+    /// the next ones are not followed
+    max_unroll: usize,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -23,40 +78,106 @@ pub struct Ret<'ctx> {
     ret: bool,
     /// wether it reverted or not
     rev: bool,
+    /// pc of the instruction the path stopped at, with an operand the prover doesn't support
+    unsupported: Option<usize>,
+    /// a jump of the path was not followed, its destination being visited too many times
+    bounded: bool,
 }
 
-impl Ret<'_> {
+impl<'ctx> Ret<'ctx> {
     pub fn has_ret(&self) -> bool {
         self.ret || self.rev
     }
+
+    /// the execution halted without reverting
+    pub fn is_success(&self) -> bool {
+        self.ret && !self.rev
+    }
+
+    /// the path stopped at the instruction at this pc, without halting
+    pub fn unsupported(&self) -> Option<usize> {
+        self.unsupported
+    }
+
+    /// the path reached the unroll bound, some of its continuations are left out
+    pub fn bounded(&self) -> bool {
+        self.bounded
+    }
+
+    /// what the caller of this frame sees as returndata
+    pub fn returndata(&self, ctx: &'ctx Context) -> ReturnData<'ctx> {
+        ReturnData::from_bytes(ctx, self.val.as_ref())
+    }
+}
+
+/// An event emitted by LOG0..LOG4
+#[derive(Debug, Clone)]
+pub struct Log<'ctx> {
+    pub topics: Vec<z3::ast::BV<'ctx>>,
+    /// data read from memory, `None` if empty
+    pub data: Option<z3::ast::BV<'ctx>>,
+}
+
+impl<'ctx> Log<'ctx> {
+    /// the raw log if its topics and data are all concrete
+    pub fn to_raw(&self) -> Option<ethabi::RawLog> {
+        let topics = self
+            .topics
+            .iter()
+            .map(|topic| bv_to_word(topic).map(ethabi::Hash::from))
+            .collect::<Option<Vec<_>>>()?;
+        let data = match &self.data {
+            Some(data) => bv_to_bytes(data)?,
+            None => Vec::new(),
+        };
+
+        Some(ethabi::RawLog { topics, data })
+    }
 }
 
 #[derive(Debug)]
 pub struct Symbolic<'ctx> {
-    calldata: z3::FuncDecl<'ctx>,
+    /// start of the name of every symbol, empty for the outermost frame
+    prefix: String,
+    /// bytes of the calldata by index, the ones past `calldatasize` are ignored
+    calldata: z3::ast::Array<'ctx>,
     value: z3::FuncDecl<'ctx>,
     address: z3::FuncDecl<'ctx>,
     caller: z3::FuncDecl<'ctx>,
     origin: z3::FuncDecl<'ctx>,
     balance_of: z3::FuncDecl<'ctx>,
     calldatasize: z3::FuncDecl<'ctx>,
+    /// size and hash of the code of an account
     codesize: z3::FuncDecl<'ctx>,
+    codehash: z3::FuncDecl<'ctx>,
     gasprice: z3::FuncDecl<'ctx>,
+    /// gas available to the frame
+    gas: z3::FuncDecl<'ctx>,
 }
 
 impl<'ctx> Symbolic<'ctx> {
-    #[rustfmt::skip]
     pub fn new(ctx: &'ctx Context) -> Self {
+        Self::with_prefix(ctx, "")
+    }
+
+    /// symbols of a nested frame, so that they don't collide with the ones of its caller
+    #[rustfmt::skip]
+    pub fn with_prefix(ctx: &'ctx Context, prefix: &str) -> Self {
+        let name = |name: &str| format!("{prefix}{name}");
+
         Self {
-            calldata: z3::FuncDecl::new(ctx, "calldata", &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
-            value: z3::FuncDecl::new(ctx, "value", &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
-            caller: z3::FuncDecl::new(ctx, "caller", &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
-            origin: z3::FuncDecl::new(ctx, "origin", &[], &z3::Sort::bitvector(ctx, 256)),
-            address: z3::FuncDecl::new(ctx, "address", &[], &z3::Sort::bitvector(ctx, 256)),
-            balance_of: z3::FuncDecl::new(ctx, "balance_of", &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
-            calldatasize: z3::FuncDecl::new(ctx, "calldatasize", &[], &z3::Sort::bitvector(ctx, 256)),
-            codesize: z3::FuncDecl::new(ctx, "codesize", &[], &z3::Sort::bitvector(ctx, 256)),
-            gasprice: z3::FuncDecl::new(ctx, "gasprice", &[], &z3::Sort::bitvector(ctx, 256)),
+            prefix: prefix.to_string(),
+            calldata: z3::ast::Array::new_const(ctx, name("calldata"), &z3::Sort::bitvector(ctx, 256), &z3::Sort::bitvector(ctx, 8)),
+            value: z3::FuncDecl::new(ctx, name("value"), &[], &z3::Sort::bitvector(ctx, 256)),
+            caller: z3::FuncDecl::new(ctx, name("caller"), &[], &z3::Sort::bitvector(ctx, 256)),
+            origin: z3::FuncDecl::new(ctx, name("origin"), &[], &z3::Sort::bitvector(ctx, 256)),
+            address: z3::FuncDecl::new(ctx, name("address"), &[], &z3::Sort::bitvector(ctx, 256)),
+            balance_of: z3::FuncDecl::new(ctx, name("balance_of"), &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
+            calldatasize: z3::FuncDecl::new(ctx, name("calldatasize"), &[], &z3::Sort::bitvector(ctx, 256)),
+            codesize: z3::FuncDecl::new(ctx, name("codesize"), &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
+            codehash: z3::FuncDecl::new(ctx, name("codehash"), &[&z3::Sort::bitvector(ctx, 256)], &z3::Sort::bitvector(ctx, 256)),
+            gasprice: z3::FuncDecl::new(ctx, name("gasprice"), &[], &z3::Sort::bitvector(ctx, 256)),
+            gas: z3::FuncDecl::new(ctx, name("gas"), &[], &z3::Sort::bitvector(ctx, 256)),
         }
     }
 }
@@ -67,84 +188,405 @@ pub struct Step<'a, 'ctx> {
     op: Mnemonic<'a>,
     stack: EVMStack<'ctx>,
     memory: EVMMemory<'ctx>,
+    storage: EVMStorage<'ctx>,
+    transient: EVMTransientStorage<'ctx>,
     ret: Ret<'ctx>,
+    /// output of the last message call
+    returndata: ReturnData<'ctx>,
+    /// number of message calls made so far
+    calls: usize,
+    /// number of contracts created so far
+    creates: usize,
+    /// runtime code of the contracts created on this path, by address
+    deployed: Vec<(z3::ast::BV<'ctx>, Vec<u8>)>,
+    /// storage of the other known accounts, as left by the calls of this path
+    accounts: BTreeMap<Address, EVMStorage<'ctx>>,
+    /// events emitted on this path so far
+    logs: Vec<Log<'ctx>>,
+    /// block hashes read on this path, with the number of their block
+    block_hashes: Vec<(z3::ast::BV<'ctx>, z3::ast::BV<'ctx>)>,
+    /// keccak256 computed on this path so far
+    keccak: EVMKeccak<'ctx>,
+    gas: EVMGas<'ctx>,
+    /// constraints added by this step, asserted on the path
+    constraints: Vec<z3::ast::Bool<'ctx>>,
+}
+
+impl<'a, 'ctx> Step<'a, 'ctx> {
+    pub fn pc(&self) -> usize {
+        self.op.pc
+    }
+
+    pub fn storage(&self) -> &EVMStorage<'ctx> {
+        &self.storage
+    }
+
+    pub fn transient(&self) -> &EVMTransientStorage<'ctx> {
+        &self.transient
+    }
+
+    pub fn ret(&self) -> &Ret<'ctx> {
+        &self.ret
+    }
+
+    pub fn logs(&self) -> &[Log<'ctx>] {
+        &self.logs
+    }
+
+    /// gas used up to this step included
+    pub fn gas_used(&self) -> z3::ast::BV<'ctx> {
+        let costs = self.op.op.revision().gas_costs();
+        self.gas.used(&self.memory, &costs)
+    }
+}
+
+/// Where a branch was created from its parent
+#[derive(Debug, Clone)]
+pub struct Fork<'ctx> {
+    pub parent: usize,
+    /// number of steps of the parent on the path to this branch, the jump included
+    /// unless the branch ends at it
+    pub step: usize,
+    /// condition for the jump to land on this branch
+    pub cond: z3::ast::Bool<'ctx>,
 }
 
 /// The full set of steps indexed by their branch id
-pub type Tree<'a, 'ctx> = BTreeMap<usize, (Solver<'ctx>, Vec<Step<'a, 'ctx>>)>;
+pub type Tree<'a, 'ctx> = BTreeMap<usize, (Solver<'ctx>, Vec<Step<'a, 'ctx>>, Option<Fork<'ctx>>)>;
+
+/// pcs executed from the start of the execution to the end of the branch `pid`
+pub fn path_trace<'a, 'ctx>(tree: &Tree<'a, 'ctx>, pid: usize) -> Vec<usize> {
+    let mut segments = Vec::new();
+    let (mut current, mut until) = (pid, None);
+
+    // walk up to the main branch, each parent only counts until the fork
+    while let Some((_, steps, fork)) = tree.get(&current) {
+        let end = until.map_or(steps.len(), |until| steps.len().min(until));
+        let pcs = steps[..end].iter().map(|step| step.pc());
+        segments.push(pcs.collect::<Vec<_>>());
+
+        match fork {
+            Some(fork) => {
+                current = fork.parent;
+                until = Some(fork.step);
+            }
+            None => break,
+        }
+    }
+
+    segments.into_iter().rev().flatten().collect()
+}
 
-// lifetime of the prover should outlive its context
-impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
+/// events emitted from the start of the execution to the end of the branch `pid`
+pub fn path_logs<'t, 'a, 'ctx>(tree: &'t Tree<'a, 'ctx>, pid: usize) -> &'t [Log<'ctx>] {
+    tree.get(&pid)
+        .and_then(|(_, steps, _)| steps.last())
+        .map_or(&[], |step| step.logs())
+}
+
+// lifetime of the context should outlive the prover
+impl<'a, 'ctx: 'a> Prover<'a, 'ctx> {
     pub fn new(ctx: &'ctx Context, code: &'a Mnemonics, abi: Contract) -> Self {
         let sym = Symbolic::new(ctx);
         let sol = Solver::new(ctx);
+        let mut cfg = Cfg::new(code);
+        let stack = cfg.analyze_stack(code);
+        let return_addresses = cfg.return_addresses(code);
+        let revision = code
+            .first()
+            .map_or_else(Revision::default, |instruction| instruction.op.revision());
 
         Self {
             ctx,
             sol,
             code,
+            cfg,
+            stack,
+            return_addresses,
             abi,
             sym,
+            storage: None,
+            transient: None,
+            accounts: BTreeMap::new(),
+            state: None,
+            depth: 0,
+            is_static: false,
+            address: None,
+            revision,
+            config: Config::default(),
+            memory_model: MemoryModel::default(),
+            max_unroll: MAX_UNROLL,
         }
     }
 
-    /// run the solver constraining algo for the given evm mnemonics.
-    /// throw with a "RevertReason" in the case of the main thread having an issue.
-    pub fn run(&'a self) -> Result<Tree<'a, 'ctx>, RevertReason> {
-        let jdest = get_jumpdest(self.code.to_vec());
+    /// set the concrete storage the execution starts from
+    pub fn set_storage(&mut self, storage: BTreeMap<Word, Word>) {
+        self.storage = Some(EVMStorage::from_words(self.ctx, &storage));
+    }
 
-        let stack = EVMStack::new();
-        let memory = EVMMemory::new(self.ctx);
-        // TODO: extract symbolic calldata from abi
+    /// set the known accounts, calls to their code will be followed
+    pub fn set_state(&mut self, state: &'a State) {
+        self.state = Some(state);
+    }
+
+    /// set the block environment, the variables it leaves unset are symbolic
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// set the representation of the memory, of this frame and the nested ones
+    pub fn set_memory_model(&mut self, model: MemoryModel) {
+        self.memory_model = model;
+    }
+
+    /// set how many times a path may jump to the same destination within the same internal
+    /// function calls, unrolling its loops
+    pub fn set_max_unroll(&mut self, max_unroll: usize) {
+        self.max_unroll = max_unroll;
+    }
+
+    /// set the concrete address of the executing account
+    pub fn set_address(&mut self, address: Address) {
+        self.address = Some(address);
+    }
 
+    /// bind the calldata of the frame to the concatenated `bytes`, empty if `None`
+    pub fn constrain_calldata(&self, bytes: Option<&z3::ast::BV<'ctx>>) {
+        let ctx = self.ctx;
+        let len = bytes.map_or(0, |bytes| bytes.get_size() / 8);
+        let size = self.sym.calldatasize.apply(&[]).as_bv().unwrap();
+        self.sol
+            .assert(&size._eq(&z3::ast::BV::from_u64(ctx, len.into(), 256)));
+
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => return,
+        };
+
+        for off in 0..len {
+            let high = (len - off) * 8 - 1;
+            let byte = self.calldata_byte(&z3::ast::BV::from_u64(ctx, off.into(), 256));
+            self.sol.assert(&byte._eq(&bytes.extract(high, high - 7)));
+        }
+    }
+
+    /// only explore the calls whose calldata starts with `selector`
+    pub fn constrain_selector(&self, selector: u32) {
+        let ctx = self.ctx;
+        let size = self.sym.calldatasize.apply(&[]).as_bv().unwrap();
+
+        for (i, byte) in selector.to_be_bytes().into_iter().enumerate() {
+            let index = z3::ast::BV::from_u64(ctx, i as u64, 256);
+            let load = self.sym.calldata.select(&index).as_bv().unwrap();
+            self.sol
+                .assert(&load._eq(&z3::ast::BV::from_u64(ctx, byte.into(), 8)));
+        }
+        self.sol
+            .assert(&size.bvuge(&z3::ast::BV::from_u64(ctx, 4, 256)));
+    }
+
+    /// byte of the calldata at `index`, zero past its end
+    fn calldata_byte(&self, index: &z3::ast::BV<'ctx>) -> z3::ast::BV<'ctx> {
+        let size = self.sym.calldatasize.apply(&[]).as_bv().unwrap();
+        let byte = self.sym.calldata.select(index).as_bv().unwrap();
+        index
+            .bvult(&size)
+            .ite(&byte, &z3::ast::BV::from_u64(self.ctx, 0, 8))
+    }
+
+    /// `size` bytes of the calldata from `off`, concatenated
+    fn calldata_bytes(&self, off: &z3::ast::BV<'ctx>, size: u32) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        (0..size)
+            .map(|i| {
+                let index = off.bvadd(&z3::ast::BV::from_u64(ctx, i.into(), 256));
+                // an offset wrapping around the word size is past the end too
+                let wrapped = index.bvult(off);
+                wrapped.ite(
+                    &z3::ast::BV::from_u64(ctx, 0, 8),
+                    &self.calldata_byte(&index),
+                )
+            })
+            .reduce(|bytes, byte| bytes.concat(&byte))
+            .expect("at least a byte is read")
+            .simplify()
+    }
+
+    /// constrain the caller and the value of the transaction with the config, on the solver
+    /// of a run so that the ones of the prover are left as they were
+    fn constrain_transaction(&self, sol: &Solver<'ctx>) {
+        let ctx = self.ctx;
+        let config = &self.config;
+        let caller = self.sym.caller.apply(&[]).as_bv().unwrap();
+        let value = self.sym.value.apply(&[]).as_bv().unwrap();
+        let is_caller = |account: &Address| caller._eq(&to_bv(ctx, account));
+
+        // the calldata fits in a block
+        let size = self.sym.calldatasize.apply(&[]).as_bv().unwrap();
+        sol.assert(&size.bvult(&z3::ast::BV::from_u64(ctx, u32::MAX.into(), 256)));
+        // and so does the gas of the transaction
+        let gas = self.sym.gas.apply(&[]).as_bv().unwrap();
+        let limit = config.gas_limit.unwrap_or(u64::MAX);
+        sol.assert(&gas.bvule(&z3::ast::BV::from_u64(ctx, limit, 256)));
+        // the caller is an account
+        sol.assert(
+            &caller
+                .extract(255, 160)
+                ._eq(&z3::ast::BV::from_u64(ctx, 0, 96)),
+        );
+        if let Some(account) = &config.caller {
+            sol.assert(&is_caller(account));
+        }
+        if !config.actors.is_empty() {
+            let actors: Vec<_> = config.actors.iter().map(is_caller).collect();
+            sol.assert(&z3::ast::Bool::or(ctx, &actors.iter().collect::<Vec<_>>()));
+        }
+
+        if let Some(pinned) = &config.value {
+            sol.assert(&value._eq(&to_bv(ctx, &pinned.as_bytes())));
+        }
+        if config.value_in_balance {
+            for account in config.caller.iter().chain(&config.actors) {
+                if let Some(balance) = self.state.and_then(|state| state.balance(account)) {
+                    let affordable = value.bvule(&to_bv(ctx, &balance.as_bytes()));
+                    sol.assert(&is_caller(account).implies(&affordable));
+                }
+            }
+        }
+    }
+
+    /// the abi event of a log, found from its topic0 when it is concrete
+    pub fn event(&self, log: &Log<'ctx>) -> Option<&ethabi::Event> {
+        let topic0 = bv_to_word(log.topics.first()?)?;
+        self.abi
+            .events()
+            .find(|event| !event.anonymous && event.signature() == ethabi::Hash::from(topic0))
+    }
+
+    /// decode a log against the abi, its topics and data must be concrete
+    pub fn decode_log(&self, log: &Log<'ctx>) -> Option<ethabi::Log> {
+        self.event(log)?.parse_log(log.to_raw()?).ok()
+    }
+
+    /// run the solver constraining algo for the given evm mnemonics.
+    /// A branch, the main one included, ends on its error: reverted on an exceptional halt,
+    /// or kept with the pc of an unsupported operand.
+    pub fn run(&'a self) -> Result<Tree<'a, 'ctx>, RevertReason> {
         let (tree, _p) = self.walk()?;
 
         // output the final solver with constraints
         Ok(tree)
     }
 
+    /// explore every function of the abi, each one with a well-formed symbolic calldata
+    pub fn run_abi(&'a self) -> Result<Vec<(SymbolicCall<'ctx>, Tree<'a, 'ctx>)>, RevertReason> {
+        self.abi
+            .functions()
+            .map(|function| {
+                let call = self.constrain_function(function);
+                let tree = self.run();
+                self.sol.pop(1);
+                Ok((call, tree?))
+            })
+            .collect()
+    }
+
+    /// only explore well-formed calls to `function`, until the solver is popped
+    pub fn constrain_function(&self, function: &ethabi::Function) -> SymbolicCall<'ctx> {
+        let call = SymbolicCall::new(self.ctx, function);
+
+        self.sol.push();
+        for constraint in &call.constraints {
+            self.sol.assert(constraint);
+        }
+        self.constrain_calldata(Some(&call.calldata));
+
+        call
+    }
+
     /// entry point of branching, is the main branch with id 0
     pub fn walk(&'a self) -> Result<(Tree<'a, 'ctx>, usize), RevertReason> {
-        let jdest = get_jumpdest(self.code.to_vec());
+        let jdest = self.cfg.jumpdests();
+        let sol = self.sol.clone();
+        // nested frames are bound to the call that runs them
+        if self.depth == 0 {
+            self.constrain_transaction(&sol);
+        }
 
         // main thread
         let stack = EVMStack::new();
-        let memory = EVMMemory::new(self.ctx);
+        let memory = EVMMemory::with_model(self.ctx, self.memory_model);
+        let storage = self.initial_storage();
+        let transient = self
+            .transient
+            .clone()
+            .unwrap_or_else(|| EVMTransientStorage::new(self.ctx));
+        let mut gas = EVMGas::new(self.ctx);
+        gas.warm_account(&self.own_address());
+        gas.warm_account(&self.sym.caller.apply(&[]).as_bv().unwrap());
         let last_step = Step {
             op: *self.code.first().unwrap(),
             stack,
             memory,
+            storage,
+            transient,
             ret: Default::default(),
+            returndata: ReturnData::new(self.ctx),
+            calls: 0,
+            creates: 0,
+            deployed: Vec::new(),
+            accounts: self.accounts.clone(),
+            logs: Vec::new(),
+            block_hashes: Vec::new(),
+            keccak: EVMKeccak::new(self.ctx),
+            gas,
+            constraints: Vec::new(),
         };
 
-        Self::path(
-            self.ctx,
+        // the main thread starts with the constraints of the prover
+        let tree = BTreeMap::from([(0, (sol, Vec::new(), None))]);
+
+        self.path(
             &jdest,
-            &self.sym,
-            self.code,
             0,
-            Default::default(),
-            &mut Default::default(),
+            Rc::new(RefCell::new(tree)),
+            BTreeMap::new(),
             last_step,
             0,
         )
     }
 
     pub fn step(
-        ctx: &'a Context,
-        sym: &'a Symbolic<'ctx>,
+        &self,
         last_step: Step<'a, 'ctx>,
         instruction: Mnemonic<'a>,
     ) -> Result<Step<'a, 'ctx>, RevertReason> {
+        let (ctx, sym) = (self.ctx, &self.sym);
         let mut step = last_step;
         step.op = instruction;
 
         let op = instruction.op;
         let opcode = op.opcode();
+        let costs = op.revision().gas_costs();
+        step.gas.charge(op.static_gas());
+        // operands the prover can't handle symbolically
+        let unsupported = || RevertReason::Unsupported(instruction.pc);
         // dbg!(&opcode);
+
+        // a frame inside a STATICCALL reverts on any change of the state
+        let changes_state = matches!(
+            opcode,
+            Sstore | Tstore | Log0 | Log1 | Log2 | Log3 | Log4 | Create | Create2 | Selfdestruct
+        );
+        if self.is_static && changes_state {
+            step.ret.rev = true;
+            return Ok(step);
+        }
+
         match opcode {
             Stop => {
                 // no output for this step
+                step.ret.ret = true;
             }
             Add => {
                 let a = step.stack.pop()?;
@@ -161,45 +603,65 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
                 let b = step.stack.pop()?;
                 step.stack.push(a.bvsub(&b))?;
             }
+            // a division by zero gives zero
             Div => {
                 let a = step.stack.pop()?;
                 let b = step.stack.pop()?;
-                step.stack.push(a.bvudiv(&b))?;
+                step.stack.push(Self::unless_zero(ctx, &b, a.bvudiv(&b)))?;
             }
             Sdiv => {
                 let a = step.stack.pop()?;
                 let b = step.stack.pop()?;
-                step.stack.push(a.bvsdiv(&b))?;
+                step.stack.push(Self::unless_zero(ctx, &b, a.bvsdiv(&b)))?;
             }
             Mod => {
                 let a = step.stack.pop()?;
                 let b = step.stack.pop()?;
-                step.stack.push(a.bvurem(&b))?;
+                step.stack.push(Self::unless_zero(ctx, &b, a.bvurem(&b)))?;
             }
+            // the sign of the result is the one of the dividend
             Smod => {
                 let a = step.stack.pop()?;
                 let b = step.stack.pop()?;
-                step.stack.push(a.bvsmod(&b))?;
+                step.stack.push(Self::unless_zero(ctx, &b, a.bvsrem(&b)))?;
             }
+            // the intermediate result doesn't wrap around
             Addmod => {
-                let a = step.stack.pop()?;
-                let b = step.stack.pop()?;
+                let a = step.stack.pop()?.zero_ext(1);
+                let b = step.stack.pop()?.zero_ext(1);
                 let n = step.stack.pop()?;
-                step.stack.push(a.bvadd(&b).bvurem(&n))?;
+                let res = a.bvadd(&b).bvurem(&n.zero_ext(1)).extract(255, 0);
+                step.stack.push(Self::unless_zero(ctx, &n, res))?;
             }
             Mulmod => {
-                let a = step.stack.pop()?;
-                let b = step.stack.pop()?;
+                let a = step.stack.pop()?.zero_ext(256);
+                let b = step.stack.pop()?.zero_ext(256);
                 let n = step.stack.pop()?;
-                step.stack.push(a.bvmul(&b).bvurem(&n))?;
+                let res = a.bvmul(&b).bvurem(&n.zero_ext(256)).extract(255, 0);
+                step.stack.push(Self::unless_zero(ctx, &n, res))?;
             }
-            // Exp => {
-            //     todo!();
-            // }
+            Exp => {
+                let base = step.stack.pop()?;
+                let exponent = step.stack.pop()?;
+                // per byte of the exponent
+                for len in 0..32 {
+                    let bound = to_bv(ctx, &[[1].as_slice(), &[0; 32][..len]].concat());
+                    step.gas.charge_if(&exponent.bvuge(&bound), costs.exp_byte);
+                }
+                let power = Self::exp(ctx, &mut step, &base, &exponent);
+                step.stack.push(power)?;
+            }
+            // extend the sign of the lowest `size + 1` bytes
             Signextend => {
-                let a = step.stack.pop()?;
-                let b = step.stack.pop32()?.unwrap();
-                step.stack.push(a.sign_ext(b))?;
+                let size = step.stack.pop()?;
+                let value = step.stack.pop()?;
+                let res = (0..31).fold(value.clone(), |res, i| {
+                    let bits = (i + 1) * 8;
+                    let extended = value.extract(bits - 1, 0).sign_ext(256 - bits);
+                    let index = z3::ast::BV::from_u64(ctx, i.into(), 256);
+                    size._eq(&index).ite(&extended, &res)
+                });
+                step.stack.push(res.simplify())?;
             }
             Lt => {
                 let a = step.stack.pop()?;
@@ -250,19 +712,14 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
                 let a = step.stack.pop()?;
                 step.stack.push(a.bvnot())?;
             }
+            // the i-th byte from the most significant one, zero past the word
             Byte => {
                 let i = step.stack.pop()?;
-                let res = if let Some(x) = step.stack.pop32()? {
-                    if x < u32::max_value() - 32 {
-                        i.extract(x + 255, x)
-                    } else {
-                        z3::ast::BV::from_u64(ctx, 0, 256)
-                    }
-                } else {
-                    z3::ast::BV::from_u64(ctx, 0, 256)
-                };
-
-                step.stack.push(res)?;
+                let x = step.stack.pop()?;
+                let word = |n| z3::ast::BV::from_u64(ctx, n, 256);
+                let shift = word(31).bvsub(&i).bvmul(&word(8));
+                let byte = x.bvlshr(&shift).bvand(&word(0xff));
+                step.stack.push(i.bvult(&word(32)).ite(&byte, &word(0)))?;
             }
             Shl => {
                 let shift = step.stack.pop()?;
@@ -275,23 +732,30 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
                 step.stack.push(value.bvlshr(&shift))?;
             }
             Sar => {
-                let a = step.stack.pop()?;
-                let b = step.stack.pop()?;
-                step.stack.push(a.bvashr(&b))?;
+                let shift = step.stack.pop()?;
+                let value = step.stack.pop()?;
+                step.stack.push(value.bvashr(&shift))?;
             }
             Sha3 => {
-                let off = step.stack.pop32()?.unwrap();
-                let size = step.stack.pop32()?.unwrap();
-                let part = step.memory.mbig_load(off, off + size);
-                dbg!(&part);
-                let hash = Self::sha3(ctx, &part);
+                let off = step.stack.pop()?;
+                let size = step.stack.pop32()?.ok_or(unsupported())?;
+                let words = z3::ast::BV::from_u64(ctx, size.into(), 256);
+                step.gas.charge_words(costs.sha3_word, &words);
+                let hash = if size > 0 {
+                    let part = step.memory.load(&off, size);
+                    Self::sha3(&mut step, &part)
+                } else {
+                    to_bv(ctx, &keccak256(&[]))
+                };
                 step.stack.push(hash)?;
             }
             Address => {
-                step.stack.push(sym.address.apply(&[]).as_bv().unwrap())?;
+                step.stack.push(self.own_address())?;
             }
             Balance => {
                 let address = step.stack.pop()?;
+                let cold = step.gas.access_account(&address);
+                step.gas.charge_if(&cold, costs.cold_account);
                 step.stack
                     .push(sym.balance_of.apply(&[&address]).as_bv().unwrap())?;
             }
@@ -299,79 +763,172 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
                 step.stack.push(sym.origin.apply(&[]).as_bv().unwrap())?;
             }
             Caller => {
-                // step.stack.push(sym.caller.apply(&[]).as_bv().unwrap())?;
-                // TODO: should it be constant or not ?
-                // Probably should, and write the caller address in step
-                step.stack.push(
-                    sym.caller
-                        .apply(&[&z3::ast::BV::from_u64(ctx, 0, 256)])
-                        .as_bv()
-                        .unwrap(),
-                )?;
+                step.stack.push(sym.caller.apply(&[]).as_bv().unwrap())?;
             }
             Callvalue => {
-                // step.stack.push(
-                //     sym.value
-                //         .apply(&[&z3::ast::BV::from_u64(ctx, 0, 256)])
-                //         .as_bv()
-                //         .unwrap(),
-                // )?;
-                step.stack.push(z3::ast::BV::from_u64(ctx, 0, 256))?;
+                step.stack.push(sym.value.apply(&[]).as_bv().unwrap())?;
             }
             Calldataload => {
                 let off = step.stack.pop()?;
-                let load = sym
-                    .calldata
-                    .apply(&[&off])
-                    .as_bv()
-                    .expect("couldn't convert calldata into a bitvector")
-                    .simplify();
-
-                step.stack.push(load)?;
+                step.stack.push(self.calldata_bytes(&off, 32))?;
             }
             Calldatasize => {
                 step.stack
                     .push(sym.calldatasize.apply(&[]).as_bv().unwrap())?;
             }
+            Calldatacopy => {
+                let dest_off = step.stack.pop()?;
+                let off = step.stack.pop()?;
+                let size = step.stack.pop()?;
+                step.gas.charge_words(costs.copy_word, &size);
+                let (len, symbolic) = Self::copy_size(ctx, &mut step, &size);
+                if len > 0 {
+                    let bytes = self.calldata_bytes(&off, len);
+                    step.memory.store_sized(&dest_off, bytes, symbolic.as_ref());
+                }
+            }
             Codesize => {
+                // the code may have been cut from its metadata or constructor arguments
                 let address = sym.address.apply(&[]).as_bv().unwrap();
-                step.stack
-                    .push(sym.codesize.apply(&[&address]).as_bv().unwrap())?;
+                let size = sym.codesize.apply(&[&address]).as_bv().unwrap();
+                let len = self
+                    .code
+                    .last()
+                    .map_or(0, |last| last.pc + 1 + last.pushes.len());
+                let len = z3::ast::BV::from_u64(ctx, len as u64, 256);
+                step.constraints.push(size.bvuge(&len));
+                step.stack.push(size)?;
             }
             Codecopy => {
                 let addr = sym.address.apply(&[]).as_bv().unwrap();
-                let dest_off = step.stack.pop32()?.unwrap();
-                let off = step.stack.pop32()?.unwrap();
-                let size = step.stack.pop32()?.unwrap();
-                step = Self::code_copy(ctx, addr, dest_off, off, size, step)?;
+                let dest_off = step.stack.pop()?;
+                let off = step.stack.pop32()?.ok_or(unsupported())?;
+                let size = step.stack.pop32()?.ok_or(unsupported())?;
+                let words = z3::ast::BV::from_u64(ctx, size.into(), 256);
+                step.gas.charge_words(costs.copy_word, &words);
+                step = Self::code_copy(ctx, addr, &dest_off, off, size, step)?;
             }
             Gasprice => {
                 step.stack.push(sym.gasprice.apply(&[]).as_bv().unwrap())?;
             }
+            Blockhash => {
+                let number = step.stack.pop()?;
+                let hash = self.block_hash(&mut step, &number);
+                step.block_hashes.push((number, hash.clone()));
+                step.stack.push(hash)?;
+            }
+            Coinbase => {
+                let coinbase = self.config.coinbase.map(|coinbase| coinbase.to_vec());
+                let coinbase = self.env_var(&mut step, "coinbase", coinbase, 160, 0);
+                step.stack.push(coinbase)?;
+            }
+            Timestamp => {
+                let config = &self.config;
+                let timestamp = config
+                    .timestamp
+                    .map(|timestamp| timestamp.to_be_bytes().to_vec());
+                let timestamp =
+                    self.env_var(&mut step, "timestamp", timestamp, 64, config.min_timestamp);
+                step.stack.push(timestamp)?;
+            }
+            Number => {
+                let number = self.number(&mut step);
+                step.stack.push(number)?;
+            }
+            Difficulty => {
+                let prevrandao = self
+                    .config
+                    .prevrandao
+                    .map(|value| value.as_bytes().to_vec());
+                let prevrandao = self.env_var(&mut step, "prevrandao", prevrandao, 256, 0);
+                step.stack.push(prevrandao)?;
+            }
+            Gaslimit => {
+                let limit = self
+                    .config
+                    .gas_limit
+                    .map(|limit| limit.to_be_bytes().to_vec());
+                let limit = self.env_var(&mut step, "gaslimit", limit, 64, 0);
+                step.stack.push(limit)?;
+            }
+            Chainid => {
+                let id = self.config.chain_id.map(|id| id.to_be_bytes().to_vec());
+                let id = self.env_var(&mut step, "chainid", id, 64, 0);
+                step.stack.push(id)?;
+            }
+            Selfbalance => {
+                let balance = match self
+                    .address
+                    .and_then(|address| self.state?.balance(&address))
+                {
+                    Some(balance) => to_bv(ctx, &balance.as_bytes()),
+                    None => sym
+                        .balance_of
+                        .apply(&[&self.own_address()])
+                        .as_bv()
+                        .unwrap(),
+                };
+                step.stack.push(balance)?;
+            }
+            Basefee => {
+                let fee = self.config.base_fee.map(|fee| fee.as_bytes().to_vec());
+                let fee = self.env_var(&mut step, "basefee", fee, 128, 0);
+                step.stack.push(fee)?;
+            }
+            Blobbasefee => {
+                let fee = self.config.blob_base_fee.map(|fee| fee.as_bytes().to_vec());
+                let fee = self.env_var(&mut step, "blobbasefee", fee, 128, 0);
+                step.stack.push(fee)?;
+            }
             Extcodesize => {
                 let address = step.stack.pop()?;
-                step.stack
-                    .push(sym.codesize.apply(&[&address]).as_bv().unwrap())?;
+                let cold = step.gas.access_account(&address);
+                step.gas.charge_if(&cold, costs.cold_account);
+                let size = match self.known_code(&step, &address) {
+                    Some(code) => z3::ast::BV::from_u64(ctx, code.len() as u64, 256),
+                    None => sym.codesize.apply(&[&address]).as_bv().unwrap(),
+                };
+                step.stack.push(size)?;
+            }
+            Extcodehash => {
+                let address = step.stack.pop()?;
+                let cold = step.gas.access_account(&address);
+                step.gas.charge_if(&cold, costs.cold_account);
+                let hash = match self.known_code(&step, &address) {
+                    Some(code) => to_bv(ctx, &keccak256(&code)),
+                    None => sym.codehash.apply(&[&address]).as_bv().unwrap(),
+                };
+                step.stack.push(hash)?;
             }
             Extcodecopy => {
                 let addr = step.stack.pop()?;
-                let dest_off = step.stack.pop32()?.unwrap();
-                let off = step.stack.pop32()?.unwrap();
-                let size = step.stack.pop32()?.unwrap();
-                step = Self::code_copy(ctx, addr, dest_off, off, size, step)?;
+                let cold = step.gas.access_account(&addr);
+                step.gas.charge_if(&cold, costs.cold_account);
+                let dest_off = step.stack.pop()?;
+                let off = step.stack.pop32()?.ok_or(unsupported())?;
+                let size = step.stack.pop32()?.ok_or(unsupported())?;
+                let words = z3::ast::BV::from_u64(ctx, size.into(), 256);
+                step.gas.charge_words(costs.copy_word, &words);
+                step = Self::code_copy(ctx, addr, &dest_off, off, size, step)?;
             }
             Returndatasize => {
-                let size = if let Some(val) = &step.ret.val {
-                    val.get_size()
-                } else {
-                    0
-                };
-                step.stack
-                    .push(z3::ast::BV::from_u64(ctx, size.into(), 256))?;
+                step.stack.push(step.returndata.size().clone())?;
+            }
+            Returndatacopy => {
+                let dest_off = step.stack.pop()?;
+                let off = step.stack.pop()?;
+                let size = step.stack.pop()?;
+                step.gas.charge_words(costs.copy_word, &size);
+                let (len, symbolic) = Self::copy_size(ctx, &mut step, &size);
+                if let Some(data) = step.returndata.load(&off, len) {
+                    step.memory.store_sized(&dest_off, data, symbolic.as_ref());
+                }
+
+                // reading past the returndata halts, only explore valid reads
+                let end = off.bvadd(&size);
+                step.constraints.push(end.bvuge(&off));
+                step.constraints.push(end.bvule(step.returndata.size()));
             }
-            // Returndatacopy => {
-            //     todo!();
-            // }
             Push0 | Push1 | Push2 | Push3 | Push4 | Push5 | Push6 | Push7 | Push8 | Push9
             | Push10 | Push11 | Push12 | Push13 | Push14 | Push15 | Push16 | Push17 | Push18
             | Push19 | Push20 | Push21 | Push22 | Push23 | Push24 | Push25 | Push26 | Push27
@@ -386,31 +943,227 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
             Swap1 | Swap2 | Swap3 | Swap4 | Swap5 | Swap6 | Swap7 | Swap8 | Swap9 | Swap10
             | Swap11 | Swap12 | Swap13 | Swap14 | Swap15 | Swap16 => {
                 let swap = op.swap_size().unwrap();
-                step.stack.swapn((swap - 1) as usize)?;
+                step.stack.swapn(swap as usize)?;
             }
             Pop => {
                 step.stack.pop()?;
             }
             Mload => {
-                let off = step.stack.pop32()?.unwrap();
-                let mem = step.memory.mload(off);
+                let off = step.stack.pop()?;
+                let mem = step.memory.mload(&off);
                 step.stack.push(mem)?;
             }
             Mstore => {
-                let off = step.stack.pop32()?.unwrap();
+                let off = step.stack.pop()?;
+                let val = step.stack.pop()?;
+                step.memory.mstore(&off, val);
+            }
+            Mstore8 => {
+                let off = step.stack.pop()?;
+                let val = step.stack.pop()?;
+                step.memory.mstore8(&off, &val);
+            }
+            Msize => {
+                step.stack.push(step.memory.msize().clone())?;
+            }
+            Sload => {
+                let key = step.stack.pop()?;
+                let cold = step.gas.access_slot(&key);
+                step.gas.charge_if(&cold, costs.cold_slot);
+                let val = step.storage.sload(&key);
+                step.stack.push(val)?;
+            }
+            Sstore => {
+                let key = step.stack.pop()?;
+                let val = step.stack.pop()?;
+                let cost = self.sstore_gas(&mut step, &key, &val);
+                step.gas.charge_bv(&cost);
+                step.storage.sstore(key, val);
+            }
+            Tload => {
+                let key = step.stack.pop()?;
+                let val = step.transient.tload(&key);
+                step.stack.push(val)?;
+            }
+            Tstore => {
+                let key = step.stack.pop()?;
                 let val = step.stack.pop()?;
-                step.memory.mstore(off, val);
+                step.transient.tstore(&key, &val);
+            }
+            Mcopy => {
+                let dest_off = step.stack.pop()?;
+                let off = step.stack.pop()?;
+                let size = step.stack.pop()?;
+                step.gas.charge_words(costs.copy_word, &size);
+                match Self::copy_size(ctx, &mut step, &size) {
+                    (len, None) => step.memory.mcopy(&dest_off, &off, len),
+                    (len, Some(size)) => {
+                        let data = step.memory.load(&off, len);
+                        step.memory.store_sized(&dest_off, data, Some(&size));
+                    }
+                }
             }
             Return => {
-                step = Self::ret(ctx, step)?;
+                step = Self::ret(step)?;
+                step.ret.ret = true;
             }
             Revert => {
-                step = Self::ret(ctx, step)?;
+                step = Self::ret(step)?;
                 step.ret.rev = true;
             }
             Invalid => {
                 step.ret.rev = true;
             }
+            Call | Callcode | Delegatecall | Staticcall => {
+                let _gas = step.stack.pop()?;
+                let address = step.stack.pop()?;
+                // delegated code sees the value of the current call
+                let value = if opcode == &Call || opcode == &Callcode {
+                    step.stack.pop()?
+                } else if opcode == &Delegatecall {
+                    sym.value.apply(&[]).as_bv().unwrap()
+                } else {
+                    z3::ast::BV::from_u64(ctx, 0, 256)
+                };
+                let args_off = step.stack.pop()?;
+                let args_size = step.stack.pop32()?.ok_or(unsupported())?;
+                let ret_off = step.stack.pop()?;
+                let ret_size = step.stack.pop32()?.ok_or(unsupported())?;
+
+                // and so does sending value from it
+                if self.is_static && opcode == &Call {
+                    match bv_to_word(&value) {
+                        Some(value) if value == [0; 32] => {}
+                        Some(_) => {
+                            step.ret.rev = true;
+                            return Ok(step);
+                        }
+                        None => return Err(unsupported()),
+                    }
+                }
+
+                let cold = step.gas.access_account(&address);
+                step.gas.charge_if(&cold, costs.cold_account);
+                if opcode == &Call || opcode == &Callcode {
+                    let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+                    step.gas
+                        .charge_if(&value._eq(&zero).not(), costs.call_value);
+                }
+
+                let args = if args_size > 0 {
+                    Some(step.memory.load(&args_off, args_size))
+                } else {
+                    None
+                };
+
+                step.calls += 1;
+                let prefix = format!("{}call{}_", sym.prefix, step.calls);
+                let success = match self.inline_call(
+                    &mut step,
+                    opcode,
+                    &address,
+                    &value,
+                    args.as_ref(),
+                    &prefix,
+                ) {
+                    Some(success) => success,
+                    None => self.unknown_call(&mut step, opcode, &prefix),
+                };
+
+                // only the bytes that were returned overwrite the memory
+                if ret_size > 0 {
+                    let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+                    let new = step.returndata.load(&zero, ret_size).unwrap();
+                    let returned = step.returndata.size().clone();
+                    step.memory.store_sized(&ret_off, new, Some(&returned));
+                    let size = z3::ast::BV::from_u64(ctx, ret_size.into(), 256);
+                    step.memory.expand(&ret_off, &size);
+                }
+
+                step.stack.push(bool_to_bv(ctx, &success))?;
+            }
+            Create | Create2 => {
+                let value = step.stack.pop()?;
+                let off = step.stack.pop()?;
+                let size = step.stack.pop32()?.ok_or(unsupported())?;
+                let salt = if opcode == &Create2 {
+                    Some(step.stack.pop()?)
+                } else {
+                    None
+                };
+
+                let words = z3::ast::BV::from_u64(ctx, size.into(), 256);
+                step.gas.charge_words(costs.initcode_word, &words);
+                if salt.is_some() {
+                    step.gas.charge_words(costs.sha3_word, &words);
+                }
+
+                let init = if size > 0 {
+                    Some(step.memory.load(&off, size))
+                } else {
+                    None
+                };
+
+                let address = self.create_address(&mut step, salt.as_ref(), init.as_ref());
+                step.creates += 1;
+                let prefix = format!("{}create{}_", sym.prefix, step.creates);
+                let (success, runtime) =
+                    match self.create(&mut step, &address, &value, init.as_ref(), &prefix) {
+                        Some(created) => created,
+                        None => (self.unknown_call(&mut step, opcode, &prefix), None),
+                    };
+
+                let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+                let created = success.ite(&address, &zero).simplify();
+                // later calls to the new account run its runtime code
+                if let Some(runtime) = runtime {
+                    if created != address {
+                        step.deployed.push((created.clone(), runtime.clone()));
+                    }
+                    step.deployed.push((address, runtime));
+                }
+                step.stack.push(created)?;
+            }
+            Log0 | Log1 | Log2 | Log3 | Log4 => {
+                let off = step.stack.pop()?;
+                let size = step.stack.pop32()?.ok_or(unsupported())?;
+                let topics = (0..op.log_size().unwrap())
+                    .map(|_| step.stack.pop())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let topics_gas = costs.log_topic * topics.len() as u64;
+                step.gas
+                    .charge(topics_gas + costs.log_byte * u64::from(size));
+                let data = if size > 0 {
+                    Some(step.memory.load(&off, size))
+                } else {
+                    None
+                };
+                step.logs.push(Log { topics, data });
+            }
+            Pc => {
+                let pc = z3::ast::BV::from_u64(ctx, instruction.pc as u64, 256);
+                step.stack.push(pc)?;
+            }
+            Blobhash => {
+                let index = step.stack.pop()?;
+                let hash = Self::blob_hash(ctx, &mut step, &index);
+                step.stack.push(hash)?;
+            }
+            Selfdestruct => {
+                // balances are not tracked across the path, the transfer to the beneficiary is
+                // ignored and the frame stops successfully
+                let beneficiary = step.stack.pop()?;
+                let cold = step.gas.access_account(&beneficiary);
+                step.gas.charge_if(&cold, costs.cold_account);
+                step.ret.ret = true;
+            }
+            Gas => {
+                // the frame was not out of gas so far, and gets what remains
+                let gas = sym.gas.apply(&[]).as_bv().unwrap();
+                let used = step.gas_used();
+                step.constraints.push(used.bvule(&gas));
+                step.stack.push(gas.bvsub(&used))?;
+            }
             Jumpdest => {
                 // nothing, handled by branching
             }
@@ -421,7 +1174,6 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
                 step.stack.pop()?;
                 step.stack.pop()?;
             }
-            op => todo!("{:?}", op),
         }
 
         // dbg!(&step);
@@ -429,82 +1181,873 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
         Ok(step)
     }
 
-    fn ret(ctx: &'a Context, mut step: Step<'a, 'ctx>) -> Result<Step<'a, 'ctx>, RevertReason> {
-        dbg!(&step.stack);
-        let len = step.stack.peek(1)?;
-        if len.eq(&z3::ast::BV::from_u64(ctx, 0, 256)) {
-            step.ret.val = None;
-        } else {
-            let len = len.as_u64().unwrap() as u32;
-            let off = step.stack.pop32()?.unwrap();
-            step.stack.pop()?;
-            let ret = step.memory.mbig_load(off, off + len);
-            step.ret.val = Some(ret);
+    /// call to an unknown account: any outcome, and any storage if the callee could reenter
+    fn unknown_call(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        opcode: &OpCodes,
+        prefix: &str,
+    ) -> z3::ast::Bool<'ctx> {
+        step.returndata = ReturnData::symbolic(self.ctx, prefix);
+        if opcode != &Staticcall {
+            step.storage.havoc(self.ctx, &format!("{prefix}storage"));
+            step.transient
+                .havoc(self.ctx, &format!("{prefix}transient"));
         }
 
-        Ok(step)
+        z3::ast::Bool::new_const(self.ctx, format!("{prefix}success"))
     }
 
-    fn code_copy(
-        ctx: &'a Context,
-        addr: z3::ast::BV<'a>,
-        dest_off: u32,
-        off: u32,
-        size: u32,
-        mut step: Step<'a, 'ctx>,
-    ) -> Result<Step<'a, 'ctx>, RevertReason> {
-        let codecopy = z3::FuncDecl::new(
-            ctx,
-            "codecopy",
-            &[
-                &z3::Sort::bitvector(ctx, 256),
-                &z3::Sort::bitvector(ctx, 256),
-                &z3::Sort::bitvector(ctx, 256),
-            ],
-            &z3::Sort::bitvector(ctx, size),
-        );
-
-        let code = codecopy
-            .apply(&[
-                &addr,
-                &z3::ast::BV::from_u64(ctx, off.into(), 256),
-                &z3::ast::BV::from_u64(ctx, size.into(), 256),
-            ])
-            .as_bv()
-            .unwrap();
+    /// execute the code of a known account in a nested frame and merge all of its paths.
+    /// Returns the success flag, or `None` if the callee code is unknown.
+    fn inline_call(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        opcode: &OpCodes,
+        address: &z3::ast::BV<'ctx>,
+        value: &z3::ast::BV<'ctx>,
+        args: Option<&z3::ast::BV<'ctx>>,
+        prefix: &str,
+    ) -> Option<z3::ast::Bool<'ctx>> {
+        let ctx = self.ctx;
+        if self.depth >= MAX_CALL_DEPTH {
+            return None;
+        }
 
-        step.memory.mbig_store(dest_off, code);
+        let account = bv_to_word(address).map(|word| to_address(&word));
+        let code = self.known_code(step, address)?;
 
-        Ok(step)
-    }
+        // calling an account without code always succeeds and returns nothing
+        if code.is_empty() {
+            step.returndata = ReturnData::new(ctx);
+            return Some(z3::ast::Bool::from_bool(ctx, true));
+        }
 
-    /// compute the symbolic keccak256 of an arbitrary length bitvector
-    fn sha3(ctx: &'a Context, part: &z3::ast::BV<'a>) -> z3::ast::BV<'a> {
-        let sha3 = z3::FuncDecl::new(
-            ctx,
-            "sha3",
-            &[&z3::Sort::bitvector(ctx, part.get_size())],
-            &z3::Sort::bitvector(ctx, 256),
-        );
+        let delegated = opcode == &Delegatecall || opcode == &Callcode;
+        let mnemonics = to_mnemonics_for(&code, self.revision);
+        let mut frame = self.frame(&mnemonics, prefix);
+        // delegated code runs on the storage of the caller
+        frame.storage = if delegated {
+            Some(step.storage.clone())
+        } else {
+            account.and_then(|account| self.account_storage(step, &account))
+        };
+        // so does it on its transient storage, other accounts start from an empty one
+        if delegated {
+            frame.transient = Some(step.transient.clone());
+        }
+        frame.address = if delegated { self.address } else { account };
+        frame.accounts = step.accounts.clone();
+        frame.is_static = self.is_static || opcode == &Staticcall;
+        // the callee's storage before the call, as the frame names it
+        let callee_storage = frame.initial_storage();
 
-        sha3.apply(&[part]).as_bv().unwrap()
+        let caller_address = self.own_address();
+        let (address, caller) = if opcode == &Delegatecall {
+            let caller = self.sym.caller.apply(&[]).as_bv().unwrap();
+            (caller_address, caller)
+        } else if opcode == &Callcode {
+            (caller_address.clone(), caller_address)
+        } else {
+            (address.clone(), caller_address)
+        };
+        frame.bind(&address, &caller, value);
+        frame.constrain_calldata(args);
+
+        let tree = frame.run().ok()?;
+
+        let mut success = z3::ast::Bool::from_bool(ctx, false);
+        let mut returndata = ReturnData::new(ctx);
+        let mut storage = step.storage.clone();
+        let mut transient = step.transient.clone();
+        let mut accounts = step.accounts.clone();
+        let mut gas = z3::ast::BV::from_u64(ctx, 0, 256);
+        let mut paths = Vec::new();
+
+        for (cond, last) in Self::outcomes(ctx, &tree) {
+            let ok = z3::ast::Bool::from_bool(ctx, last.ret.is_success());
+
+            success = cond.ite(&ok, &success);
+            returndata.merge(&cond, &last.ret.returndata(ctx));
+            if delegated && last.ret.is_success() {
+                storage.merge(&cond, &last.storage);
+                transient.merge(&cond, &last.transient);
+            }
+            // the accounts written by a successful callee keep their storage,
+            // the ones unknown before the call start over from an unknown storage
+            if last.ret.is_success() {
+                let mut written = last.accounts.clone();
+                if let (false, Some(account)) = (delegated, account) {
+                    written.insert(account, last.storage.clone());
+                }
+                for (address, written) in written {
+                    let known = match accounts.get(&address) {
+                        Some(known) => Some(known.clone()),
+                        None if account == Some(address) && !delegated => {
+                            Some(callee_storage.clone())
+                        }
+                        None => self.account_storage(step, &address),
+                    };
+                    if let Some(mut known) = known {
+                        known.merge(&cond, &written);
+                        accounts.insert(address, known);
+                    }
+                }
+            }
+            let axioms = step.keccak.absorb(&last.keccak);
+            step.constraints.extend(axioms);
+            gas = cond.ite(&last.gas_used(), &gas);
+            paths.push(cond);
+        }
+
+        if paths.is_empty() {
+            return None;
+        }
+
+        step.gas.charge_bv(&gas);
+        // the callee went through one of its explored paths
+        step.constraints
+            .push(z3::ast::Bool::or(ctx, &paths.iter().collect::<Vec<_>>()));
+        step.returndata = returndata;
+        step.storage = storage;
+        step.transient = transient;
+        step.accounts = accounts;
+        // the callee could have reentered this frame and changed any slot,
+        // unless it ran on its storage or never ran other code
+        if !delegated && !frame.is_static && Self::calls_out(&tree) {
+            step.storage.havoc(ctx, &format!("{prefix}reentered"));
+            step.transient
+                .havoc(ctx, &format!("{prefix}reentered_transient"));
+        }
+
+        Some(success.simplify())
     }
 
-    /// iterate on a portion of the bytecode, branch when needed
-    fn path(
+    /// storage of the known `account` as the path left it, from the state if no call wrote it
+    fn account_storage(
+        &self,
+        step: &Step<'a, 'ctx>,
+        account: &Address,
+    ) -> Option<EVMStorage<'ctx>> {
+        if let Some(storage) = step.accounts.get(account) {
+            return Some(storage.clone());
+        }
+
+        let storage = self.state?.storage(account)?;
+        let words = storage
+            .iter()
+            .map(|(key, value)| (key.as_bytes(), value.as_bytes()))
+            .collect();
+        Some(EVMStorage::from_words(self.ctx, &words))
+    }
+
+    /// run the init code of a new account in a nested frame and merge all of its paths.
+    /// Returns the success flag and the runtime code if all successful paths deploy the same,
+    /// or `None` if the init code is unknown.
+    fn create(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        address: &z3::ast::BV<'ctx>,
+        value: &z3::ast::BV<'ctx>,
+        init: Option<&z3::ast::BV<'ctx>>,
+        prefix: &str,
+    ) -> Option<(z3::ast::Bool<'ctx>, Option<Vec<u8>>)> {
+        let ctx = self.ctx;
+
+        // without init code, an empty account is created
+        let init = match init {
+            Some(init) => init,
+            None => {
+                step.returndata = ReturnData::new(ctx);
+                return Some((z3::ast::Bool::from_bool(ctx, true), Some(Vec::new())));
+            }
+        };
+
+        if self.depth >= MAX_CALL_DEPTH {
+            return None;
+        }
+
+        let code = bv_to_bytes(init)?;
+        let mnemonics = to_mnemonics_for(&code, self.revision);
+        let mut frame = self.frame(&mnemonics, prefix);
+        frame.storage = Some(EVMStorage::new(ctx));
+        frame.address = bv_to_word(address).map(|word| to_address(&word));
+        frame.bind(address, &self.own_address(), value);
+        frame.constrain_calldata(None);
+
+        let tree = frame.run().ok()?;
+
+        let mut success = z3::ast::Bool::from_bool(ctx, false);
+        // a successful creation leaves an empty returndata
+        let mut returndata = ReturnData::new(ctx);
+        let mut runtimes = Vec::new();
+        let mut gas = z3::ast::BV::from_u64(ctx, 0, 256);
+        let mut paths = Vec::new();
+
+        for (cond, last) in Self::outcomes(ctx, &tree) {
+            let ok = z3::ast::Bool::from_bool(ctx, last.ret.is_success());
+
+            success = cond.ite(&ok, &success);
+            if last.ret.is_success() {
+                runtimes.push(match &last.ret.val {
+                    Some(val) => bv_to_bytes(val),
+                    None => Some(Vec::new()),
+                });
+            } else {
+                returndata.merge(&cond, &last.ret.returndata(ctx));
+            }
+            let axioms = step.keccak.absorb(&last.keccak);
+            step.constraints.extend(axioms);
+            gas = cond.ite(&last.gas_used(), &gas);
+            paths.push(cond);
+        }
+
+        if paths.is_empty() {
+            return None;
+        }
+
+        step.gas.charge_bv(&gas);
+        step.constraints
+            .push(z3::ast::Bool::or(ctx, &paths.iter().collect::<Vec<_>>()));
+        step.returndata = returndata;
+
+        runtimes.dedup();
+        let runtime = match runtimes.len() {
+            1 => runtimes.pop().flatten(),
+            _ => None,
+        };
+
+        Some((success.simplify(), runtime))
+    }
+
+    /// address of the next contract created by this frame, concrete if all of its inputs are
+    fn create_address(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        salt: Option<&z3::ast::BV<'ctx>>,
+        init: Option<&z3::ast::BV<'ctx>>,
+    ) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        let sender = self.own_address().extract(159, 0);
+
+        let preimage = match salt {
+            // keccak256(0xff ++ sender ++ salt ++ keccak256(init))
+            Some(salt) => {
+                let init_hash = match init {
+                    Some(init) => Self::sha3(step, init),
+                    None => to_bv(ctx, &keccak256(&[])),
+                };
+                bytes_to_bv(ctx, &[0xff])
+                    .concat(&sender)
+                    .concat(salt)
+                    .concat(&init_hash)
+            }
+            // keccak256(rlp([sender, nonce]))
+            None => {
+                let nonce = self
+                    .address
+                    .and_then(|address| self.state?.nonce(&address))
+                    .unwrap_or(1);
+                let nonce = rlp_u64(nonce + step.creates as u64);
+                let header = [0xc0 + 21 + nonce.len() as u8, 0x94];
+                bytes_to_bv(ctx, &header)
+                    .concat(&sender)
+                    .concat(&bytes_to_bv(ctx, &nonce))
+            }
+        };
+
+        Self::sha3(step, &preimage).extract(159, 0).zero_ext(96)
+    }
+
+    /// `res` unless `divisor` is zero, the result of a division by zero being zero
+    fn unless_zero(
+        ctx: &'ctx Context,
+        divisor: &z3::ast::BV<'ctx>,
+        res: z3::ast::BV<'ctx>,
+    ) -> z3::ast::BV<'ctx> {
+        let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+        divisor._eq(&zero).ite(&zero, &res)
+    }
+
+    /// runtime code of `address` when it is known. The contracts created on the path take
+    /// precedence over the known accounts.
+    fn known_code(&self, step: &Step<'a, 'ctx>, address: &z3::ast::BV<'ctx>) -> Option<Vec<u8>> {
+        let deployed = step
+            .deployed
+            .iter()
+            .find(|(deployed, _)| deployed == address);
+        match deployed {
+            Some((_, code)) => Some(code.clone()),
+            None => {
+                let account = to_address(&bv_to_word(address)?);
+                self.state?.code(&account).map(|code| code.to_vec())
+            }
+        }
+    }
+
+    /// versioned hash of the blob at `index` of the transaction, zero past its blobs
+    fn blob_hash(
+        ctx: &'ctx Context,
+        step: &mut Step<'a, 'ctx>,
+        index: &z3::ast::BV<'ctx>,
+    ) -> z3::ast::BV<'ctx> {
+        let word = z3::Sort::bitvector(ctx, 256);
+        let blobhash = z3::FuncDecl::new(ctx, "blobhash", &[&word], &word);
+        let hash = blobhash.apply(&[index]).as_bv().unwrap();
+
+        // hashes of KZG commitments start with their version, 0x01
+        let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+        let version = hash
+            .extract(255, 248)
+            ._eq(&z3::ast::BV::from_u64(ctx, 1, 8));
+        step.constraints
+            .push(z3::ast::Bool::or(ctx, &[&hash._eq(&zero), &version]));
+
+        hash
+    }
+
+    /// dynamic gas of an SSTORE of `value` at `key`, net metered since Istanbul (EIP-2200)
+    fn sstore_gas(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        key: &z3::ast::BV<'ctx>,
+        value: &z3::ast::BV<'ctx>,
+    ) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        let costs = step.op.op.revision().gas_costs();
+        let gas = |gas| z3::ast::BV::from_u64(ctx, gas, 256);
+        let zero = gas(0);
+
+        let current = step.storage.value(key);
+        let cost = match costs.sstore_dirty {
+            None => {
+                let set = z3::ast::Bool::and(ctx, &[&current._eq(&zero), &value._eq(&zero).not()]);
+                set.ite(&gas(costs.sstore_set), &gas(costs.sstore_reset))
+            }
+            Some(dirty) => {
+                let original = self.initial_storage().value(key);
+                let clean = original._eq(&current);
+                let first = original
+                    ._eq(&zero)
+                    .ite(&gas(costs.sstore_set), &gas(costs.sstore_reset));
+                let changed = z3::ast::Bool::and(ctx, &[&clean, &current._eq(value).not()]);
+                changed.ite(&first, &gas(dirty))
+            }
+        };
+
+        let cold = step.gas.access_slot(key);
+        let surcharge = costs.cold_slot + step.op.op.revision().static_gas(&Sload);
+        let surcharge = match costs.cold_slot {
+            0 => zero,
+            _ => cold.ite(&gas(surcharge), &zero),
+        };
+
+        cost.bvadd(&surcharge).simplify()
+    }
+
+    /// whether the path `pid` of `tree` can use more than `bound` gas
+    pub fn exceeds_gas(&self, tree: &Tree<'a, 'ctx>, pid: usize, bound: u64) -> bool {
+        let (sol, last) = match tree.get(&pid) {
+            Some((sol, steps, _)) if !steps.is_empty() => (sol, steps.last().unwrap()),
+            _ => return false,
+        };
+
+        sol.push();
+        sol.assert(
+            &last
+                .gas_used()
+                .bvugt(&z3::ast::BV::from_u64(self.ctx, bound, 256)),
+        );
+        let exceeds = sol.check() == SatResult::Sat;
+        sol.pop(1);
+
+        exceeds
+    }
+
+    /// storage of the contract before the execution
+    fn initial_storage(&self) -> EVMStorage<'ctx> {
+        self.storage.clone().unwrap_or_else(|| {
+            EVMStorage::symbolic(self.ctx, &format!("{}storage", self.sym.prefix))
+        })
+    }
+
+    /// concrete inputs driving the execution down the branch `pid` of `tree`.
+    /// `call` is the symbolic calldata the tree was explored with, if any.
+    pub fn witness(
+        &self,
+        tree: &Tree<'a, 'ctx>,
+        pid: usize,
+        call: Option<&SymbolicCall<'ctx>>,
+    ) -> Option<Witness> {
+        let ctx = self.ctx;
+        let (sol, steps, _) = tree.get(&pid)?;
+        let last = steps.last()?;
+        let size = self.sym.calldatasize.apply(&[]).as_bv().unwrap();
+
+        let (sym, config) = (&self.sym, &self.config);
+        let gasprice = sym.gasprice.apply(&[]).as_bv().unwrap();
+        let origin = sym.origin.apply(&[]).as_bv().unwrap();
+        let address = self.own_address();
+
+        sol.push();
+        sol.assert(&size.bvule(&z3::ast::BV::from_u64(ctx, MAX_WITNESS_CALLDATA, 256)));
+        // so that the environment fits in the one of a concrete execution
+        sol.assert(&gasprice.bvule(&z3::ast::BV::from_u64(ctx, u64::MAX, 256)));
+        for account in [&origin, &address] {
+            sol.assert(
+                &account
+                    .extract(255, 160)
+                    ._eq(&z3::ast::BV::from_u64(ctx, 0, 96)),
+            );
+        }
+        let model = Self::model(sol, last);
+        sol.pop(1);
+        let model = model?;
+
+        let calldata = match call {
+            Some(call) => call.bytes(&model)?,
+            None => {
+                let size = model.eval(&size, true)?.as_u64()?;
+                (0..size)
+                    .map(|i| {
+                        let index = z3::ast::BV::from_u64(ctx, i, 256);
+                        let byte = self.sym.calldata.select(&index).as_bv()?;
+                        Some(model.eval(&byte, true)?.as_u64()? as u8)
+                    })
+                    .collect::<Option<Vec<_>>>()?
+            }
+        };
+
+        let value = self.sym.value.apply(&[]).as_bv().unwrap();
+        let value = bv_to_word(&model.eval(&value, true)?)?;
+        let caller = self.sym.caller.apply(&[]).as_bv().unwrap();
+        let caller = to_address(&bv_to_word(&model.eval(&caller, true)?)?);
+
+        let eval = |bv: &z3::ast::BV<'ctx>| model.eval(bv, true);
+        let word = |bv: &z3::ast::BV<'ctx>| bv_to_word(&eval(bv)?);
+        // the configured block variables, or their symbols
+        let var = |name, value: Option<Vec<u8>>| match value {
+            Some(value) => eval(&to_bv(ctx, &value)),
+            None => eval(&z3::ast::BV::new_const(ctx, name, 256)),
+        };
+        let u64_var = |name, value: Option<u64>| {
+            var(name, value.map(|value| value.to_be_bytes().to_vec()))?.as_u64()
+        };
+        let u256_var = |name, value: Option<U256>| {
+            let value = var(name, value.map(|value| value.as_bytes().to_vec()))?;
+            bv_to_word(&value).map(U256::new)
+        };
+        let coinbase = config.coinbase.map(|coinbase| coinbase.to_vec());
+        let block_hashes = last
+            .block_hashes
+            .iter()
+            .filter_map(|(number, hash)| Some((eval(number)?.as_u64()?, word(hash)?)))
+            .collect();
+        let env = Env {
+            caller,
+            origin: to_address(&word(&origin)?),
+            coinbase: to_address(&bv_to_word(&var("coinbase", coinbase)?)?),
+            value: U256::new(value),
+            gas_limit: u64_var("gaslimit", config.gas_limit)?,
+            gas_price: eval(&gasprice)?.as_u64()?,
+            timestamp: u64_var("timestamp", config.timestamp)?,
+            difficulty: u256_var("prevrandao", config.prevrandao)?,
+            number: u64_var("number", config.number)?,
+            chain_id: u64_var("chainid", config.chain_id)?,
+            base_fee: u256_var("basefee", config.base_fee)?,
+            blob_base_fee: u256_var("blobbasefee", config.blob_base_fee)?,
+            block_hashes,
+            ..Default::default()
+        };
+        let gas = eval(&sym.gas.apply(&[]).as_bv().unwrap())?.as_u64()?;
+
+        let initial = self.initial_storage();
+        let storage = last
+            .storage
+            .read()
+            .iter()
+            .filter_map(|key| {
+                let key = model.eval(key, true)?;
+                let value = model.eval(&initial.value(&key), true)?;
+                Some((bv_to_word(&key)?, bv_to_word(&value)?))
+            })
+            .filter(|(_, value)| value != &[0; 32])
+            .collect();
+
+        let call = match call {
+            Some(call) => call
+                .decode(&model)
+                .map(|tokens| (call.function.name.clone(), tokens)),
+            None => decode_call(&self.abi, &calldata),
+        };
+
+        Some(Witness {
+            calldata,
+            value,
+            caller,
+            address: to_address(&word(&address)?),
+            env,
+            gas,
+            storage,
+            call,
+        })
+    }
+
+    /// model of the path ending with `last`, whose hashes are the keccak256 of their inputs
+    pub fn model(sol: &Solver<'ctx>, last: &Step<'a, 'ctx>) -> Option<Model<'ctx>> {
+        sol.push();
+        let mut model = None;
+        for _ in 0..MAX_KECCAK_ROUNDS {
+            if sol.check() != SatResult::Sat {
+                break;
+            }
+            let candidate = match sol.get_model() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+            let fixes = last.keccak.reconcile(&candidate);
+            if fixes.is_empty() {
+                model = Some(candidate);
+                break;
+            }
+            fixes.iter().for_each(|fix| sol.assert(fix));
+        }
+        sol.pop(1);
+
+        model
+    }
+
+    /// address of the executing account
+    fn own_address(&self) -> z3::ast::BV<'ctx> {
+        match &self.address {
+            Some(address) => to_bv(self.ctx, address),
+            None => self.sym.address.apply(&[]).as_bv().unwrap(),
+        }
+    }
+
+    /// block variable `name`: its configured big-endian `value`, else a symbol of at most `bits` bits
+    /// and no less than `min`. Every frame of the transaction sees the same symbol.
+    fn env_var(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        name: &str,
+        value: Option<Vec<u8>>,
+        bits: u32,
+        min: u64,
+    ) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        if let Some(value) = value {
+            return to_bv(ctx, &value);
+        }
+
+        let var = z3::ast::BV::new_const(ctx, name, 256);
+        if bits < 256 {
+            let high = var.extract(255, bits);
+            step.constraints
+                .push(high._eq(&z3::ast::BV::from_u64(ctx, 0, 256 - bits)));
+        }
+        if min > 0 {
+            step.constraints
+                .push(var.bvuge(&z3::ast::BV::from_u64(ctx, min, 256)));
+        }
+
+        var
+    }
+
+    /// number of the current block
+    fn number(&self, step: &mut Step<'a, 'ctx>) -> z3::ast::BV<'ctx> {
+        let config = &self.config;
+        let number = config.number.map(|number| number.to_be_bytes().to_vec());
+        self.env_var(step, "number", number, 64, config.min_number)
+    }
+
+    /// hash of the block `number`, zero unless it is one of the 256 blocks before the current one
+    fn block_hash(
+        &self,
+        step: &mut Step<'a, 'ctx>,
+        number: &z3::ast::BV<'ctx>,
+    ) -> z3::ast::BV<'ctx> {
+        let ctx = self.ctx;
+        let current = self.number(step);
+        let word = z3::Sort::bitvector(ctx, 256);
+        let hashes = z3::FuncDecl::new(ctx, "blockhash", &[&word], &word);
+
+        // the configured hashes, or an unknown one
+        let unknown = hashes.apply(&[number]).as_bv().unwrap();
+        let hash = self
+            .config
+            .block_hashes
+            .iter()
+            .fold(unknown, |hash, (known, value)| {
+                let known = z3::ast::BV::from_u64(ctx, *known, 256);
+                number._eq(&known).ite(&to_bv(ctx, value), &hash)
+            });
+
+        let recent = z3::ast::Bool::and(
+            ctx,
+            &[
+                &number.bvult(&current),
+                &current
+                    .bvsub(number)
+                    .bvule(&z3::ast::BV::from_u64(ctx, 256, 256)),
+            ],
+        );
+        recent
+            .ite(&hash, &z3::ast::BV::from_u64(ctx, 0, 256))
+            .simplify()
+    }
+
+    /// a prover for a nested frame, that knows the same accounts
+    fn frame<'b>(&self, code: &'b Mnemonics<'b>, prefix: &str) -> Prover<'b, 'ctx>
+    where
+        'a: 'b,
+    {
+        let mut frame = Prover::new(self.ctx, code, Contract::default());
+        frame.sym = Symbolic::with_prefix(self.ctx, prefix);
+        frame.state = self.state;
+        frame.config = self.config.clone();
+        frame.memory_model = self.memory_model;
+        frame.max_unroll = self.max_unroll;
+        frame.is_static = self.is_static;
+        frame.depth = self.depth + 1;
+        frame
+    }
+
+    /// bind the address of the frame, the one of its caller and the value it is sent
+    fn bind(
+        &self,
+        address: &z3::ast::BV<'ctx>,
+        caller: &z3::ast::BV<'ctx>,
+        value: &z3::ast::BV<'ctx>,
+    ) {
+        let frame_address = self.sym.address.apply(&[]).as_bv().unwrap();
+        let frame_caller = self.sym.caller.apply(&[]).as_bv().unwrap();
+        let frame_value = self.sym.value.apply(&[]).as_bv().unwrap();
+        self.sol.assert(&frame_address._eq(address));
+        self.sol.assert(&frame_caller._eq(caller));
+        self.sol.assert(&frame_value._eq(value));
+    }
+
+    /// terminated paths of a nested frame, with the condition to go through them
+    fn outcomes<'b>(
+        ctx: &'ctx Context,
+        tree: &'b Tree<'b, 'ctx>,
+    ) -> Vec<(z3::ast::Bool<'ctx>, &'b Step<'b, 'ctx>)> {
+        tree.values()
+            .filter_map(|(sol, steps, _)| {
+                let last = steps.last().filter(|last| last.ret.has_ret())?;
+                let assertions = sol.get_assertions();
+                let cond =
+                    z3::ast::Bool::and(ctx, &assertions.iter().collect::<Vec<_>>()).simplify();
+                Some((cond, last))
+            })
+            .collect()
+    }
+
+    /// whether a path of `tree` runs other code that can change the state, e.g. reentering
+    fn calls_out(tree: &Tree<'_, 'ctx>) -> bool {
+        tree.values().flat_map(|(_, steps, _)| steps).any(|step| {
+            matches!(
+                step.op.opcode(),
+                Call | Callcode | Delegatecall | Create | Create2
+            )
+        })
+    }
+
+    /// `step` ending its branch at `instruction` on `reason`: an exceptional halt reverts,
+    /// an unsupported operand is kept on the branch to be reported
+    fn halt(
+        mut step: Step<'a, 'ctx>,
+        instruction: Mnemonic<'a>,
+        reason: RevertReason,
+    ) -> Step<'a, 'ctx> {
+        step.op = instruction;
+        match reason {
+            RevertReason::Unsupported(pc) => step.ret.unsupported = Some(pc),
+            _ => step.ret.rev = true,
+        }
+
+        step
+    }
+
+    fn ret(mut step: Step<'a, 'ctx>) -> Result<Step<'a, 'ctx>, RevertReason> {
+        let off = step.stack.pop()?;
+        let len = step.stack.pop32()?;
+        let len = len.ok_or(RevertReason::Unsupported(step.op.pc))?;
+        step.ret.val = match len {
+            0 => None,
+            len => Some(step.memory.load(&off, len)),
+        };
+
+        Ok(step)
+    }
+
+    /// bytes to copy: the concrete `size`, or at most MAX_SYMBOLIC_COPY of them
+    /// with the symbolic `size` telling how many are written
+    fn copy_size(
+        ctx: &'ctx Context,
+        step: &mut Step<'a, 'ctx>,
+        size: &z3::ast::BV<'ctx>,
+    ) -> (u32, Option<z3::ast::BV<'ctx>>) {
+        if let Some(size) = size.simplify().as_u64() {
+            if let Ok(size) = u32::try_from(size) {
+                return (size, None);
+            }
+        }
+
+        let max = z3::ast::BV::from_u64(ctx, MAX_SYMBOLIC_COPY.into(), 256);
+        step.constraints.push(size.bvule(&max));
+        (MAX_SYMBOLIC_COPY, Some(size.clone()))
+    }
+
+    fn code_copy(
+        ctx: &'ctx Context,
+        addr: z3::ast::BV<'ctx>,
+        dest_off: &z3::ast::BV<'ctx>,
+        off: u32,
+        size: u32,
+        mut step: Step<'a, 'ctx>,
+    ) -> Result<Step<'a, 'ctx>, RevertReason> {
+        if size == 0 {
+            return Ok(step);
+        }
+
+        let codecopy = z3::FuncDecl::new(
+            ctx,
+            "codecopy",
+            &[
+                &z3::Sort::bitvector(ctx, 256),
+                &z3::Sort::bitvector(ctx, 256),
+                &z3::Sort::bitvector(ctx, 256),
+            ],
+            &z3::Sort::bitvector(ctx, size),
+        );
+
+        let code = codecopy
+            .apply(&[
+                &addr,
+                &z3::ast::BV::from_u64(ctx, off.into(), 256),
+                &z3::ast::BV::from_u64(ctx, size.into(), 256),
+            ])
+            .as_bv()
+            .unwrap();
+
+        step.memory.store(dest_off, code);
+
+        Ok(step)
+    }
+
+    /// `base` to the power of `exponent`, modulo 2^256. Multiplied out if the exponent is
+    /// constant, shifted if the base is a constant power of two, uninterpreted otherwise.
+    fn exp(
         ctx: &'ctx Context,
+        step: &mut Step<'a, 'ctx>,
+        base: &z3::ast::BV<'ctx>,
+        exponent: &z3::ast::BV<'ctx>,
+    ) -> z3::ast::BV<'ctx> {
+        let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+        let one = z3::ast::BV::from_u64(ctx, 1, 256);
+
+        // square and multiply, from the highest bit of the exponent
+        if let Some(exponent) = bv_to_word(exponent) {
+            return (0..256).rev().fold(one, |power, bit| {
+                let power = power.bvmul(&power);
+                match (exponent[31 - bit / 8] >> (bit % 8)) & 1 {
+                    1 => power.bvmul(base),
+                    _ => power,
+                }
+                .simplify()
+            });
+        }
+
+        if let Some(base) = bv_to_word(base) {
+            let ones = base.iter().map(|byte| byte.count_ones()).sum::<u32>();
+            let bits = base
+                .iter()
+                .rev()
+                .flat_map(|byte| (0..8).map(move |bit| byte >> bit & 1));
+            match (ones, bits.take_while(|bit| *bit == 0).count()) {
+                // 0^0 is 1, and 0 for any other exponent
+                (0, _) => return is_zero(ctx, exponent),
+                (1, 0) => return one,
+                // 2^(log * exponent), the exponents past 256 shift everything out
+                (1, log) => {
+                    let log = z3::ast::BV::from_u64(ctx, log as u64, 256);
+                    let big = exponent.bvuge(&z3::ast::BV::from_u64(ctx, 256, 256));
+                    return big.ite(&zero, &one.bvshl(&log.bvmul(exponent))).simplify();
+                }
+                _ => {}
+            }
+        }
+
+        let exp = z3::FuncDecl::new(
+            ctx,
+            "exp",
+            &[
+                &z3::Sort::bitvector(ctx, 256),
+                &z3::Sort::bitvector(ctx, 256),
+            ],
+            &z3::Sort::bitvector(ctx, 256),
+        );
+        let power = exp.apply(&[base, exponent]).as_bv().unwrap();
+
+        // the values known without multiplying out the exponent
+        let two = z3::ast::BV::from_u64(ctx, 2, 256);
+        let known = [
+            (exponent._eq(&zero), one.clone()),
+            (exponent._eq(&one), base.clone()),
+            (exponent._eq(&two), base.bvmul(base)),
+            (base._eq(&zero), is_zero(ctx, exponent)),
+            (base._eq(&one), one.clone()),
+        ];
+        for (cond, value) in known {
+            step.constraints.push(cond.implies(&power._eq(&value)));
+        }
+
+        power
+    }
+
+    /// compute the keccak256 of an arbitrary length bitvector, symbolic unless it is constant
+    fn sha3(step: &mut Step<'a, 'ctx>, part: &z3::ast::BV<'ctx>) -> z3::ast::BV<'ctx> {
+        let (hash, axioms) = step.keccak.hash(part);
+        step.constraints.extend(axioms);
+        hash
+    }
+
+    /// visits of a branch jumping to `dest` with `stack`, `None` once the path jumped there
+    /// `max_unroll` times with the same return addresses on the stack.
+    /// An internal function called from different places isn't bounded, its loops are.
+    fn unroll(&self, visits: &Visits, dest: u64, stack: &EVMStack<'ctx>) -> Option<Visits> {
+        let calls = stack
+            .constants()
+            .filter(|word| self.return_addresses.contains(word))
+            .collect();
+        let key = (dest, calls);
+        let visited = visits.get(&key).copied().unwrap_or(0);
+        if visited >= self.max_unroll {
+            return None;
+        }
+
+        let mut visits = visits.clone();
+        visits.insert(key, visited + 1);
+        Some(visits)
+    }
+
+    /// iterate on a portion of the bytecode, branch when needed.
+    /// `visits` counts the jumps taken on the way to the branch.
+    fn path(
+        &self,
         jdest: &Vec<u64>,
-        sym: &'a Symbolic<'ctx>, // unhappy path's solver
-        code: &Mnemonics<'a>,
         mut pid: usize,
         tree: Rc<RefCell<Tree<'a, 'ctx>>>,
-        vdest: &mut Vec<u64>,
+        visits: Visits,
         mut step: Step<'a, 'ctx>,
         pc: usize,
     ) -> Result<(Tree<'a, 'ctx>, usize), RevertReason> {
+        let (ctx, code) = (self.ctx, self.code);
         let last_pid = pid;
         let trc = tree.clone();
-        let t: &BTreeMap<_, (_, _)> = &trc.as_ref().borrow().clone();
+        let t: &BTreeMap<_, (_, _, _)> = &trc.as_ref().borrow().clone();
         let sol = match &t.get(&pid) {
             Some(v) => v.0.clone(),
             None => Solver::new(ctx),
@@ -512,112 +2055,166 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
         drop(trc);
 
         // start the execution from the id
-        for instruction in code.iter().skip_while(|ins| ins.pc < pc) {
+        for instruction in &code[self.cfg.index(pc)..] {
             let opcode = instruction.opcode();
+            // stays reachable unless this is a jump that can't fall through
+            let mut fall = None;
+
+            // branches start after the jump consumed its operands, with their own unroll
+            // bound. Without the operands the jump ends the path below
+            let jumped = match opcode {
+                Jump | Jumpi => self
+                    .step(step.clone(), *instruction)
+                    .ok()
+                    .map(|mut jumped| {
+                        jumped.ret.bounded = false;
+                        jumped
+                    }),
+                _ => None,
+            };
 
-            if opcode == &Jump || opcode == &Jumpi {
+            if let Some(jumped) = jumped {
                 // find potential jump dests
                 let dest = step.stack.peek(0)?;
-
-                dbg!(&dest);
-                // dbg!(&dest.is_app());
-                // dbg!(&dest.is_const());
-
                 let cond = if opcode == &Jumpi {
                     step.stack.peek(1)?
                 } else {
                     z3::ast::BV::from_u64(ctx, 1, 256)
                 };
 
-                // dbg!(&cond);
-
-                // Y shouldn't it be 0 tho ?
-                // let is_reachable = cond.ne(&z3::ast::BV::from_u64(ctx, 0, 256)) || !cond.is_const();
-                // if opcode == &Jumpi {
-                //     sol.assert(
-                //         &cond
-                //             ._eq(&z3::ast::BV::from_u64(ctx, 0, 256))
-                //             .not()
-                //             .simplify(),
-                //     );
-                // }
-
-                if true {
-                    // if symbolic dest, find for all valable destinations
-                    if !dest.is_const() {
-                        // for each potential jump dest
-                        for jd in jdest {
-                            let dest_int = z3::ast::Int::from_u64(ctx, *jd);
-                            sol.push();
-                            sol.assert(&dest_int._eq(&dest.to_int(false)).simplify());
-                            // check if dest is reachable
-                            if sol.check() == SatResult::Sat && !vdest.contains(jd) {
-                                vdest.push(*jd);
-
-                                // TODO: watch out for infinite loops !
-                                if let Ok((t, p)) = Self::path(
-                                    ctx,
-                                    jdest,
-                                    sym,
-                                    code,
-                                    pid + 1,
-                                    tree.clone(),
-                                    vdest,
-                                    step.clone(),
-                                    *jd as usize,
-                                ) {
-                                    pid = p;
+                // the branches come after the steps of this one so far and the jump
+                let forked_at = tree
+                    .borrow()
+                    .get(&last_pid)
+                    .map_or(0, |(_, steps, _)| steps.len())
+                    + 1;
+
+                let zero = z3::ast::BV::from_u64(ctx, 0, 256);
+                let taken = cond._eq(&zero).not().simplify();
+                fall = Some(cond._eq(&zero).simplify());
+
+                // if symbolic dest, find for all valable destinations
+                if !dest.is_const() {
+                    // only the targets of the stack analysis, when it knows all of them
+                    let targets: Vec<u64> = match self.stack.resolved(instruction.pc) {
+                        Some(targets) => targets.iter().map(|target| *target as u64).collect(),
+                        None => jdest.clone(),
+                    };
+                    // for each potential jump dest
+                    for jd in &targets {
+                        let dest_int = z3::ast::Int::from_u64(ctx, *jd);
+                        let lands = dest_int._eq(&dest.to_int(false)).simplify();
+                        sol.push();
+                        sol.assert(&lands);
+                        sol.assert(&taken);
+                        // check if dest is reachable
+                        if sol.check() == SatResult::Sat {
+                            match self.unroll(&visits, *jd, &jumped.stack) {
+                                Some(visits) => {
+                                    let fork = Fork {
+                                        parent: last_pid,
+                                        step: forked_at,
+                                        cond: z3::ast::Bool::and(ctx, &[&lands, &taken]).simplify(),
+                                    };
+                                    tree.borrow_mut()
+                                        .insert(pid + 1, (sol.clone(), Vec::new(), Some(fork)));
+
+                                    (_, pid) = self.path(
+                                        jdest,
+                                        pid + 1,
+                                        tree.clone(),
+                                        visits,
+                                        jumped.clone(),
+                                        *jd as usize,
+                                    )?;
                                 }
+                                None => step.ret.bounded = true,
                             }
-                            sol.pop(1);
                         }
-                    } else if let Some(d) = dest.as_u64() {
-                        if !vdest.contains(&d) {
-                            vdest.push(d);
-
-                            if jdest.contains(&d) {
-                                sol.push();
-                                if let Ok((t, p)) = Self::path(
-                                    ctx,
-                                    jdest,
-                                    sym,
-                                    code,
-                                    pid + 1,
-                                    tree.clone(),
-                                    vdest,
-                                    step.clone(),
-                                    d as usize,
-                                ) {
-                                    pid = p;
-                                }
+                        sol.pop(1);
+                    }
+                } else {
+                    sol.push();
+                    sol.assert(&taken);
+                    if sol.check() == SatResult::Sat {
+                        // a destination past 64 bits is never a JUMPDEST
+                        match dest.as_u64().filter(|d| jdest.contains(d)) {
+                            Some(d) => match self.unroll(&visits, d, &jumped.stack) {
+                                Some(visits) => {
+                                    let fork = Fork {
+                                        parent: last_pid,
+                                        step: forked_at,
+                                        cond: taken.clone(),
+                                    };
+                                    tree.borrow_mut()
+                                        .insert(pid + 1, (sol.clone(), Vec::new(), Some(fork)));
 
-                                sol.pop(1);
+                                    (_, pid) = self.path(
+                                        jdest,
+                                        pid + 1,
+                                        tree.clone(),
+                                        visits,
+                                        jumped.clone(),
+                                        d as usize,
+                                    )?;
+                                }
+                                None => step.ret.bounded = true,
+                            },
+                            // jumping elsewhere reverts, on a branch ending at the jump
+                            None => {
+                                let mut reverted = jumped.clone();
+                                reverted.ret.rev = true;
+                                let fork = Fork {
+                                    parent: last_pid,
+                                    step: forked_at - 1,
+                                    cond: taken.clone(),
+                                };
+                                tree.borrow_mut()
+                                    .insert(pid + 1, (sol.clone(), vec![reverted], Some(fork)));
+                                pid += 1;
                             }
-                        } else {
-                            // already visited
                         }
-                    } else {
-                        step = Self::ret(ctx, step)?;
-                        step.ret.rev = true;
                     }
-                } else {
-                    dbg!("unreachable!");
+
+                    sol.pop(1);
                 }
             }
 
-            // also keep up with the left branch
-            step = Self::step(ctx, sym, step.clone(), *instruction)?;
+            // also keep up with the left branch, it ends on its error
+            step = match self.step(step.clone(), *instruction) {
+                Ok(step) => step,
+                Err(reason) => Self::halt(step, *instruction, reason),
+            };
+            for constraint in step.constraints.drain(..) {
+                sol.assert(&constraint);
+            }
+
+            // the left branch of a jump only continues if the condition can be false
+            let halted = match &fall {
+                Some(fall) => {
+                    sol.push();
+                    sol.assert(fall);
+                    let reachable = sol.check() == SatResult::Sat;
+                    sol.pop(1);
+                    if reachable {
+                        sol.assert(fall);
+                    }
+                    !reachable
+                }
+                None => false,
+            };
+
             let tr = tree.clone();
             let mut t = tr.borrow_mut();
-            if let Some((_sol, steps)) = t.get_mut(&last_pid) {
+            if let Some((_sol, steps, _)) = t.get_mut(&last_pid) {
                 steps.push(step.clone());
                 // keep up with this solver
                 *_sol = sol.clone();
             } else {
-                t.insert(last_pid, (sol.clone(), vec![step.clone()]));
+                t.insert(last_pid, (sol.clone(), vec![step.clone()], None));
             };
 
-            if step.ret.has_ret() {
+            if step.ret.has_ret() || step.ret.unsupported.is_some() || halted {
                 // main thread has returned, get out
                 break;
             }
@@ -634,7 +2231,13 @@ impl<'a: 'ctx, 'ctx> Prover<'a, 'ctx> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::to_mnemonics;
+    use crate::{
+        asm::assemble,
+        bytecode::to_mnemonics,
+        config,
+        helpers::{to_word, U256},
+        opcodes::OpCode,
+    };
     use z3::{Config, SatResult};
 
     #[test]
@@ -666,6 +2269,27 @@ mod tests {
         // dbg!(&model);
     }
 
+    #[test]
+    fn undefined_opcode() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        // PUSH0 PUSH0 RETURN
+        let hex = hex::decode("5F5FF3").unwrap();
+
+        let code = to_mnemonics_for(&hex, Revision::Shanghai);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        assert!(tree[&0].1.last().unwrap().ret().is_success());
+
+        // PUSH0 doesn't exist on london, the first instruction is INVALID
+        let code = to_mnemonics_for(&hex, Revision::London);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        assert!(last.ret().has_ret() && !last.ret().is_success());
+        assert_eq!(last.pc(), 0);
+    }
+
     #[test]
     fn password() {
         let cfg = Config::default();
@@ -722,6 +2346,8 @@ mod tests {
         let code = to_mnemonics(&hex);
         let ctx = Context::new(&cfg);
         let mut prover = Prover::new(&ctx, &code, Contract::default());
+        // the loop is followed once
+        prover.set_max_unroll(1);
         let tree = prover.run().unwrap();
         let sol = &tree[&0].0;
         assert_eq!(tree.keys().len(), 2);
@@ -734,22 +2360,999 @@ mod tests {
     }
 
     #[test]
-    fn dyn_jump() {
+    fn storage() {
+        // sstore(0, 0x2a) then sload(0)
         let cfg = Config::default();
-        let hex = hex::decode("5F35600656FE5B00").unwrap();
+        let hex = hex::decode("602a5f555f5400").unwrap();
         let code = to_mnemonics(&hex);
         let ctx = Context::new(&cfg);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        assert_eq!(last.stack.peek(0).unwrap().as_u64(), Some(0x2a));
+
+        // untouched slots are unknown, unless a pre-state is given
+        let hex = hex::decode("5f5400").unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        assert!(last.stack.peek(0).unwrap().as_u64().is_none());
+
         let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_storage(BTreeMap::from([([0u8; 32], to_word(&[7]))]));
         let tree = prover.run().unwrap();
-        let sol = &tree[&0].0;
-        assert_eq!(sol.check(), SatResult::Sat);
-        assert_eq!(tree.keys().len(), 2);
-        let model = sol.get_model();
-        dbg!(&sol);
-        // dbg!(&model);
+        let last = tree[&0].1.last().unwrap();
+        assert_eq!(last.stack.peek(0).unwrap().as_u64(), Some(7));
     }
 
-    /// only the main thread make the proving revert, not branches
+    #[test]
+    fn transient() {
+        // tstore(0, 0x2a) then tload(0) and tload(1)
+        let cfg = Config::default();
+        let code = assemble("PUSH1 0x2a PUSH0 TSTORE PUSH0 TLOAD PUSH1 0x01 TLOAD STOP").unwrap();
+        let code = to_mnemonics(&code);
+        let ctx = Context::new(&cfg);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        assert_eq!(last.stack.peek(1).unwrap().as_u64(), Some(0x2a));
+        // a transaction starts with an empty transient storage
+        assert_eq!(last.stack.peek(0).unwrap().as_u64(), Some(0));
+        // which is apart from the persistent one
+        assert_eq!(last.storage().iter().count(), 0);
+    }
+
+    #[test]
+    fn block_env() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code = assemble("TIMESTAMP NUMBER CHAINID STOP").unwrap();
+        let code = to_mnemonics(&code);
+        let config = config::Config {
+            timestamp: Some(1000),
+            number: Some(7),
+            chain_id: Some(1),
+            ..Default::default()
+        };
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config.clone());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        assert_eq!(last.stack.peek(2).unwrap().as_u64(), Some(1000));
+        assert_eq!(last.stack.peek(1).unwrap().as_u64(), Some(7));
+        assert_eq!(last.stack.peek(0).unwrap().as_u64(), Some(1));
+
+        // a later transaction is on the same chain, and can't go back in time
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config.next());
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let last = steps.last().unwrap();
+        let timestamp = last.stack.peek(2).unwrap();
+        assert!(timestamp.as_u64().is_none());
+        assert_eq!(last.stack.peek(0).unwrap().as_u64(), Some(1));
+        sol.assert(&timestamp.bvult(&z3::ast::BV::from_u64(&ctx, 1000, 256)));
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn block_hash() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code = assemble(
+            "PUSH2 0x012b BLOCKHASH PUSH2 0x012c BLOCKHASH PUSH1 0x2b BLOCKHASH PUSH2 0x012a BLOCKHASH STOP",
+        )
+        .unwrap();
+        let code = to_mnemonics(&code);
+        let hash = to_word(&[0xaa; 32]);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config::Config {
+            number: Some(300),
+            block_hashes: BTreeMap::from([(299, hash)]),
+            ..Default::default()
+        });
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        let word = |n| bv_to_word(&last.stack.peek(n).unwrap());
+        assert_eq!(word(3), Some(hash));
+        // the current block, and the ones older than 256 blocks, have no hash
+        assert_eq!(word(2), Some([0; 32]));
+        assert_eq!(word(1), Some([0; 32]));
+        // the other recent ones are unknown
+        assert_eq!(word(0), None);
+    }
+
+    #[test]
+    fn unknown_call() {
+        // call(gas, 0xaa, 0, 0, 0, 0, 0) then returndatasize
+        let cfg = Config::default();
+        let hex = hex::decode("5f5f5f5f5f60aa5ff13d00").unwrap();
+        let code = to_mnemonics(&hex);
+        let ctx = Context::new(&cfg);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        // both the success and the size of the output are unknown
+        assert!(last.stack.peek(0).unwrap().as_u64().is_none());
+        assert!(last.stack.peek(1).unwrap().as_u64().is_none());
+    }
+
+    #[test]
+    fn inline_call() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut callee = [0u8; 20];
+        callee[19] = 0xaa;
+
+        // the callee returns nothing, or reverts
+        for (callee_code, success) in [("5f5ff3", 1), ("5f5ffd", 0)] {
+            let mut state = State::new();
+            state.set_code(callee, hex::decode(callee_code).unwrap());
+
+            let hex = hex::decode("5f5f5f5f5f60aa5ff100").unwrap();
+            let code = to_mnemonics(&hex);
+            let mut prover = Prover::new(&ctx, &code, Contract::default());
+            prover.set_state(&state);
+            let tree = prover.run().unwrap();
+            let (sol, steps, _) = &tree[&0];
+            let flag = steps.last().unwrap().stack.peek(0).unwrap();
+
+            assert_eq!(sol.check(), SatResult::Sat);
+            sol.assert(&flag._eq(&z3::ast::BV::from_u64(&ctx, success, 256)).not());
+            assert_eq!(sol.check(), SatResult::Unsat);
+        }
+    }
+
+    #[test]
+    fn call_value() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut callee = [0u8; 20];
+        callee[19] = 0xaa;
+        let mut state = State::new();
+        // the callee reverts unless it is sent some value
+        let payable = "CALLVALUE PUSH1 paid JUMPI PUSH0 PUSH0 REVERT paid: JUMPDEST STOP";
+        state.set_code(callee, assemble(payable).unwrap());
+
+        for (value, success) in [("0x01", 1), ("0x00", 0)] {
+            let hex = assemble(&format!(
+                "PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 {value} PUSH1 0xaa PUSH0 CALL STOP"
+            ))
+            .unwrap();
+            let code = to_mnemonics(&hex);
+            let mut prover = Prover::new(&ctx, &code, Contract::default());
+            prover.set_state(&state);
+            let tree = prover.run().unwrap();
+            let (sol, steps, _) = &tree[&0];
+            let flag = steps.last().unwrap().stack.peek(0).unwrap();
+
+            sol.assert(&flag._eq(&z3::ast::BV::from_u64(&ctx, success, 256)).not());
+            assert_eq!(sol.check(), SatResult::Unsat);
+        }
+
+        // the value of a transaction is symbolic, unless it is pinned
+        let code = assemble(payable).unwrap();
+        let code = to_mnemonics(&code);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        assert_eq!(prover.run().unwrap().len(), 2);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config::Config {
+            value: Some(U256::zero()),
+            ..Default::default()
+        });
+        assert_eq!(prover.run().unwrap().len(), 1);
+
+        // or bounded by the balance of the caller
+        let rich =
+            "PUSH1 0x05 CALLVALUE GT PUSH1 rich JUMPI STOP rich: JUMPDEST PUSH0 PUSH0 REVERT";
+        let code = assemble(rich).unwrap();
+        let code = to_mnemonics(&code);
+        let mut state = State::new();
+        state.set_balance(callee, U256::from(5u8));
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_state(&state);
+        prover.set_config(config::Config {
+            actors: vec![callee],
+            value_in_balance: true,
+            ..Default::default()
+        });
+        assert_eq!(prover.run().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn actors() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let (owner, other) = ([0x11; 20], [0x22; 20]);
+        // only the owner gets through
+        let code = assemble(&format!(
+            "CALLER PUSH20 0x{} EQ PUSH1 owner JUMPI PUSH0 PUSH0 REVERT owner: JUMPDEST STOP",
+            hex::encode(owner)
+        ))
+        .unwrap();
+        let code = to_mnemonics(&code);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config::Config {
+            actors: vec![other],
+            ..Default::default()
+        });
+        let tree = prover.run().unwrap();
+        assert_eq!(tree.len(), 1);
+        assert!(!tree[&0].1.last().unwrap().ret().is_success());
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config::Config {
+            actors: vec![owner, other],
+            ..Default::default()
+        });
+        assert_eq!(prover.run().unwrap().len(), 2);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config::Config {
+            caller: Some(owner),
+            ..Default::default()
+        });
+        let tree = prover.run().unwrap();
+        let witness = prover.witness(&tree, 0, None).unwrap();
+        assert_eq!(witness.caller, owner);
+    }
+
+    #[test]
+    fn delegatecall() {
+        // the callee writes 0x2a at slot 0 of the caller, which is read back
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut callee = [0u8; 20];
+        callee[19] = 0xaa;
+        let mut state = State::new();
+        state.set_code(callee, hex::decode("602a5f5500").unwrap());
+
+        let hex = hex::decode("5f5f5f5f60aa5ff45f5400").unwrap();
+        let code = to_mnemonics(&hex);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_state(&state);
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let slot = steps.last().unwrap().stack.peek(0).unwrap();
+
+        assert_eq!(sol.check(), SatResult::Sat);
+        sol.assert(&slot._eq(&z3::ast::BV::from_u64(&ctx, 0x2a, 256)).not());
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn call_storage() {
+        // the callee only succeeds on an empty slot 0, which it sets
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut callee = [0u8; 20];
+        callee[19] = 0xaa;
+        let mut state = State::new();
+        let once = "PUSH0 SLOAD PUSH1 taken JUMPI PUSH1 0x01 PUSH0 SSTORE STOP \
+                    taken: JUMPDEST PUSH0 PUSH0 REVERT";
+        state.set_code(callee, assemble(once).unwrap());
+        state.set_storage(callee, U256::zero(), U256::zero());
+
+        // so calling it twice only succeeds the first time
+        let call = "PUSH0 PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 CALL";
+        let code = assemble(&format!("{call} {call} STOP")).unwrap();
+        let code = to_mnemonics(&code);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_state(&state);
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let stack = &steps.last().unwrap().stack;
+        let (second, first) = (stack.peek(0).unwrap(), stack.peek(1).unwrap());
+
+        assert_eq!(sol.check(), SatResult::Sat);
+        sol.assert(&z3::ast::Bool::or(
+            &ctx,
+            &[
+                &first._eq(&z3::ast::BV::from_u64(&ctx, 1, 256)).not(),
+                &second._eq(&z3::ast::BV::from_u64(&ctx, 0, 256)).not(),
+            ],
+        ));
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn reentrancy() {
+        // slot 0 is set to 0x2a, or only read, before calling a callee, then read back
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut callee = [0u8; 20];
+        callee[19] = 0xaa;
+        let reenter = "PUSH0 PUSH0 PUSH0 PUSH0 PUSH0 CALLER PUSH0 CALL STOP";
+        let call = "PUSH0 PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 CALL";
+        let staticcall = "PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 STATICCALL";
+
+        // a callee calling back could have changed it, unless it ran in a static call,
+        // a callee running no other code couldn't
+        for (callee_code, call, kept) in [
+            (reenter, call, false),
+            (reenter, staticcall, true),
+            ("STOP", call, true),
+        ] {
+            let mut state = State::new();
+            state.set_code(callee, assemble(callee_code).unwrap());
+
+            for prologue in ["PUSH1 0x2a PUSH0 SSTORE PUSH1 0x2a", "PUSH0 SLOAD"] {
+                let code = assemble(&format!("{prologue} {call} POP PUSH0 SLOAD STOP")).unwrap();
+                let code = to_mnemonics(&code);
+                let mut prover = Prover::new(&ctx, &code, Contract::default());
+                prover.set_state(&state);
+                let tree = prover.run().unwrap();
+                let (sol, steps, _) = &tree[&0];
+                let stack = &steps.last().unwrap().stack;
+                let (after, before) = (stack.peek(0).unwrap(), stack.peek(1).unwrap());
+
+                sol.assert(&after._eq(&before).not());
+                let expected = if kept {
+                    SatResult::Unsat
+                } else {
+                    SatResult::Sat
+                };
+                assert_eq!(sol.check(), expected, "{callee_code} {prologue} {call}");
+            }
+        }
+    }
+
+    #[test]
+    fn staticcall() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let mut callee = [0u8; 20];
+        callee[19] = 0xaa;
+
+        // a static callee fails on any change of the state
+        for (callee_code, success) in [
+            ("STOP", 1),
+            ("PUSH1 0x01 PUSH0 SSTORE STOP", 0),
+            ("PUSH0 PUSH0 LOG0 STOP", 0),
+            ("PUSH0 PUSH0 PUSH0 CREATE STOP", 0),
+            (
+                "PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0x01 PUSH1 0xbb PUSH0 CALL STOP",
+                0,
+            ),
+        ] {
+            let mut state = State::new();
+            state.set_code(callee, assemble(callee_code).unwrap());
+
+            let code =
+                assemble("PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 STATICCALL STOP").unwrap();
+            let code = to_mnemonics(&code);
+            let mut prover = Prover::new(&ctx, &code, Contract::default());
+            prover.set_state(&state);
+            let tree = prover.run().unwrap();
+            let (sol, steps, _) = &tree[&0];
+            let flag = steps.last().unwrap().stack.peek(0).unwrap();
+
+            sol.assert(&flag._eq(&z3::ast::BV::from_u64(&ctx, success, 256)).not());
+            assert_eq!(sol.check(), SatResult::Unsat, "{callee_code}");
+        }
+    }
+
+    #[test]
+    fn create() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+
+        // create(0, 0, 0) from an account with a nonce of 0
+        let sender = hex::decode("6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0").unwrap();
+        let sender = to_address(&to_word(&sender));
+        let mut state = State::new();
+        state.set_nonce(sender, 0);
+
+        let hex = hex::decode("5f5f5ff000").unwrap();
+        let code = to_mnemonics(&hex);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_address(sender);
+        prover.set_state(&state);
+        let tree = prover.run().unwrap();
+        let created = tree[&0].1.last().unwrap().stack.peek(0).unwrap();
+        let expected = hex::decode("cd234a471b72ba2f1ccf0a70fcaba648a5eecd8d").unwrap();
+        assert_eq!(bv_to_word(&created), Some(to_word(&expected)));
+
+        // create2(0, 0, 0, 0) from the zero address
+        let hex = hex::decode("5f5f5f5ff500").unwrap();
+        let code = to_mnemonics(&hex);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_address([0; 20]);
+        let tree = prover.run().unwrap();
+        let created = tree[&0].1.last().unwrap().stack.peek(0).unwrap();
+        let expected = hex::decode("e33c0c7f7df4809055c3eba6c09cfe4baf1bd9e0").unwrap();
+        assert_eq!(bv_to_word(&created), Some(to_word(&expected)));
+    }
+
+    #[test]
+    fn logs() {
+        // log2(0, 0, keccak256("Ping(uint256)"), 7)
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex = hex::decode(
+            "60077f48257dc961b6f792c2b78a080dacfed693b660960a702de21cee364e20270e2f5f5fa200",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let abi = Contract::load(
+            br#"[{"type":"event","name":"Ping","anonymous":false,"inputs":[{"name":"n","type":"uint256","indexed":true}]}]"#
+                .as_slice(),
+        )
+        .unwrap();
+        let prover = Prover::new(&ctx, &code, abi);
+        let tree = prover.run().unwrap();
+        let logs = path_logs(&tree, 0);
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].topics.len(), 2);
+        assert!(logs[0].data.is_none());
+
+        assert_eq!(prover.event(&logs[0]).unwrap().name, "Ping");
+        let decoded = prover.decode_log(&logs[0]).unwrap();
+        assert_eq!(
+            decoded.params[0].value,
+            ethabi::Token::Uint(ethabi::Uint::from(7))
+        );
+    }
+
+    #[test]
+    fn abi_calls() {
+        // reverts unless the first argument is 0x2a
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex = assemble(
+            "
+            PUSH1 0x04 CALLDATALOAD PUSH1 0x2a EQ PUSH1 ok JUMPI
+            PUSH0 PUSH0 REVERT
+            ok: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let abi = Contract::load(
+            br#"[{"type":"function","name":"f","stateMutability":"nonpayable","outputs":[],"inputs":[{"name":"x","type":"uint8"}]}]"#
+                .as_slice(),
+        )
+        .unwrap();
+        let prover = Prover::new(&ctx, &code, abi);
+        let calls = prover.run_abi().unwrap();
+        assert_eq!(calls.len(), 1);
+
+        let (call, tree) = &calls[0];
+        let (sol, steps, _) = &tree[&1];
+        assert!(steps.last().unwrap().ret().is_success());
+        assert_eq!(sol.check(), SatResult::Sat);
+        let tokens = call.decode(&sol.get_model().unwrap()).unwrap();
+        assert_eq!(tokens, vec![ethabi::Token::Uint(ethabi::Uint::from(0x2a))]);
+    }
+
+    #[test]
+    fn calldata() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code =
+            assemble("PUSH0 CALLDATALOAD PUSH1 0x04 CALLDATALOAD PUSH1 0x20 CALLDATALOAD STOP")
+                .unwrap();
+        let code = to_mnemonics(&code);
+
+        // overlapping loads read the same bytes
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let stack = &steps.last().unwrap().stack;
+        let (first, second) = (stack.peek(2).unwrap(), stack.peek(1).unwrap());
+        sol.assert(&first.extract(223, 0)._eq(&second.extract(255, 32)).not());
+        assert_eq!(sol.check(), SatResult::Unsat);
+
+        // and zeros past the end
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let bytes = to_bv(&ctx, &[0xff; 32]).concat(&z3::ast::BV::from_u64(&ctx, 0xaabbccdd, 32));
+        prover.constrain_calldata(Some(&bytes));
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let stack = &steps.last().unwrap().stack;
+        let mut tail = [0; 32];
+        tail[..4].copy_from_slice(&[0xaa, 0xbb, 0xcc, 0xdd]);
+        let differs = |n, word: [u8; 32]| stack.peek(n).unwrap()._eq(&to_bv(&ctx, &word)).not();
+        sol.assert(&z3::ast::Bool::or(
+            &ctx,
+            &[&differs(2, [0xff; 32]), &differs(0, tail)],
+        ));
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn symbolic_memory() {
+        // mstore(calldata(0), 0x2a) mload(calldata(0)) mstore8(0x3f, 0x1234) msize
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code = assemble(
+            "PUSH1 0x2a PUSH0 CALLDATALOAD MSTORE PUSH0 CALLDATALOAD MLOAD \
+             PUSH2 0x1234 PUSH1 0x3f MSTORE8 MSIZE STOP",
+        )
+        .unwrap();
+        let code = to_mnemonics(&code);
+
+        for model in [MemoryModel::Concat, MemoryModel::Array] {
+            let mut prover = Prover::new(&ctx, &code, Contract::default());
+            prover.set_memory_model(model);
+            let tree = prover.run().unwrap();
+            let (sol, steps, _) = &tree[&0];
+            let stack = &steps.last().unwrap().stack;
+            // the memory grows at least up to the byte written by MSTORE8
+            sol.assert(&z3::ast::Bool::or(
+                &ctx,
+                &[
+                    &stack.peek(1).unwrap()._eq(&to_bv(&ctx, &[0x2a])).not(),
+                    &stack.peek(0).unwrap().bvult(&to_bv(&ctx, &[0x40])),
+                ],
+            ));
+            assert_eq!(sol.check(), SatResult::Unsat);
+        }
+    }
+
+    #[test]
+    fn exp() {
+        // 3^5, 0x100^calldata(0), calldata(0)^calldata(0x20)
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code = assemble(
+            "PUSH1 0x05 PUSH1 0x03 EXP PUSH0 CALLDATALOAD PUSH2 0x0100 EXP \
+             PUSH1 0x20 CALLDATALOAD PUSH0 CALLDATALOAD EXP STOP",
+        )
+        .unwrap();
+        let code = to_mnemonics(&code);
+
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let stack = &steps.last().unwrap().stack;
+        assert_eq!(stack.peek(2).unwrap().as_u64(), Some(243));
+
+        // 0x100^2 and 0x100^32, with a symbolic exponent
+        let calldata = |n| {
+            let index = to_bv(&ctx, &[n]);
+            prover.calldata_bytes(&index, 32)
+        };
+        let shifted = stack.peek(1).unwrap();
+        sol.push();
+        sol.assert(&calldata(0)._eq(&to_bv(&ctx, &[2])));
+        sol.assert(&shifted._eq(&to_bv(&ctx, &[1, 0, 0])).not());
+        assert_eq!(sol.check(), SatResult::Unsat);
+        sol.pop(1);
+        sol.push();
+        sol.assert(&calldata(0)._eq(&to_bv(&ctx, &[32])));
+        sol.assert(&shifted._eq(&to_bv(&ctx, &[0])).not());
+        assert_eq!(sol.check(), SatResult::Unsat);
+        sol.pop(1);
+
+        // x^1 is x, whatever x
+        let power = stack.peek(0).unwrap();
+        sol.assert(&calldata(0x20)._eq(&to_bv(&ctx, &[1])));
+        sol.assert(&power._eq(&calldata(0)).not());
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    /// Every instruction with its operands, and the word it leaves on the stack when it is
+    /// concrete. The code is followed by a STOP.
+    const CONFORMANCE: &[(&str, &str, Option<i64>)] = &[
+        ("STOP", "STOP", None),
+        ("ADD", "PUSH1 0x02 PUSH1 0x03 ADD", Some(5)),
+        ("MUL", "PUSH1 0x02 PUSH1 0x03 MUL", Some(6)),
+        ("SUB", "PUSH1 0x02 PUSH1 0x03 SUB", Some(1)),
+        ("DIV", "PUSH0 PUSH1 0x07 DIV", Some(0)),
+        ("SDIV", "PUSH1 0x02 PUSH1 0x04 PUSH0 SUB SDIV", Some(-2)),
+        ("MOD", "PUSH1 0x03 PUSH1 0x0a MOD", Some(1)),
+        ("SMOD", "PUSH1 0x03 PUSH1 0x0a PUSH0 SUB SMOD", Some(-1)),
+        ("ADDMOD", "PUSH1 0x05 PUSH1 0x01 PUSH0 NOT ADDMOD", Some(1)),
+        ("MULMOD", "PUSH1 0x07 PUSH0 NOT PUSH0 NOT MULMOD", Some(1)),
+        ("EXP", "PUSH1 0x05 PUSH1 0x03 EXP", Some(243)),
+        ("SIGNEXTEND", "PUSH1 0xff PUSH0 SIGNEXTEND", Some(-1)),
+        ("LT", "PUSH1 0x02 PUSH1 0x01 LT", Some(1)),
+        ("GT", "PUSH1 0x02 PUSH1 0x01 GT", Some(0)),
+        ("SLT", "PUSH1 0x01 PUSH0 NOT SLT", Some(1)),
+        ("SGT", "PUSH1 0x01 PUSH0 NOT SGT", Some(0)),
+        ("EQ", "PUSH1 0x01 PUSH1 0x01 EQ", Some(1)),
+        ("ISZERO", "PUSH0 ISZERO", Some(1)),
+        ("AND", "PUSH1 0x0c PUSH1 0x0a AND", Some(8)),
+        ("OR", "PUSH1 0x0c PUSH1 0x0a OR", Some(14)),
+        ("XOR", "PUSH1 0x0c PUSH1 0x0a XOR", Some(6)),
+        ("NOT", "PUSH0 NOT", Some(-1)),
+        ("BYTE", "PUSH2 0x1234 PUSH1 0x1e BYTE", Some(0x12)),
+        ("SHL", "PUSH1 0x01 PUSH1 0x04 SHL", Some(16)),
+        ("SHR", "PUSH1 0x10 PUSH1 0x04 SHR", Some(1)),
+        ("SAR", "PUSH1 0x10 PUSH0 SUB PUSH1 0x04 SAR", Some(-1)),
+        ("SHA3", "PUSH0 PUSH0 SHA3", None),
+        ("ADDRESS", "ADDRESS", None),
+        ("BALANCE", "PUSH0 BALANCE", None),
+        ("ORIGIN", "ORIGIN", None),
+        ("CALLER", "CALLER", None),
+        ("CALLVALUE", "CALLVALUE", None),
+        ("CALLDATALOAD", "PUSH0 CALLDATALOAD", None),
+        ("CALLDATASIZE", "CALLDATASIZE", None),
+        (
+            "CALLDATACOPY",
+            "PUSH1 0x20 PUSH0 PUSH0 CALLDATACOPY MSIZE",
+            Some(32),
+        ),
+        ("CODESIZE", "CODESIZE", None),
+        (
+            "CODECOPY",
+            "PUSH1 0x20 PUSH0 PUSH0 CODECOPY MSIZE",
+            Some(32),
+        ),
+        ("GASPRICE", "GASPRICE", None),
+        ("EXTCODESIZE", "PUSH0 EXTCODESIZE", None),
+        (
+            "EXTCODECOPY",
+            "PUSH1 0x20 PUSH0 PUSH0 PUSH0 EXTCODECOPY MSIZE",
+            Some(32),
+        ),
+        ("RETURNDATASIZE", "RETURNDATASIZE", Some(0)),
+        (
+            "RETURNDATACOPY",
+            "PUSH0 PUSH0 PUSH0 RETURNDATACOPY MSIZE",
+            Some(0),
+        ),
+        ("EXTCODEHASH", "PUSH0 EXTCODEHASH", None),
+        ("BLOCKHASH", "PUSH0 BLOCKHASH", None),
+        ("COINBASE", "COINBASE", None),
+        ("TIMESTAMP", "TIMESTAMP", None),
+        ("NUMBER", "NUMBER", None),
+        ("DIFFICULTY", "DIFFICULTY", None),
+        ("GASLIMIT", "GASLIMIT", None),
+        ("CHAINID", "CHAINID", None),
+        ("SELFBALANCE", "SELFBALANCE", None),
+        ("BASEFEE", "BASEFEE", None),
+        ("BLOBHASH", "PUSH0 BLOBHASH", None),
+        ("BLOBBASEFEE", "BLOBBASEFEE", None),
+        ("POP", "PUSH1 0x01 PUSH1 0x02 POP", Some(1)),
+        ("MLOAD", "PUSH1 0x2a PUSH0 MSTORE PUSH0 MLOAD", Some(42)),
+        ("MSTORE", "PUSH1 0x2a PUSH1 0x20 MSTORE MSIZE", Some(64)),
+        (
+            "MSTORE8",
+            "PUSH2 0x1234 PUSH1 0x1f MSTORE8 PUSH0 MLOAD",
+            Some(0x34),
+        ),
+        ("SLOAD", "PUSH0 SLOAD", None),
+        (
+            "SSTORE",
+            "PUSH1 0x2a PUSH1 0x01 SSTORE PUSH1 0x01 SLOAD",
+            Some(42),
+        ),
+        (
+            "JUMP",
+            "PUSH1 0x04 JUMP INVALID JUMPDEST PUSH1 0x01",
+            Some(1),
+        ),
+        (
+            "JUMPI",
+            "PUSH1 0x01 PUSH1 0x06 JUMPI INVALID JUMPDEST PUSH1 0x01",
+            Some(1),
+        ),
+        ("PC", "PUSH0 POP PC", Some(2)),
+        ("MSIZE", "PUSH0 MLOAD POP MSIZE", Some(32)),
+        ("GAS", "GAS", None),
+        ("JUMPDEST", "JUMPDEST PUSH1 0x01", Some(1)),
+        ("TLOAD", "PUSH1 0x2a PUSH0 TSTORE PUSH0 TLOAD", Some(42)),
+        (
+            "TSTORE",
+            "PUSH1 0x2a PUSH1 0x01 TSTORE PUSH1 0x01 TLOAD",
+            Some(42),
+        ),
+        (
+            "MCOPY",
+            "PUSH1 0x2a PUSH0 MSTORE PUSH1 0x20 PUSH0 PUSH1 0x20 MCOPY PUSH1 0x20 MLOAD",
+            Some(42),
+        ),
+        ("CREATE", "PUSH0 PUSH0 PUSH0 CREATE", None),
+        (
+            "CALL",
+            "PUSH0 PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 CALL",
+            None,
+        ),
+        (
+            "CALLCODE",
+            "PUSH0 PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 CALLCODE",
+            None,
+        ),
+        ("RETURN", "PUSH0 PUSH0 RETURN", None),
+        (
+            "DELEGATECALL",
+            "PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 DELEGATECALL",
+            None,
+        ),
+        ("CREATE2", "PUSH0 PUSH0 PUSH0 PUSH0 CREATE2", None),
+        (
+            "STATICCALL",
+            "PUSH0 PUSH0 PUSH0 PUSH0 PUSH1 0xaa PUSH0 STATICCALL",
+            None,
+        ),
+        ("REVERT", "PUSH0 PUSH0 REVERT", None),
+        ("INVALID", "INVALID", None),
+        ("SELFDESTRUCT", "PUSH0 SELFDESTRUCT", None),
+    ];
+
+    /// run `source` and check the last step of each of its successful paths
+    fn conformance_run(source: &str, check: impl Fn(&Step)) {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code = assemble(&format!("{source} STOP")).unwrap();
+        let code = to_mnemonics(&code);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover
+            .run()
+            .unwrap_or_else(|reason| panic!("{source}: {reason:?}"));
+        for (_, steps, _) in tree.values() {
+            let last = steps.last().unwrap();
+            if last.ret.is_success() {
+                check(last);
+            }
+        }
+    }
+
+    #[test]
+    fn conformance() {
+        let word = |value: i64| {
+            let mut word = [if value < 0 { 0xff } else { 0 }; 32];
+            word[24..].copy_from_slice(&value.to_be_bytes());
+            word
+        };
+
+        for (name, source, expected) in CONFORMANCE {
+            conformance_run(source, |last| {
+                if let Some(expected) = expected {
+                    let top = bv_to_word(&last.stack.peek(0).unwrap());
+                    assert_eq!(top, Some(word(*expected)), "{name}");
+                }
+            });
+        }
+
+        for n in 0..=32 {
+            let source = match n {
+                0 => "PUSH0".to_string(),
+                n => format!("PUSH{n} 0x2a"),
+            };
+            let expected = if n == 0 { 0 } else { 42 };
+            conformance_run(&source, |last| {
+                assert_eq!(
+                    last.stack.peek(0).unwrap().as_u64(),
+                    Some(expected),
+                    "{source}"
+                );
+            });
+        }
+
+        // the n-th word from the top is n
+        let words = |n: u8| {
+            (1..=n)
+                .rev()
+                .map(|i| format!("PUSH1 {i:#04x}"))
+                .collect::<Vec<_>>()
+        };
+        for n in 1..=16u8 {
+            let source = format!("{} DUP{n}", words(16).join(" "));
+            conformance_run(&source, |last| {
+                assert_eq!(
+                    last.stack.peek(0).unwrap().as_u64(),
+                    Some(n.into()),
+                    "DUP{n}"
+                );
+            });
+            let source = format!("{} SWAP{n}", words(17).join(" "));
+            conformance_run(&source, |last| {
+                let (top, swapped) = (
+                    last.stack.peek(0).unwrap(),
+                    last.stack.peek(n.into()).unwrap(),
+                );
+                assert_eq!(top.as_u64(), Some(u64::from(n) + 1), "SWAP{n}");
+                assert_eq!(swapped.as_u64(), Some(1), "SWAP{n}");
+            });
+        }
+
+        for n in 0..=4 {
+            let source = format!("{} PUSH0 PUSH0 LOG{n}", words(n).join(" "));
+            conformance_run(&source, |last| {
+                assert_eq!(last.logs.len(), 1, "LOG{n}");
+                assert_eq!(last.logs[0].topics.len(), usize::from(n), "LOG{n}");
+            });
+        }
+
+        // every instruction of the latest revision is covered
+        for op in (0..=u8::MAX)
+            .map(OpCode::from_u8)
+            .filter(OpCode::is_defined)
+        {
+            let family = op.is_push() || op.is_dup() || op.is_swap() || op.is_log();
+            let covered = CONFORMANCE.iter().any(|(name, ..)| *name == op.name());
+            assert!(family || covered, "{} is not covered", op.name());
+        }
+    }
+
+    #[test]
+    fn gas() {
+        // sstore(0, 1) sstore(0, 2) gas
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex =
+            assemble("PUSH1 0x01 PUSH1 0x00 SSTORE PUSH1 0x02 PUSH1 0x00 SSTORE GAS STOP").unwrap();
+
+        let code = to_mnemonics_for(&hex, Revision::Cancun);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_storage(BTreeMap::new());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        // 3 + 3 + 20000 + 2100 from a cold zero slot, 3 + 3 + 100 once dirty, and 2 for GAS
+        assert_eq!(last.gas_used().as_u64(), Some(22214));
+        assert!(prover.exceeds_gas(&tree, 0, 22000));
+        assert!(!prover.exceeds_gas(&tree, 0, 22214));
+
+        // the remaining gas is symbolic, and covers what was used
+        let (sol, steps, _) = &tree[&0];
+        let remaining = steps.last().unwrap().stack.peek(0).unwrap();
+        assert_eq!(remaining.as_u64(), None);
+        sol.assert(&remaining.bvugt(&to_bv(&ctx, &(u64::MAX - 22214).to_be_bytes())));
+        assert_eq!(sol.check(), SatResult::Unsat);
+
+        // no cold access nor net metering before berlin
+        let code = to_mnemonics_for(&hex, Revision::Petersburg);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_storage(BTreeMap::new());
+        let tree = prover.run().unwrap();
+        let last = tree[&0].1.last().unwrap();
+        assert_eq!(last.gas_used().as_u64(), Some(25014));
+    }
+
+    #[test]
+    fn mapping() {
+        // m[calldata(0x20)] after m[calldata(0)] = 1, for a mapping m at slot 0
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let code = assemble(
+            "PUSH1 0x20 CALLDATALOAD PUSH0 MSTORE PUSH1 0x40 PUSH0 SHA3 \
+             PUSH1 0x01 PUSH0 CALLDATALOAD PUSH0 MSTORE PUSH1 0x40 PUSH0 SHA3 SSTORE \
+             SLOAD PUSH0 CALLDATALOAD PUSH1 0x20 CALLDATALOAD STOP",
+        )
+        .unwrap();
+        let code = to_mnemonics(&code);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_storage(BTreeMap::new());
+        let tree = prover.run().unwrap();
+        let (sol, steps, _) = &tree[&0];
+        let stack = &steps.last().unwrap().stack;
+        let (value, key, other) = (
+            stack.peek(2).unwrap(),
+            stack.peek(1).unwrap(),
+            stack.peek(0).unwrap(),
+        );
+        // the entries are the same only for the same keys
+        let zero = to_bv(&ctx, &[0]);
+        sol.assert(&value._eq(&zero).not()._eq(&key._eq(&other)).not());
+        assert_eq!(sol.check(), SatResult::Unsat);
+    }
+
+    #[test]
+    fn witness() {
+        // reverts unless calldata(0) is 0x2a and slot 1 is 7
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex = assemble(
+            "
+            PUSH0 CALLDATALOAD PUSH1 0x2a EQ
+            PUSH1 0x01 SLOAD PUSH1 0x07 EQ
+            AND PUSH1 ok JUMPI
+            PUSH0 PUSH0 REVERT
+            ok: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        assert!(tree[&1].1.last().unwrap().ret().is_success());
+
+        let witness = prover.witness(&tree, 1, None).unwrap();
+        assert!(witness.calldata.len() >= 32);
+        assert_eq!(witness.calldata[31], 0x2a);
+        assert!(witness.calldata[..31].iter().all(|b| *b == 0));
+        assert_eq!(
+            witness.storage,
+            BTreeMap::from([(to_word(&[1]), to_word(&[7]))])
+        );
+        assert!(witness.call.is_none());
+    }
+
+    #[test]
+    fn abi_witness() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex = assemble(
+            "
+            PUSH1 0x04 CALLDATALOAD PUSH1 0x2a EQ PUSH1 ok JUMPI
+            PUSH0 PUSH0 REVERT
+            ok: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let abi = Contract::load(
+            br#"[{"type":"function","name":"f","stateMutability":"nonpayable","outputs":[],"inputs":[{"name":"x","type":"uint8"}]}]"#
+                .as_slice(),
+        )
+        .unwrap();
+        let prover = Prover::new(&ctx, &code, abi);
+        let calls = prover.run_abi().unwrap();
+        let (call, tree) = &calls[0];
+
+        let witness = prover.witness(tree, 1, Some(call)).unwrap();
+        assert_eq!(witness.calldata.len(), 36);
+        let (name, tokens) = witness.call.unwrap();
+        assert_eq!(name, "f");
+        assert_eq!(tokens, vec![ethabi::Token::Uint(ethabi::Uint::from(0x2a))]);
+    }
+
+    #[test]
+    fn dyn_jump() {
+        let cfg = Config::default();
+        let hex = hex::decode("5F35600656FE5B00").unwrap();
+        let code = to_mnemonics(&hex);
+        let ctx = Context::new(&cfg);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let sol = &tree[&0].0;
+        assert_eq!(sol.check(), SatResult::Sat);
+        assert_eq!(tree.keys().len(), 2);
+        let model = sol.get_model();
+        dbg!(&sol);
+        // dbg!(&model);
+    }
+
+    /// a jump to a constant that is no JUMPDEST reverts, on a branch of its own
+    #[test]
+    fn invalid_jump() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        // the trace of each halted branch, and whether it succeeded
+        let ends = |tree: &Tree| -> Vec<(Vec<usize>, bool)> {
+            tree.keys()
+                .filter_map(|pid| {
+                    let ret = tree[pid].1.last()?.ret();
+                    ret.has_ret()
+                        .then(|| (path_trace(tree, *pid), ret.is_success()))
+                })
+                .collect()
+        };
+
+        let hex = assemble("PUSH1 0x05 JUMP STOP").unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        assert_eq!(ends(&tree), vec![(vec![0, 2], false)]);
+
+        // the fall through of a JUMPI still stops, to a byte that is no JUMPDEST
+        // or past 64 bits alike
+        for dest in ["PUSH1 0x05", "PUSH9 0x010000000000000000"] {
+            let hex = assemble(&format!("PUSH0 CALLDATALOAD {dest} JUMPI STOP")).unwrap();
+            let code = to_mnemonics(&hex);
+            let jumpi = code.iter().find(|mn| mn.opcode() == &Jumpi).unwrap().pc;
+            let prover = Prover::new(&ctx, &code, Contract::default());
+            let tree = prover.run().unwrap();
+            assert_eq!(
+                ends(&tree),
+                vec![
+                    (vec![0, 1, 2, jumpi, jumpi + 1], true),
+                    (vec![0, 1, 2, jumpi], false)
+                ]
+            );
+        }
+    }
+
+    /// an error of the main thread ends its branch, as it does for the other branches
     #[test]
     fn main_reverts() {
         let cfg = Config::default();
@@ -757,7 +3360,16 @@ mod tests {
         let code = to_mnemonics(&hex);
         let ctx = Context::new(&cfg);
         let mut prover = Prover::new(&ctx, &code, Contract::default());
-        assert!(prover.run().is_err());
+        let tree = prover.run().unwrap();
+        let ret = tree[&0].1.last().unwrap().ret();
+        assert!(ret.has_ret() && !ret.is_success());
+
+        // a symbolic size of returned data is kept unsupported
+        let hex = assemble("PUSH0 CALLDATALOAD PUSH0 RETURN").unwrap();
+        let code = to_mnemonics(&hex);
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        assert_eq!(tree[&0].1.last().unwrap().ret().unsupported(), Some(3));
 
         let cfg = Config::default();
         let hex = hex::decode("600160065F5B50").unwrap();
@@ -767,6 +3379,142 @@ mod tests {
         assert!(prover.run().is_ok());
     }
 
+    /// a branch stopping on an unsupported operand is kept, and doesn't hide the next ones
+    #[test]
+    fn unsupported_branch() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex = assemble(
+            "
+            PUSH0 CALLDATALOAD PUSH1 symbolic JUMPI
+            PUSH1 0x20 CALLDATALOAD PUSH1 empty JUMPI
+            STOP
+            symbolic: JUMPDEST PUSH0 CALLDATALOAD PUSH0 RETURN
+            empty: JUMPDEST PUSH0 PUSH0 RETURN
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+        let ret = code.iter().find(|mn| mn.opcode() == &Return).unwrap().pc;
+        let prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+
+        let lasts: Vec<_> = tree
+            .values()
+            .filter_map(|(_, steps, _)| steps.last())
+            .collect();
+        assert_eq!(lasts.len(), 3);
+        let unsupported: Vec<_> = lasts
+            .iter()
+            .filter_map(|last| last.ret().unsupported())
+            .collect();
+        assert_eq!(unsupported, vec![ret]);
+        assert_eq!(
+            lasts.iter().filter(|last| last.ret().is_success()).count(),
+            2
+        );
+    }
+
+    /// the transaction is constrained on each run, the prover's solver is left untouched
+    #[test]
+    fn repeated_runs() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        let hex = assemble("CALLER PUSH0 SSTORE STOP").unwrap();
+        let code = to_mnemonics(&hex);
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_config(config::Config {
+            caller: Some([0x11; 20]),
+            ..Default::default()
+        });
+
+        let (first, second) = (prover.run().unwrap(), prover.run().unwrap());
+        assert_eq!(
+            first[&0].0.get_assertions().len(),
+            second[&0].0.get_assertions().len()
+        );
+        assert!(prover.sol.get_assertions().is_empty());
+    }
+
+    /// a loop over calldata is unrolled on each path up to the bound
+    #[test]
+    fn unroll() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        // for (i = 0; i < calldata(0); i++) {}, 34 gas and 43 more per iteration
+        let hex = assemble(
+            "
+            PUSH0 CALLDATALOAD PUSH0
+            loop: JUMPDEST DUP2 DUP2 LT ISZERO PUSH1 end JUMPI
+            PUSH1 0x01 ADD PUSH1 loop JUMP
+            end: JUMPDEST STOP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        let tree = prover.run().unwrap();
+        let exits = |tree: &Tree| {
+            tree.values()
+                .filter(|(_, steps, _)| steps.last().is_some_and(|step| step.ret().is_success()))
+                .count()
+        };
+        let over = |prover: &Prover, tree: &Tree| {
+            tree.keys()
+                .filter(|pid| prover.exceeds_gas(tree, **pid, 200))
+                .count()
+        };
+        let bounded = |tree: &Tree| {
+            tree.values()
+                .filter(|(_, steps, _)| steps.last().is_some_and(|step| step.ret().bounded()))
+                .count()
+        };
+        // leaving after 0 to 4 iterations, the 4th one costing 206 gas
+        // as the path cut before the 5th
+        assert_eq!(exits(&tree), MAX_UNROLL + 1);
+        assert_eq!(over(&prover, &tree), 2);
+        assert_eq!(bounded(&tree), 1);
+
+        prover.set_max_unroll(2);
+        let tree = prover.run().unwrap();
+        assert_eq!(exits(&tree), 3);
+        assert_eq!(over(&prover, &tree), 0);
+        assert_eq!(bounded(&tree), 1);
+    }
+
+    /// the unroll bound counts the jumps within the same internal function calls
+    #[test]
+    fn unroll_calls() {
+        let cfg = Config::default();
+        let ctx = Context::new(&cfg);
+        // f is called from three places, more than the bound
+        let hex = assemble(
+            "
+            PUSH1 r1 PUSH1 f JUMP
+            r1: JUMPDEST PUSH1 r2 PUSH1 f JUMP
+            r2: JUMPDEST PUSH1 r3 PUSH1 f JUMP
+            r3: JUMPDEST STOP
+            f: JUMPDEST JUMP
+            ",
+        )
+        .unwrap();
+        let code = to_mnemonics(&hex);
+
+        let mut prover = Prover::new(&ctx, &code, Contract::default());
+        prover.set_max_unroll(2);
+        let tree = prover.run().unwrap();
+        let lasts: Vec<_> = tree
+            .values()
+            .filter_map(|(_, steps, _)| steps.last())
+            .collect();
+        assert!(lasts.iter().all(|last| !last.ret().bounded()));
+        assert_eq!(
+            lasts.iter().filter(|last| last.ret().is_success()).count(),
+            1
+        );
+    }
+
     #[test]
     fn payable() {
         // https://github.com/huff-language/huffmate/blob/main/src/auth/NonPayable.huff
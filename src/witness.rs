@@ -0,0 +1,129 @@
+use crate::helpers::{Address, Env, Word};
+use ethabi::{Contract, Token};
+use serde_json::json;
+use std::{collections::BTreeMap, fmt::Display};
+
+/// Concrete inputs that drive the execution down a path
+#[derive(Debug, Clone, PartialEq)]
+pub struct Witness {
+    pub calldata: Vec<u8>,
+    pub value: Word,
+    pub caller: Address,
+    /// account executing the code
+    pub address: Address,
+    /// block and transaction environment, with the caller and the value above
+    pub env: Env,
+    /// gas available to the call
+    pub gas: u64,
+    /// nonzero storage slots read on the path, as they were before the call
+    pub storage: BTreeMap<Word, Word>,
+    /// name of the function called and its arguments, if it is in the abi
+    pub call: Option<(String, Vec<Token>)>,
+}
+
+/// decode calldata against the functions of the abi
+pub fn decode_call(abi: &Contract, calldata: &[u8]) -> Option<(String, Vec<Token>)> {
+    let selector = calldata.get(..4)?;
+    let function = abi
+        .functions()
+        .find(|function| function.short_signature() == selector)?;
+    let tokens = function.decode_input(&calldata[4..]).ok()?;
+
+    Some((function.name.clone(), tokens))
+}
+
+impl Witness {
+    pub fn to_json(&self) -> serde_json::Value {
+        let storage: serde_json::Map<_, _> = self
+            .storage
+            .iter()
+            .map(|(key, value)| {
+                let value = format!("0x{}", hex::encode(value));
+                (format!("0x{}", hex::encode(key)), value.into())
+            })
+            .collect();
+        let call = self.call.as_ref().map(|(name, tokens)| {
+            let args: Vec<_> = tokens.iter().map(|token| token.to_string()).collect();
+            json!({ "function": name, "args": args })
+        });
+
+        let env = &self.env;
+        let block_hashes: serde_json::Map<_, _> = env
+            .block_hashes
+            .iter()
+            .map(|(number, hash)| {
+                (
+                    number.to_string(),
+                    format!("0x{}", hex::encode(hash)).into(),
+                )
+            })
+            .collect();
+
+        json!({
+            "calldata": format!("0x{}", hex::encode(&self.calldata)),
+            "caller": format!("0x{}", hex::encode(self.caller)),
+            "value": format!("0x{}", hex::encode(self.value)),
+            "address": format!("0x{}", hex::encode(self.address)),
+            "gas": self.gas,
+            "env": {
+                "origin": format!("0x{}", hex::encode(env.origin)),
+                "coinbase": format!("0x{}", hex::encode(env.coinbase)),
+                "gas_price": env.gas_price,
+                "timestamp": env.timestamp,
+                "number": env.number,
+                "prevrandao": format!("0x{}", hex::encode(env.difficulty.as_bytes())),
+                "gas_limit": env.gas_limit,
+                "chain_id": env.chain_id,
+                "base_fee": format!("0x{}", hex::encode(env.base_fee.as_bytes())),
+                "blob_base_fee": format!("0x{}", hex::encode(env.blob_base_fee.as_bytes())),
+                "block_hashes": block_hashes,
+            },
+            "storage": storage,
+            "call": call,
+        })
+    }
+}
+
+impl Display for Witness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.call {
+            Some((name, tokens)) => {
+                let args: Vec<_> = tokens.iter().map(|token| token.to_string()).collect();
+                writeln!(f, "call: {name}({})", args.join(", "))?;
+            }
+            None => writeln!(f, "call: 0x{}", hex::encode(&self.calldata))?,
+        }
+        writeln!(f, "caller: 0x{}", hex::encode(self.caller))?;
+        writeln!(f, "value: 0x{}", hex::encode(self.value))?;
+        writeln!(f, "address: 0x{}", hex::encode(self.address))?;
+        writeln!(f, "gas: {}", self.gas)?;
+        for (key, value) in &self.storage {
+            writeln!(
+                f,
+                "storage[0x{}]: 0x{}",
+                hex::encode(key),
+                hex::encode(value)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn decode() {
+    let abi = Contract::load(
+        br#"[{"type":"function","name":"f","stateMutability":"nonpayable","outputs":[],"inputs":[{"name":"x","type":"uint256"}]}]"#
+            .as_slice(),
+    )
+    .unwrap();
+    let function = abi.function("f").unwrap();
+    let calldata = function
+        .encode_input(&[Token::Uint(ethabi::Uint::from(7))])
+        .unwrap();
+
+    let (name, tokens) = decode_call(&abi, &calldata).unwrap();
+    assert_eq!(name, "f");
+    assert_eq!(tokens, vec![Token::Uint(ethabi::Uint::from(7))]);
+    assert!(decode_call(&abi, &[0xde, 0xad, 0xbe, 0xef]).is_none());
+}